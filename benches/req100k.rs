@@ -2,7 +2,7 @@ use cares_rs::*;
 use libc::*;
 use std::ffi::CString;
 use std::ptr;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::slice;
 use std::time::Instant;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -102,9 +102,9 @@ impl BenchRunner {
         unsafe { (self.cares.ares_destroy)(channel) };
     }
     fn set_localhost_nameservers(&mut self, channel: Channel) {
-        let localhost = [ &Ipv4Addr::LOCALHOST.octets()[..], &[0u8; 12][..] ].concat();
         let mut sentinel = ares_addr_node { next: ptr::null_mut(), family: 0, data: [0; 16] };
-        let mut head = ares_addr_node { next: &mut sentinel, family: AF_INET, data: localhost.try_into().unwrap() };
+        let mut head = ares_addr_node::from(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        head.next = &mut sentinel;
         unsafe { (self.cares.ares_set_servers)(channel, &mut head) };
     }
 }
@@ -0,0 +1,20 @@
+#![no_main]
+
+use std::io::Cursor;
+use libfuzzer_sys::fuzz_target;
+use cares_rs::core::packets::DnsFrame;
+use cares_rs::core::record::Record;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(frame) = DnsFrame::parse(&mut Cursor::new(data)) else { return };
+    let _ = frame.canonical_name(data);
+    for answer in frame.answers.iter().chain(&frame.authorities).chain(&frame.additionals) {
+        let _ = answer.name.build_string(data);
+        let _ = Record::decode(answer, data);
+    }
+
+    let mut hostent: *mut libc::hostent = std::ptr::null_mut();
+    if unsafe { cares_rs::ares_parse_a_reply(data.as_ptr(), data.len() as i32, &mut hostent) } == 0 {
+        unsafe { cares_rs::ares_free_hostent(hostent) };
+    }
+});
@@ -0,0 +1,99 @@
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
+
+use crate::core::ares::Family;
+use crate::core::error::DnsError;
+use crate::core::replies::parse_addrinfo;
+
+/// `ai_flags` bits of getaddrinfo hints, with c-ares' values
+pub const AI_CANONNAME: i32 = 1 << 0;
+pub const AI_NUMERICHOST: i32 = 1 << 1;
+pub const AI_PASSIVE: i32 = 1 << 2;
+
+/// The parts of getaddrinfo hints that steer the lookup itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hints {
+    pub family: Family,
+    pub flags: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddrInfo {
+    /// Set with AI_CANONNAME only
+    pub canonname: Option<String>,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl Hints {
+    fn has(&self, flag: i32) -> bool {
+        self.flags & flag != 0
+    }
+    fn wants(&self, addr: &IpAddr) -> bool {
+        match self.family {
+            Family::Ipv4 => addr.is_ipv4(),
+            Family::Ipv6 => addr.is_ipv6(),
+            Family::Unspec => true,
+        }
+    }
+    /// Answers the lookup without DNS where it can or has to be: no name gets the wildcard addresses with
+    /// AI_PASSIVE and the loopback ones without, a literal address stands for itself, and with AI_NUMERICHOST
+    /// any other name is rejected. None means the name is to be looked up
+    pub fn resolve_locally(&self, name: Option<&str>) -> Option<Result<AddrInfo, DnsError>> {
+        let (canonname, addrs) = match name {
+            None if self.has(AI_PASSIVE) => (None, vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)]),
+            None => (None, vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)]),
+            Some(name) => match name.parse::<IpAddr>() {
+                Ok(addr) => (Some(name.to_string()), vec![addr]),
+                Err(_) if self.has(AI_NUMERICHOST) => return Some(Err(DnsError::NotFound)),
+                Err(_) => return None,
+            },
+        };
+        let addrs: Vec<IpAddr> = addrs.into_iter().filter(|addr| self.wants(addr)).collect();
+        if addrs.is_empty() {
+            return Some(Err(DnsError::NotFound));
+        }
+        Some(Ok(AddrInfo { canonname: canonname.filter(|_| self.has(AI_CANONNAME)), addrs }))
+    }
+    /// Result of a lookup from its reply, with the end of the CNAME chain as canonical name under AI_CANONNAME
+    pub fn from_reply(&self, buf: &[u8]) -> Result<AddrInfo, DnsError> {
+        let (canonname, addrs) = parse_addrinfo(buf, self.family)?;
+        Ok(AddrInfo { canonname: canonname.filter(|_| self.has(AI_CANONNAME)), addrs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_host_rejects_names() {
+        let hints = Hints { family: Family::Unspec, flags: AI_NUMERICHOST };
+        assert_eq!(hints.resolve_locally(Some("example.test")), Some(Err(DnsError::NotFound)));
+        let expected = AddrInfo { canonname: None, addrs: vec!["192.0.2.1".parse().unwrap()] };
+        assert_eq!(hints.resolve_locally(Some("192.0.2.1")), Some(Ok(expected)));
+        let hints = Hints { family: Family::Ipv6, flags: AI_NUMERICHOST };
+        assert_eq!(hints.resolve_locally(Some("192.0.2.1")), Some(Err(DnsError::NotFound)));
+        assert_eq!(Hints { family: Family::Unspec, flags: 0 }.resolve_locally(Some("example.test")), None);
+    }
+
+    #[test]
+    fn test_passive_gets_wildcard_addresses() {
+        let hints = Hints { family: Family::Ipv4, flags: AI_PASSIVE };
+        assert_eq!(hints.resolve_locally(None).unwrap().unwrap().addrs, vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]);
+        let hints = Hints { family: Family::Ipv6, flags: 0 };
+        assert_eq!(hints.resolve_locally(None).unwrap().unwrap().addrs, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn test_canonname_follows_cname_chain() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x06\x03cdn\xc0\x10",
+            b"\xc0\x2d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let hints = Hints { family: Family::Ipv4, flags: AI_CANONNAME };
+        let expected = AddrInfo { canonname: Some("cdn.example.com".to_string()), addrs: vec!["1.2.3.4".parse().unwrap()] };
+        assert_eq!(hints.from_reply(&buf), Ok(expected));
+        let hints = Hints { family: Family::Ipv4, flags: 0 };
+        assert_eq!(hints.from_reply(&buf).unwrap().canonname, None);
+    }
+}
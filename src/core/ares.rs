@@ -1,11 +1,21 @@
-use std::net::{ UdpSocket, SocketAddr };
-use bytes::BytesMut;
-use std::io::Cursor;
-use rand::Rng;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket };
+use bytes::{ BufMut, BytesMut };
+use std::io::{ self, Cursor };
+use rand::{ Rng, RngCore, SeedableRng };
+use rand::rngs::StdRng;
 use std::time::{ Instant, Duration };
 
-use crate::core::sysconfig::SysConfig;
+use crate::core::sysconfig::{ SysConfig, ConfigSource, ns_socket_addr };
+#[cfg(target_os = "android")]
+use crate::core::sysconfig::{ android_nameservers, system_property };
 use crate::core::packets::*;
+use crate::core::reuseport;
+use crate::core::reverse;
+use crate::core::dns64;
+use crate::core::socket::{ Socket, SocketHooks };
+use crate::core::error::{ DnsError, Rcode };
 
 /* TODO: reconcile ChannelData here */
 pub struct Ares<T> {
@@ -13,104 +23,1038 @@ pub struct Ares<T> {
     pub tasks: Vec<Task<T>>,
     pub default_udp_port: u16,
     pub default_tcp_port: u16,
+    /// Number of SO_REUSEPORT sockets sharing the receive load, 0 for a dedicated socket per task
+    pub udp_receive_sockets: usize,
+    pub receive_group: Vec<UdpSocket>,
+    /// `udp_source_port` the receive group was bound for
+    pub receive_group_port: u16,
+    /// Sockets of groups replaced while their queries were in flight, still drained until those are done
+    pub retired_receive_sockets: Vec<UdpSocket>,
+    /// UDP payload size advertised when EDNS0 is enabled (ednspsz)
+    pub edns_udp_size: u16,
+    /// EDNS Client Subnet attached to every query, which implies EDNS0
+    pub client_subnet: Option<ClientSubnet>,
+    /// Caller-provided socket functions creating and driving every task's socket
+    pub socket_hooks: Option<SocketHooks>,
+    /// Addresses pinned at runtime, keyed by lowercase name without the trailing dot
+    pub static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// Ask a query once more without EDNS when the server answers it FORMERR, as middleboxes
+    /// not knowing the OPT record do
+    pub edns_formerr_retry: bool,
+    /// Also take records past the counted sections as answers, for servers under-reporting ancount
+    pub tolerant_counts: bool,
+    /// Local port for query sockets, 0 for a random ephemeral port per socket.
+    /// A fixed port makes replies much easier to spoof, so it's meant for tests and NATs that need it.
+    /// Without `udp_receive_sockets`, only one query at a time can hold the port
+    pub udp_source_port: u16,
+    /// Upper bound of a random delay added to each try's timeout, so that queries timing out together
+    /// don't all retransmit at once
+    pub retry_jitter: Duration,
+    /// Class of the questions `gethostbyname` asks, IN unless the deployment serves another one.
+    /// Explicit queries carry their own class
+    pub default_class: u16,
+    /// NAT64 prefix (see `dns64::WELL_KNOWN_PREFIX`) IPv6 lookups of names without AAAA records map
+    /// their A records into, for networks lacking DNS64; None leaves such lookups without addresses
+    pub nat64_prefix: Option<Ipv6Addr>,
+    /// Source of transaction ids and retry jitter, a CSPRNG unless replaced with `set_rng`
+    rng: RefCell<Box<dyn RngCore + Send>>,
 }
 
+/// UDP payload size advertised by default, resolv.conf's `options edns0` carrying none (DNS Flag Day 2020)
+pub const DEFAULT_EDNS_UDP_SIZE: u16 = 1232;
+
+/// Advertised UDP payload size for the one-step retry of a truncated EDNS query
+pub const EDNS_RETRY_UDP_SIZE: u16 = 4096;
+
+/// Doublings of a try's timeout beyond which retransmissions stop backing off further
+pub const MAX_BACKOFF_DOUBLINGS: u32 = 5;
+
 #[derive(PartialEq, Debug, Clone, Copy)]
-pub enum Family { Ipv4, Ipv6 }
+pub enum Family { Ipv4, Ipv6, Unspec }
 
 impl<T> Ares<T> {
     pub fn new(config: SysConfig) -> Self {
-        Ares { config, tasks: vec![], default_udp_port: 53, default_tcp_port: 53 }
+        Ares { config, tasks: vec![], default_udp_port: 53, default_tcp_port: 53, udp_receive_sockets: 0, receive_group: vec![], receive_group_port: 0, retired_receive_sockets: vec![], edns_udp_size: DEFAULT_EDNS_UDP_SIZE, client_subnet: None, socket_hooks: None, static_hosts: HashMap::new(), edns_formerr_retry: true, tolerant_counts: false, udp_source_port: 0, retry_jitter: Duration::ZERO, default_class: 1, nat64_prefix: None, rng: RefCell::new(Box::new(StdRng::from_entropy())) }
+    }
+    /// Draws transaction ids and retry jitter from `rng` from now on, e.g. a seeded one for reproducible tests
+    pub fn set_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.rng = RefCell::new(Box::new(rng));
+    }
+    fn next_transaction_id(&self) -> u16 {
+        self.rng.borrow_mut().r#gen::<u16>()
     }
     pub fn from_sysconfig() -> Self {
         Ares::new(build_sysconfig())
     }
-    pub fn gethostbyname(&mut self, hostname: &str, family: Family, userdata: T) -> &Task<T> {
+    pub fn from_config_source(source: &ConfigSource) -> Self {
+        Ares::new(source.load())
+    }
+    pub fn search_candidates(&self, name: &str) -> Vec<String> {
+        self.config.search_candidates(name)
+    }
+    /// Pins `addrs` to `name`, in addition to any addresses pinned before
+    pub fn add_static_host(&mut self, name: &str, addrs: Vec<IpAddr>) {
+        let key = name.trim_end_matches('.').to_ascii_lowercase();
+        self.static_hosts.entry(key).or_default().extend(addrs);
+    }
+    /// Pinned addresses of `name` for `family`, which for Unspec are the IPv6 ones if any, like the lookup itself.
+    /// Lookups are to be answered from here before `gethostbyname` goes to the network
+    pub fn static_host(&self, name: &str, family: Family) -> Option<Vec<IpAddr>> {
+        let pinned = self.static_hosts.get(&name.trim_end_matches('.').to_ascii_lowercase())?;
+        let of = |ipv6: bool| pinned.iter().filter(|addr| addr.is_ipv6() == ipv6).copied().collect::<Vec<_>>();
+        let addrs = match family {
+            Family::Ipv4 => of(false),
+            Family::Ipv6 => of(true),
+            Family::Unspec if self.config.options.no_aaaa => of(false),
+            Family::Unspec => match of(true) {
+                v6 if !v6.is_empty() => v6,
+                _ => of(false),
+            },
+        };
+        Some(addrs).filter(|addrs| !addrs.is_empty())
+    }
+    pub fn gethostbyname(&mut self, hostname: &str, family: Family, userdata: T) -> io::Result<&Task<T>> {
         let qtype = match family {
-            Family::Ipv4 => 0x01, // A
-            Family::Ipv6 => 0x1c, // AAAA
+            Family::Ipv4 => RecordType::A,
+            Family::Ipv6 => RecordType::Aaaa,
+            Family::Unspec if self.config.options.no_aaaa => RecordType::A,
+            Family::Unspec => RecordType::Aaaa,
         };
-        let sock = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
-        let _ = sock.set_nonblocking(true);
+        let no_aaaa = self.config.options.no_aaaa;
+        let nat64_prefix = self.nat64_prefix;
+        let task = self.new_task(None, hostname, self.default_class, qtype.as_u16(), userdata)?;
+        // AF_UNSPEC prefers AAAA and falls back to A when there are none
+        if family == Family::Unspec && !no_aaaa {
+            task.fallback_qtype = Some(RecordType::A);
+        }
+        if let (Family::Ipv6, Some(prefix)) = (family, nat64_prefix) {
+            task.fallback_qtype = Some(RecordType::A);
+            task.nat64_prefix = Some(prefix);
+        }
+        Ok(task)
+    }
+    /// PTR query for `addr` under in-addr.arpa or ip6.arpa
+    pub fn gethostbyaddr(&mut self, addr: &IpAddr, userdata: T) -> io::Result<&Task<T>> {
+        let name = reverse::reverse_name(addr, self.config.options.ip6_dotint);
+        Ok(self.new_task(None, &name, 1, RecordType::Ptr.as_u16(), userdata)?)
+    }
+    pub fn query(&mut self, name: &str, dnsclass: u16, dnstype: u16, userdata: T) -> io::Result<&Task<T>> {
+        Ok(self.new_task(None, name, dnsclass, dnstype, userdata)?)
+    }
+    /// Sends the query to `server` alone, whatever the configured nameservers
+    pub fn query_to(&mut self, server: SocketAddr, name: &str, dnsclass: u16, dnstype: u16, userdata: T) -> io::Result<&Task<T>> {
+        Ok(self.new_task(Some(server), name, dnsclass, dnstype, userdata)?)
+    }
+    /// Sends the caller-built query `qbuf` as is, failing with InvalidData unless it parses with a question
+    pub fn send(&mut self, qbuf: &[u8], userdata: T) -> io::Result<&Task<T>> {
+        let request = DnsFrame::parse(&mut Cursor::new(qbuf)).filter(|frame| !frame.queries.is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed query"))?;
+        Ok(self.push_task(None, request, BytesMut::from(qbuf), userdata)?)
+    }
+    /// Fails without enqueueing anything if no socket can be had (e.g. EMFILE)
+    fn new_task(&mut self, server: Option<SocketAddr>, name: &str, qclass: u16, qtype: u16, userdata: T) -> io::Result<&mut Task<T>> {
         let query = DnsQuery {
-            name: hostname.split(".").map(str::to_owned).collect(),
+            name: name.split(".").map(str::to_owned).collect(),
             qtype,
-            qclass: 1,
+            qclass,
         };
-        let request = DnsFrame {
-            transaction_id: rand::thread_rng().r#gen::<u16>(),
-            flags: 0x100,
+        let mut request = DnsFrame {
+            transaction_id: self.next_transaction_id(),
+            flags: DnsHeader::query_flags(true),
             queries: vec![query],
             answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
         };
-        let expires_at = Instant::now() + Duration::new(1, 0) * self.config.options.timeout_secs;
-        let mut task = Task { status: Status::Writing, sock, writebuf: BytesMut::new(), userdata, expires_at };
-        request.write(&mut task.writebuf);
-        self.tasks.push(task);
-        self.tasks.last().unwrap()
+        if self.config.options.edns0 || self.client_subnet.is_some() {
+            request.set_edns_udp_size(Some(self.edns_udp_size));
+            request.set_client_subnet(self.client_subnet.as_ref());
+        }
+        let mut writebuf = BytesMut::with_capacity(request.encoded_len());
+        request.write(&mut writebuf);
+        self.push_task(server, request, writebuf, userdata)
     }
-    pub fn query(&mut self, name: &str, dnsclass: u16, dnstype: u16, userdata: T) {
-        let sock = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
-        let _ = sock.set_nonblocking(true);
-        let query = DnsQuery {
-            name: name.split(".").map(str::to_owned).collect(),
-            qtype: dnstype,
-            qclass: dnsclass,
-        };
-        let request = DnsFrame {
-            transaction_id: rand::thread_rng().r#gen::<u16>(),
-            flags: 0x100,
-            queries: vec![query],
-            answers: vec![],
+    /// Enqueues a task sending `writebuf`, the encoding of `request`
+    fn push_task(&mut self, server: Option<SocketAddr>, request: DnsFrame, writebuf: BytesMut, userdata: T) -> io::Result<&mut Task<T>> {
+        // The first try goes to the first nameserver, see `nameserver_for`
+        let ipv6 = match server {
+            Some(server) => server.is_ipv6(),
+            None => matches!(self.config.nameservers.first(), Some((IpAddr::V6(_), _))),
         };
-        let expires_at = Instant::now() + Duration::new(1, 0) * self.config.options.timeout_secs;
-        let mut task = Task { status: Status::Writing, sock, writebuf: BytesMut::new(), userdata, expires_at };
-        request.write(&mut task.writebuf);
+        let (sock, grouped) = self.bind_socket(ipv6)?;
+        let expires_at = Instant::now() + jittered(&self.rng, Duration::new(1, 0) * self.config.options.timeout_secs, self.retry_jitter);
+        let task = Task { status: Status::Writing, sock, writebuf, userdata, expires_at, request, fallback_qtype: None, nat64_prefix: None, timeouts: 0, server_errors: vec![], server, sent_at: None, sent_to: None, latency: None, tcp: None, grouped, ipv6 };
         self.tasks.push(task);
+        Ok(self.tasks.last_mut().unwrap())
     }
-    pub fn write_impl(&mut self, task: &mut Task<T>) {
-        let ns_addr = self.config.nameservers.first().unwrap();
-        let socket_addr = SocketAddr::from((ns_addr.0, ns_addr.1.unwrap_or(self.default_udp_port)));
-        let _len = task.sock.send_to(&task.writebuf, socket_addr).unwrap();
+    /// Socket of the family of the server, along with whether it belongs to the receive group
+    fn bind_socket(&mut self, ipv6: bool) -> io::Result<(Socket, bool)> {
+        if let Some(hooks) = self.socket_hooks {
+            return Ok((Socket::open(hooks, if ipv6 { libc::AF_INET6 } else { libc::AF_INET })?, false));
+        }
+        // The receive group is IPv4 only, so IPv6 servers get a socket of their own
+        if self.udp_receive_sockets == 0 || ipv6 {
+            let local = if ipv6 { IpAddr::V6(Ipv6Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::UNSPECIFIED) };
+            let sock = UdpSocket::bind((local, self.udp_source_port))?;
+            sock.set_nonblocking(true)?;
+            return Ok((sock.into(), false));
+        }
+        if self.receive_group.len() != self.udp_receive_sockets || self.receive_group_port != self.udp_source_port {
+            let group = reuseport::bind_group(self.udp_receive_sockets, self.udp_source_port)?;
+            // Queries in flight hold dups of the old sockets, whose replies still arrive on them
+            self.retired_receive_sockets.append(&mut std::mem::replace(&mut self.receive_group, group));
+            self.receive_group_port = self.udp_source_port;
+        }
+        let sock = &self.receive_group[self.tasks.len() % self.receive_group.len()];
+        Ok((sock.try_clone()?.into(), true))
+    }
+    /// Sends the task's query, or the next part of it over TCP. A failed UDP send completes the task,
+    /// the error being the caller's to report
+    pub fn write_impl(&mut self, task: &mut Task<T>) -> Result<(), DnsError> {
+        if let Some(exchange) = &mut task.tcp {
+            match task.sock.send_to(&exchange.query[exchange.written..], exchange.server) {
+                Ok(len) => exchange.written += len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // A failed connection turns readable as well, which is where the exchange is given up
+                Err(_) => exchange.written = exchange.query.len(),
+            }
+            if exchange.written == exchange.query.len() {
+                task.status = Status::Reading;
+            }
+            return Ok(());
+        }
+        // Not a single nameserver to send to
+        let Some(socket_addr) = self.server_addr(task, false) else {
+            task.status = Status::Completed;
+            return Err(DnsError::Transport(io::ErrorKind::ConnectionRefused));
+        };
+        // A retry moving on to a server of the other family needs a socket of that family
+        if socket_addr.is_ipv6() != task.ipv6 {
+            let (sock, grouped) = match self.bind_socket(socket_addr.is_ipv6()) {
+                Ok(sock) => sock,
+                Err(err) => {
+                    task.status = Status::Completed;
+                    return Err(err.into());
+                }
+            };
+            (task.sock, task.grouped, task.ipv6) = (sock, grouped, socket_addr.is_ipv6());
+        }
+        match task.sock.send_to(&task.writebuf, socket_addr) {
+            Ok(_len) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => {
+                task.status = Status::Completed;
+                return Err(err.into());
+            }
+        }
         task.status = Status::Reading;
+        task.sent_at = Some(Instant::now());
+        task.sent_to = Some(socket_addr);
+        Ok(())
     }
     pub fn read_impl(&mut self, task: &mut Task<T>) -> Option<(Vec<u8>, DnsFrame)> {
-        let mut buf = vec![0u8; 65_535];
-        let (len, _src) = task.sock.recv_from(&mut buf).unwrap();
+        if task.tcp.is_some() {
+            return self.read_tcp(task);
+        }
+        // One spare byte tells a datagram overrunning the advertised size apart from one that fits
+        let mut buf = vec![0u8; task.receive_buffer_size() + 1];
+        let len = loop {
+            // Spurious wakeups (e.g. from a stale fd_set) leave the task waiting
+            let Ok((len, src)) = task.sock.recv_from(&mut buf) else { return None };
+            // Neither a stray nor a forged datagram ends the wait for the actual reply, which may be queued behind it.
+            // Hooked sockets not reporting the source are connected, so only get datagrams from the server
+            let from_server = src.is_none() || src == task.sent_to;
+            if from_server && buf[0..len].get(0..2) == Some(&task.request.transaction_id.to_be_bytes()[..]) {
+                break len;
+            }
+        };
         task.status = Status::Completed;
+        let overrun = len > task.receive_buffer_size();
+        buf.truncate(len);
 
-        let frame = DnsFrame::parse(&mut Cursor::new(&buf[0..len]))?;
+        let frame = match self.parse_reply(&buf) {
+            Some(frame) => frame,
+            // The cut-off records don't matter, the reply is going to be retried anyway
+            None if overrun => {
+                let header = DnsHeader::parse(&mut Cursor::new(&buf))?;
+                DnsFrame { transaction_id: header.transaction_id, flags: header.flags, queries: vec![], answers: vec![], authorities: vec![], additionals: vec![] }
+            }
+            None => return None,
+        };
+        // Without a TCP connection the truncated reply is delivered as is
+        if (frame.is_truncated() || overrun) && (task.bump_edns_udp_size() || self.start_tcp(task, &buf)) {
+            return None;
+        }
+        self.complete(task, buf, frame)
+    }
+    /// Advances the task's TCP exchange, ending it once the whole reply is in or the connection failed
+    fn read_tcp(&self, task: &mut Task<T>) -> Option<(Vec<u8>, DnsFrame)> {
+        let exchange = task.tcp.as_mut()?;
+        let mut chunk = vec![0u8; 65_535];
+        let reply = match task.sock.recv_from(&mut chunk) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return None,
+            Ok((len, _src)) if len > 0 => {
+                exchange.received.extend_from_slice(&chunk[0..len]);
+                Some(exchange.reply()?)
+            }
+            // Closed or failed connection
+            _ => None,
+        };
+        let truncated = task.end_tcp()?;
+        task.status = Status::Completed;
+        // Without a usable TCP reply the truncated one is delivered as is
+        let tcp_reply = reply.and_then(|buf| Some((self.parse_reply(&buf)?, buf)));
+        let (frame, buf) = match tcp_reply {
+            Some(tcp_reply) => tcp_reply,
+            None => (self.parse_reply(&truncated)?, truncated),
+        };
+        self.complete(task, buf, frame)
+    }
+    /// Delivers the reply, unless the task goes on with its fallback query type
+    fn complete(&self, task: &mut Task<T>, buf: Vec<u8>, frame: DnsFrame) -> Option<(Vec<u8>, DnsFrame)> {
+        let formerr = matches!(Rcode::from_flags(frame.flags), Some(Rcode::FormErr));
+        if formerr && self.edns_formerr_retry && task.drop_edns() {
+            return None;
+        }
+        // Like c-ares, a server that failed is given up on for the next one while tries remain
+        if let Some(rcode) = Rcode::from_flags(frame.flags).filter(Rcode::is_server_failure) {
+            task.server_errors.push(rcode);
+        }
+        let server_failed = Rcode::from_flags(frame.flags).is_some_and(|rcode| rcode.is_server_failure());
+        if server_failed && task.tries() < self.config.options.attempts.max(1) {
+            task.status = Status::Writing;
+            let timeout = Duration::new(1, 0) * self.config.options.timeout_secs;
+            task.expires_at = Instant::now() + jittered(&self.rng, timeout, self.retry_jitter);
+            return None;
+        }
+        if !task.has_answer(&frame) && task.fall_back(self.next_transaction_id()) {
+            return None;
+        }
+        task.latency = task.sent_at.map(|sent_at| sent_at.elapsed());
+        let fell_back_to_a = task.request.queries[0].qtype == RecordType::A.as_u16();
+        if let Some(prefix) = task.nat64_prefix.filter(|_| fell_back_to_a) {
+            return dns64::synthesize_reply(&frame, &buf, prefix);
+        }
         Some((buf, frame))
     }
-    pub fn max_wait_time(&self) -> Duration {
-        self.tasks.iter().map(Task::time_remaining).min().unwrap()
+    fn parse_reply(&self, buf: &[u8]) -> Option<DnsFrame> {
+        if self.tolerant_counts {
+            DnsFrame::parse_tolerant(&mut Cursor::new(buf))
+        } else {
+            DnsFrame::parse(&mut Cursor::new(buf))
+        }
+    }
+    /// UDP and TCP ports of a configured nameserver: its own port for both if it has one,
+    /// the channel defaults otherwise
+    pub fn ns_ports(&self, ns: &(IpAddr, Option<u16>)) -> (u16, u16) {
+        match ns.1 {
+            Some(port) => (port, port),
+            None => (self.default_udp_port, self.default_tcp_port),
+        }
+    }
+    /// Configured nameserver the task's current try goes to, each try moving on to the next one
+    pub fn nameserver_for(&self, task: &Task<T>) -> Option<(IpAddr, Option<u16>)> {
+        let nameservers = &self.config.nameservers;
+        (!nameservers.is_empty()).then(|| nameservers[task.tries() as usize % nameservers.len()])
+    }
+    /// Whether each configured nameserver failed the task at least once, for a task out of tries
+    pub fn all_servers_failed(&self, task: &Task<T>) -> bool {
+        task.server.is_none() && task.tries() as usize >= self.config.nameservers.len()
+    }
+    /// Where the task's query goes: its own server if it has one, else the nameserver of its current try
+    fn server_addr(&self, task: &Task<T>, tcp: bool) -> Option<SocketAddr> {
+        if task.server.is_some() {
+            return task.server;
+        }
+        let ns_addr = self.nameserver_for(task)?;
+        let (udp_port, tcp_port) = self.ns_ports(&ns_addr);
+        Some(ns_socket_addr(ns_addr.0, if tcp { tcp_port } else { udp_port }))
+    }
+    /// Switches the task over to a TCP connection to its server, to repeat the query there.
+    /// The truncated UDP reply is kept in case the exchange fails
+    fn start_tcp(&self, task: &mut Task<T>, truncated: &[u8]) -> bool {
+        let Some(server) = self.server_addr(task, true) else { return false };
+        let Ok(stream) = Socket::connect_tcp(server) else { return false };
+        let mut query = Vec::with_capacity(2 + task.writebuf.len());
+        query.put_u16(task.writebuf.len() as u16);
+        query.put_slice(&task.writebuf);
+        let udp = std::mem::replace(&mut task.sock, stream);
+        task.tcp = Some(TcpExchange { udp, server, truncated: truncated.to_vec(), query, written: 0, received: vec![] });
+        task.status = Status::Writing;
+        true
+    }
+    /// Sockets of the receive group, along with the retired ones queries in flight may still get replies on
+    pub fn receive_sockets(&self) -> impl Iterator<Item = &UdpSocket> {
+        self.receive_group.iter().chain(&self.retired_receive_sockets)
+    }
+    /// Drains every readable socket of the receive group, routing replies to their tasks by transaction id
+    pub fn drain_receive_group<F>(&mut self, tasks: &mut [Task<T>], is_readable: F) -> Vec<(usize, Vec<u8>, DnsFrame)>
+    where F: Fn(&UdpSocket) -> bool
+    {
+        let mut replies = vec![];
+        for sock in self.receive_sockets().filter(|sock| is_readable(sock)) {
+            let mut buf = vec![0u8; 65_535];
+            while let Ok((len, src)) = sock.recv_from(&mut buf) {
+                let Some(frame) = self.parse_reply(&buf[0..len]) else { continue };
+                let owner = tasks.iter().position(|task| {
+                    task.status == Status::Reading && task.grouped && task.tcp.is_none() && task.request.transaction_id == frame.transaction_id
+                        && task.sent_to == Some(src)
+                });
+                let Some(idx) = owner else { continue };
+                let reply = buf[0..len].to_vec();
+                if frame.is_truncated() && (tasks[idx].bump_edns_udp_size() || self.start_tcp(&mut tasks[idx], &reply)) {
+                    continue;
+                }
+                tasks[idx].status = Status::Completed;
+                if let Some((reply, frame)) = self.complete(&mut tasks[idx], reply, frame) {
+                    replies.push((idx, reply, frame));
+                }
+            }
+        }
+        replies
+    }
+    /// Re-arms every expired task that has tries left, leaving the others expired.
+    /// Each retransmission waits twice as long as the try before, like c-ares does.
+    /// Returns the transaction id and new try number (2 for the first retransmission) of each re-armed task
+    pub fn retry_expired(&mut self) -> Vec<(u16, u32)> {
+        let tries = self.config.options.attempts.max(1);
+        let timeout = Duration::new(1, 0) * self.config.options.timeout_secs;
+        let mut retried = vec![];
+        for task in self.tasks.iter_mut().filter(|task| task.is_expired() && !task.is_done()) {
+            if task.tries() + 1 < tries {
+                // A TCP exchange that timed out is retried over UDP from the start
+                task.end_tcp();
+                task.timeouts += 1;
+                let backoff = 1 << task.timeouts.min(MAX_BACKOFF_DOUBLINGS);
+                task.expires_at = Instant::now() + jittered(&self.rng, timeout * backoff, self.retry_jitter);
+                task.status = Status::Writing;
+                retried.push((task.request.transaction_id, task.timeouts + 1));
+            }
+        }
+        retried
+    }
+    /// Time until the next task expires, None without any task
+    pub fn max_wait_time(&self) -> Option<Duration> {
+        self.tasks.iter().map(Task::time_remaining).min()
+    }
+    /// Queries still waiting for their answer, in creation order
+    pub fn pending_queries(&self) -> Vec<PendingQuery> {
+        self.tasks.iter()
+            .filter(|task| !task.is_done())
+            .map(|task| PendingQuery {
+                id: task.request.transaction_id,
+                name: task.request.queries[0].name.join("."),
+                server: task.server.map(|addr| (addr.ip(), Some(addr.port()))).or_else(|| self.nameserver_for(task)),
+            })
+            .collect()
+    }
+    /// Marks the pending query with transaction id `id` cancelled, leaving its callback to the caller
+    pub fn cancel_query(&mut self, id: u16) -> Option<&Task<T>> {
+        let task = self.tasks.iter_mut().find(|task| !task.is_done() && task.request.transaction_id == id)?;
+        task.status = Status::Cancelled;
+        Some(task)
     }
     pub fn remove_completed(&mut self) {
-        self.tasks.retain(|task| !task.is_expired());
+        self.tasks.retain(|task| !task.is_done());
+        if !self.tasks.iter().any(|task| task.grouped) {
+            self.retired_receive_sockets.clear();
+        }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingQuery {
+    pub id: u16,
+    pub name: String,
+    pub server: Option<(IpAddr, Option<u16>)>,
+}
+
 #[derive(PartialEq)]
-pub enum Status { Writing, Reading, Completed }
+/// A task is done once Completed (its reply or error handed over) or Cancelled (by the caller),
+/// and is dropped by the next `remove_completed` either way
+pub enum Status { Writing, Reading, Completed, Cancelled }
 
 pub struct Task<T> {
     pub status: Status,
-    pub sock: UdpSocket,
+    pub sock: Socket,
     pub writebuf: BytesMut,
     pub userdata: T,
     pub expires_at: Instant,
+    pub request: DnsFrame,
+    /// Query type to retry with when the reply carries no answer of the requested type
+    pub fallback_qtype: Option<RecordType>,
+    /// Prefix to turn the answers of the A fallback into AAAA ones with, for IPv6 lookups
+    pub nat64_prefix: Option<Ipv6Addr>,
+    /// Tries that timed out so far
+    pub timeouts: u32,
+    /// Tries a server answered with a server failure RCODE, moving on to the next server
+    pub server_errors: Vec<Rcode>,
+    /// Server the task is pinned to, bypassing the configured nameservers
+    pub server: Option<SocketAddr>,
+    /// When the latest try went out
+    pub sent_at: Option<Instant>,
+    /// Where the latest try went, the only source replies are taken from
+    pub sent_to: Option<SocketAddr>,
+    /// Round trip of the try that got the reply, once completed
+    pub latency: Option<Duration>,
+    /// Exchange over TCP under way, `sock` being its connection meanwhile
+    pub tcp: Option<TcpExchange>,
+    /// `sock` is a socket of the receive group, read through `drain_receive_group` rather than per task
+    pub grouped: bool,
+    /// `sock` is an IPv6 socket
+    pub ipv6: bool,
+}
+
+/// A query repeated over TCP after a truncated UDP reply
+pub struct TcpExchange {
+    /// The task's UDP socket, restored once the exchange is over
+    udp: Socket,
+    server: SocketAddr,
+    /// The truncated reply, delivered as is if the exchange fails
+    truncated: Vec<u8>,
+    /// Length-prefixed query, and how much of it went out so far
+    query: Vec<u8>,
+    written: usize,
+    /// Length-prefixed reply, as much of it as came in so far
+    received: Vec<u8>,
+}
+
+impl TcpExchange {
+    /// The reply, once it came in whole
+    fn reply(&self) -> Option<Vec<u8>> {
+        let len = u16::from_be_bytes([*self.received.first()?, *self.received.get(1)?]) as usize;
+        self.received.get(2..2 + len).map(<[u8]>::to_vec)
+    }
 }
 
 impl<T> Task<T> {
     pub fn is_expired(&self) -> bool {
         Instant::now() >= self.expires_at
     }
+    pub fn is_done(&self) -> bool {
+        matches!(self.status, Status::Completed | Status::Cancelled)
+    }
     pub fn time_remaining(&self) -> Duration {
         self.expires_at.saturating_duration_since(Instant::now())
     }
+    /// Tries used up so far, by timing out or by a server failure
+    pub fn tries(&self) -> u32 {
+        self.timeouts + self.server_errors.len() as u32
+    }
+    /// Error to report instead of `frame` when tries ran out on a server failure RCODE.
+    /// Any timeout makes it a timeout, the worst server failure seen otherwise, whatever the last server said
+    pub fn final_error(&self, frame: &DnsFrame) -> Option<DnsError> {
+        if !Rcode::from_flags(frame.flags)?.is_server_failure() {
+            return None;
+        }
+        if self.timeouts > 0 {
+            return Some(DnsError::Timeout);
+        }
+        let worst = self.server_errors.iter().copied().max_by_key(Rcode::severity)?;
+        Some(worst.into())
+    }
+    /// Closes the TCP connection if any, going back to the UDP socket. Returns the truncated UDP reply
+    fn end_tcp(&mut self) -> Option<Vec<u8>> {
+        let exchange = self.tcp.take()?;
+        self.sock = exchange.udp;
+        Some(exchange.truncated)
+    }
+    /// Re-arms the task with a larger EDNS buffer size, if it's worth retrying over UDP
+    fn bump_edns_udp_size(&mut self) -> bool {
+        match self.request.edns_udp_size() {
+            Some(size) if size < EDNS_RETRY_UDP_SIZE => {
+                self.request.set_edns_udp_size(Some(EDNS_RETRY_UDP_SIZE));
+                self.writebuf.clear();
+                self.request.write(&mut self.writebuf);
+                self.status = Status::Writing;
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Re-arms the task without its OPT record, if it had one
+    fn drop_edns(&mut self) -> bool {
+        if self.request.edns_udp_size().is_none() {
+            return false;
+        }
+        self.request.set_edns_udp_size(None);
+        self.writebuf.clear();
+        self.request.write(&mut self.writebuf);
+        self.status = Status::Writing;
+        true
+    }
+    /// Receive buffer sized to the advertised EDNS payload size (at least 512), or 65535 without EDNS
+    pub fn receive_buffer_size(&self) -> usize {
+        match self.request.edns_udp_size() {
+            Some(size) => size.max(512) as usize,
+            None => 65_535,
+        }
+    }
+    fn has_answer(&self, frame: &DnsFrame) -> bool {
+        let qtype = self.request.queries[0].qtype;
+        frame.answers.iter().any(|answer| answer.record_type == qtype)
+    }
+    /// Re-arms the task with its fallback query type, under the fresh `transaction_id`
+    fn fall_back(&mut self, transaction_id: u16) -> bool {
+        let Some(qtype) = self.fallback_qtype.take() else { return false };
+        self.request.queries[0].qtype = qtype.as_u16();
+        self.request.transaction_id = transaction_id;
+        self.writebuf.clear();
+        self.request.write(&mut self.writebuf);
+        self.status = Status::Writing;
+        true
+    }
 }
 
+/// `timeout` plus a share of `jitter` drawn from `rng`, which no jitter leaves untouched
+fn jittered(rng: &RefCell<Box<dyn RngCore + Send>>, timeout: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return timeout;
+    }
+    timeout + jitter.mul_f64(rng.borrow_mut().r#gen::<f64>())
+}
+
+#[cfg(not(target_os = "android"))]
 pub fn build_sysconfig() -> SysConfig {
-    let try_resolv_conf = || std::fs::read_to_string("/etc/resolv.conf").ok()?.parse::<SysConfig>().ok();
-    try_resolv_conf().unwrap_or_else(SysConfig::default)
+    ConfigSource::default().load()
+}
+
+/// Falls back to the nameservers of the system properties, resolv.conf being unreadable by apps there
+#[cfg(target_os = "android")]
+pub fn build_sysconfig() -> SysConfig {
+    let mut conf = ConfigSource::default().load();
+    if conf.nameservers.is_empty() {
+        conf.nameservers = android_nameservers(system_property);
+    }
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::*;
+
+    #[test]
+    fn test_truncated_edns_query_retries_with_larger_size() {
+        let server = StubServer::spawn(|query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            match frame.edns_udp_size() {
+                Some(size) if size >= EDNS_RETRY_UDP_SIZE => Some(reply(query, 0x8180, &[A_ANSWER])),
+                _ => Some(reply(query, 0x8380, &[])), // TC
+            }
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.config.options.edns0 = true;
+        ares.gethostbyname("example.test", Family::Ipv4, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        assert_eq!(task.request.edns_udp_size(), Some(DEFAULT_EDNS_UDP_SIZE));
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Writing);
+        assert_eq!(task.request.edns_udp_size(), Some(EDNS_RETRY_UDP_SIZE));
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_, frame) = ares.read_impl(&mut task).unwrap();
+        assert!(!frame.is_truncated());
+        assert_eq!(frame.answers.len(), 1);
+        assert!(task.status == Status::Completed);
+    }
+
+    #[test]
+    fn test_formerr_edns_query_retries_without_edns() {
+        let server = StubServer::spawn(|query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            match frame.edns_udp_size() {
+                Some(_) => Some(reply(query, 0x8181, &[])), // FORMERR
+                None => Some(reply(query, 0x8180, &[A_ANSWER])),
+            }
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.config.options.edns0 = true;
+        ares.gethostbyname("example.test", Family::Ipv4, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Writing);
+        assert_eq!(task.request.edns_udp_size(), None);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.answers.len(), 1);
+
+        ares.edns_formerr_retry = false;
+        ares.gethostbyname("example.test", Family::Ipv4, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(Rcode::from_flags(frame.flags), Some(Rcode::FormErr));
+    }
+
+    #[test]
+    fn test_retry_to_other_family_reopens_socket() {
+        let v6 = StubServer::spawn_on("::1".parse().unwrap(), |query| Some(reply(query, 0x8182, &[]))); // SERVFAIL
+        let v4 = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut config = v6.sysconfig();
+        config.nameservers.extend(v4.sysconfig().nameservers);
+        let mut ares: Ares<()> = Ares::new(config);
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        assert!(task.ipv6);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        ares.tasks.push(task);
+        assert_eq!(ares.pending_queries()[0].server, Some((v4.addr.ip(), Some(v4.addr.port()))));
+        let mut task = ares.tasks.pop().unwrap();
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(!task.ipv6);
+        assert!(wait_readable(&task.sock));
+        let (_, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_unspec_falls_back_to_a() {
+        let server = StubServer::spawn(|query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            match frame.queries[0].qtype {
+                0x01 => Some(reply(query, 0x8180, &[A_ANSWER])),
+                _ => Some(reply(query, 0x8180, &[])),
+            }
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.gethostbyname("example.test", Family::Unspec, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        assert_eq!(task.request.queries[0].qtype, 0x1c);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Writing);
+        assert_eq!(task.request.queries[0].qtype, 0x01);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_ipv6_lookup_synthesizes_aaaa_from_a() {
+        let server = StubServer::spawn(|query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            match frame.queries[0].qtype {
+                0x01 => Some(reply(query, 0x8180, &[A_ANSWER])),
+                _ => Some(reply(query, 0x8180, &[])),
+            }
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.nat64_prefix = Some(dns64::WELL_KNOWN_PREFIX);
+        ares.gethostbyname("example.test", Family::Ipv6, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.question(&buf).unwrap(), DnsQuery::new("example.test", 0x1c, 1));
+        assert_eq!(frame.answers.len(), 1);
+        assert_eq!(frame.answers[0].rtype(), RecordType::Aaaa);
+        let synthesized: Ipv6Addr = "64:ff9b::1.2.3.4".parse().unwrap();
+        assert_eq!(frame.answers[0].data, synthesized.octets());
+        assert_eq!(DnsFrame::parse(&mut Cursor::new(&buf)), Some(frame));
+    }
+
+    #[test]
+    fn test_no_aaaa_skips_aaaa_query() {
+        let qtypes = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let seen = qtypes.clone();
+        let server = StubServer::spawn(move |query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            seen.lock().unwrap().push(frame.queries[0].qtype);
+            Some(reply(query, 0x8180, &[A_ANSWER]))
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.config.options.no_aaaa = true;
+        ares.gethostbyname("example.test", Family::Unspec, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_some());
+        assert_eq!(*qtypes.lock().unwrap(), vec![0x01]);
+    }
+
+    #[test]
+    fn test_default_class_applies_to_gethostbyname() {
+        let mut ares: Ares<()> = Ares::new(SysConfig { nameservers: vec![("127.0.0.1".parse().unwrap(), None)], ..SysConfig::default() });
+        ares.default_class = 3; // CH
+        ares.gethostbyname("example.test", Family::Ipv4, ()).unwrap();
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let query = &ares.tasks[0].request.queries[0];
+        assert_eq!((query.qtype, query.qclass), (1, 3));
+        assert_eq!(ares.tasks[1].request.queries[0].qclass, 1);
+    }
+
+    #[test]
+    fn test_edns0_option_controls_opt_record() {
+        let sent_opt = |ares: &mut Ares<()>| {
+            ares.query("example.test", 1, 1, ()).unwrap();
+            let task = ares.tasks.pop().unwrap();
+            let sent = DnsFrame::parse(&mut Cursor::new(&task.writebuf[..])).unwrap();
+            assert_eq!(sent.edns_udp_size(), task.request.edns_udp_size());
+            (sent.additionals.len(), sent.edns_udp_size())
+        };
+        let mut ares: Ares<()> = Ares::new("nameserver 127.0.0.1".parse().unwrap());
+        assert_eq!(sent_opt(&mut ares), (0, None));
+
+        ares.config = "nameserver 127.0.0.1\noptions edns0".parse().unwrap();
+        assert_eq!(sent_opt(&mut ares), (1, Some(DEFAULT_EDNS_UDP_SIZE)));
+        ares.edns_udp_size = 4000;
+        assert_eq!(sent_opt(&mut ares), (1, Some(4000)));
+    }
+
+    #[test]
+    fn test_receive_buffer_follows_edns_size() {
+        // Almost 700 bytes without TC, past the advertised 512
+        let txt = [&b"\xc0\x0c\x00\x10\x00\x01\x00\x00\x01\x2c\x00\xc9\xc8"[..], &[b'x'; 200]].concat();
+        let server = StubServer::spawn(move |query| Some(reply(query, 0x8180, &[&txt, &txt, &txt])));
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.config.options.edns0 = true;
+        ares.edns_udp_size = 512;
+        ares.query("example.test", 1, 0x10, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        assert_eq!(task.receive_buffer_size(), 512);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Writing);
+        assert_eq!(task.receive_buffer_size(), EDNS_RETRY_UDP_SIZE as usize);
+
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert!(buf.len() > 512);
+        assert_eq!(frame.answers.len(), 3);
+    }
+
+    #[test]
+    fn test_query_to_ignores_configured_servers() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        // Nothing listens on the configured nameserver
+        let config = SysConfig { nameservers: vec![("127.0.0.1".parse().unwrap(), Some(9))], ..SysConfig::default() };
+        let mut ares: Ares<()> = Ares::new(config);
+        ares.query_to(server.addr, "example.test", 1, 1, ()).unwrap();
+        assert_eq!(ares.pending_queries()[0].server, Some((server.addr.ip(), Some(server.addr.port()))));
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_latency_follows_server_delay() {
+        let server = StubServer::spawn(|query| {
+            std::thread::sleep(Duration::from_millis(50));
+            Some(reply(query, 0x8180, &[A_ANSWER]))
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        assert_eq!(task.latency, None);
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        ares.read_impl(&mut task).unwrap();
+        let latency = task.latency.unwrap();
+        assert!(latency >= Duration::from_millis(50) && latency < Duration::from_millis(900), "{:?}", latency);
+    }
+
+    #[test]
+    fn test_fixed_source_port() {
+        let port = UdpSocket::bind(("0.0.0.0", 0)).unwrap().local_addr().unwrap().port();
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let config = SysConfig { nameservers: vec![(addr.ip(), Some(addr.port()))], ..SysConfig::default() };
+        let mut ares: Ares<()> = Ares::new(config);
+        ares.udp_source_port = port;
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        let mut buf = [0u8; 512];
+        let (_len, src) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(src.port(), port);
+    }
+
+    #[test]
+    fn test_truncated_reply_goes_over_tcp_without_blocking() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8380, &[]))) // TC
+            .with_tcp(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.sock.is_tcp() && task.status == Status::Writing);
+
+        assert!(wait_writable(&task.sock));
+        ares.write_impl(&mut task).unwrap();
+        assert!(task.status == Status::Reading);
+        let mut reply = None;
+        while reply.is_none() && task.status == Status::Reading {
+            assert!(wait_readable(&task.sock));
+            reply = ares.read_impl(&mut task);
+        }
+        let (_buf, frame) = reply.unwrap();
+        assert!(!frame.is_truncated());
+        assert_eq!(frame.answers.len(), 1);
+        assert!(!task.sock.is_tcp() && task.status == Status::Completed);
+    }
+
+    #[test]
+    fn test_truncated_reply_kept_when_tcp_is_refused() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8380, &[A_ANSWER]))); // TC, nothing on TCP
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(wait_writable(&task.sock));
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        let (_buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert!(frame.is_truncated());
+        assert_eq!(frame.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_retry_jitter_spreads_expiry() {
+        let mut ares: Ares<()> = Ares::new(SysConfig { nameservers: vec![("127.0.0.1".parse().unwrap(), Some(9))], ..SysConfig::default() });
+        ares.retry_jitter = Duration::from_millis(500);
+        ares.query("a.example.test", 1, 1, ()).unwrap();
+        ares.query("b.example.test", 1, 1, ()).unwrap();
+        assert_ne!(ares.tasks[0].expires_at, ares.tasks[1].expires_at);
+        let timeout = Duration::new(1, 0) * ares.config.options.timeout_secs;
+        for task in &ares.tasks {
+            assert!(task.time_remaining() <= timeout + ares.retry_jitter);
+        }
+
+        for task in &mut ares.tasks {
+            task.expires_at = Instant::now();
+        }
+        assert_eq!(ares.retry_expired().len(), 2);
+        assert_ne!(ares.tasks[0].expires_at, ares.tasks[1].expires_at);
+    }
+
+    #[test]
+    fn test_retransmissions_back_off() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut ares: Ares<()> = Ares::new(SysConfig { nameservers: vec![(addr.ip(), Some(addr.port()))], ..SysConfig::default() });
+        ares.config.options.attempts = 3;
+        ares.config.options.timeout_secs = 1;
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut sends = 0;
+        let mut timeouts = vec![];
+        let mut buf = [0u8; 512];
+        loop {
+            let mut task = ares.tasks.pop().unwrap();
+            ares.write_impl(&mut task).unwrap();
+            assert!(server.recv_from(&mut buf).is_ok());
+            sends += 1;
+            task.expires_at = Instant::now();
+            ares.tasks.push(task);
+            if ares.retry_expired().is_empty() {
+                break;
+            }
+            timeouts.push(ares.tasks[0].time_remaining());
+        }
+        assert_eq!(sends, 3);
+        assert!(timeouts[0] > Duration::from_millis(1900) && timeouts[0] <= Duration::from_secs(2), "{:?}", timeouts);
+        assert!(timeouts[1] > Duration::from_millis(3900) && timeouts[1] <= Duration::from_secs(4), "{:?}", timeouts);
+        assert!(ares.tasks[0].is_expired());
+    }
+
+    #[test]
+    fn test_seeded_rng_gives_known_ids() {
+        let config = SysConfig { nameservers: vec![("127.0.0.1".parse().unwrap(), Some(9))], ..SysConfig::default() };
+        let expected = StdRng::seed_from_u64(42).r#gen::<u16>();
+        let mut ares: Ares<()> = Ares::new(config.clone());
+        ares.set_rng(StdRng::seed_from_u64(42));
+        assert_eq!(ares.query("example.test", 1, 1, ()).unwrap().request.transaction_id, expected);
+        let mut other: Ares<()> = Ares::new(config);
+        other.set_rng(StdRng::seed_from_u64(42));
+        assert_eq!(other.query("example.test", 1, 1, ()).unwrap().request.transaction_id, expected);
+    }
+
+    #[test]
+    fn test_seeded_rng_gives_known_jitter() {
+        let config = SysConfig { nameservers: vec![("127.0.0.1".parse().unwrap(), Some(9))], ..SysConfig::default() };
+        let mut seeded = StdRng::seed_from_u64(42);
+        seeded.r#gen::<u16>();
+        let jitter = Duration::from_millis(500);
+        let expected = Duration::new(1, 0) * config.options.timeout_secs + jitter.mul_f64(seeded.r#gen::<f64>());
+        let mut ares: Ares<()> = Ares::new(config);
+        ares.set_rng(StdRng::seed_from_u64(42));
+        ares.retry_jitter = jitter;
+        let before = Instant::now();
+        let expires_at = ares.query("example.test", 1, 1, ()).unwrap().expires_at;
+        assert!(expires_at >= before + expected && expires_at <= Instant::now() + expected);
+    }
+
+    #[test]
+    fn test_mismatched_transaction_id_is_dropped() {
+        let server = StubServer::spawn(|query| {
+            let mut forged = reply(query, 0x8180, &[A_ANSWER]);
+            forged[0] ^= 0xff;
+            Some(forged)
+        });
+        let mut ares: Ares<()> = Ares::new(server.sysconfig());
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Reading);
+    }
+
+    #[test]
+    fn test_reply_from_other_address_is_dropped() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let spoofer = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut ares: Ares<()> = Ares::new(SysConfig { nameservers: vec![(addr.ip(), Some(addr.port()))], ..SysConfig::default() });
+        ares.query("example.test", 1, 1, ()).unwrap();
+        let mut task = ares.tasks.pop().unwrap();
+        ares.write_impl(&mut task).unwrap();
+        let mut buf = [0u8; 512];
+        let (len, src) = server.recv_from(&mut buf).unwrap();
+        let answer = reply(&buf[0..len], 0x8180, &[A_ANSWER]);
+        spoofer.send_to(&answer, src).unwrap();
+        assert!(wait_readable(&task.sock));
+        assert!(ares.read_impl(&mut task).is_none());
+        assert!(task.status == Status::Reading);
+
+        // The genuine reply queued behind a bogus one still gets through
+        spoofer.send_to(&answer, src).unwrap();
+        server.send_to(&answer, src).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let (_buf, frame) = ares.read_impl(&mut task).unwrap();
+        assert_eq!(frame.answers.len(), 1);
+    }
 }
@@ -0,0 +1,62 @@
+use std::io::Cursor;
+use std::net::{ Ipv4Addr, Ipv6Addr };
+
+use crate::core::packets::*;
+
+/// The well-known NAT64 prefix of RFC 6052, 64:ff9b::/96
+pub const WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// IPv6 address reaching `v4` through a NAT64 gateway serving the /96 `prefix`
+pub fn synthesize(prefix: Ipv6Addr, v4: Ipv4Addr) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[12..].copy_from_slice(&v4.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// AAAA reply to the question of an A reply, each A answer mapped into `prefix` and CNAMEs kept.
+/// Names are spelled out in full and the other sections dropped, the records changing size
+pub fn synthesize_reply(frame: &DnsFrame, buf: &[u8], prefix: Ipv6Addr) -> Option<(Vec<u8>, DnsFrame)> {
+    let expand = |label: &DnsLabel| Some(DnsLabel { name: label.expand(buf)?, offset: None });
+    let mut question = frame.question(buf)?;
+    question.qtype = RecordType::Aaaa.as_u16();
+    let mut answers = vec![];
+    for answer in &frame.answers {
+        let (record_type, data) = match answer.rtype() {
+            RecordType::A => {
+                let v4: [u8; 4] = answer.data[..].try_into().ok()?;
+                (RecordType::Aaaa.as_u16(), synthesize(prefix, v4.into()).octets().to_vec())
+            }
+            RecordType::Cname => {
+                let mut data = vec![];
+                expand(&DnsLabel::parse(&mut Cursor::new(&answer.data))?)?.write(&mut data);
+                (answer.record_type, data)
+            }
+            _ => continue,
+        };
+        answers.push(DnsAnswer { name: expand(&answer.name)?, record_type, class: answer.class, ttl: answer.ttl, data });
+    }
+    let synthesized = DnsFrame {
+        transaction_id: frame.transaction_id,
+        flags: frame.flags,
+        queries: vec![question],
+        answers,
+        authorities: vec![],
+        additionals: vec![],
+    };
+    let mut out = Vec::with_capacity(synthesized.encoded_len());
+    synthesized.write(&mut out);
+    Some((out, synthesized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesize_into_prefix() {
+        let addr = synthesize(WELL_KNOWN_PREFIX, Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(addr, "64:ff9b::1.2.3.4".parse::<Ipv6Addr>().unwrap());
+        let custom = synthesize("2001:db8:64::".parse().unwrap(), Ipv4Addr::new(192, 0, 2, 33));
+        assert_eq!(custom, "2001:db8:64::c000:221".parse::<Ipv6Addr>().unwrap());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// Reply is malformed or couldn't be parsed
+    BadResponse,
+    /// Reply carries no records of the requested type
+    NoData,
+    /// The name doesn't exist (NXDOMAIN)
+    NotFound,
+    /// No reply arrived before the query expired
+    Timeout,
+    /// The query couldn't be sent or received, e.g. no socket could be had
+    Transport(io::ErrorKind),
+    /// The server answered with an error RCODE
+    Protocol(Rcode),
+}
+
+impl DnsError {
+    /// Whether the same query may succeed when sent again; protocol errors are the server's final word
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DnsError::Timeout | DnsError::Transport(_))
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(err: io::Error) -> DnsError {
+        DnsError::Transport(err.kind())
+    }
+}
+
+impl From<Rcode> for DnsError {
+    fn from(rcode: Rcode) -> DnsError {
+        match rcode {
+            Rcode::NxDomain => DnsError::NotFound,
+            rcode => DnsError::Protocol(rcode),
+        }
+    }
+}
+
+/// Response code of a reply other than NOERROR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rcode {
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    Other(u8),
+}
+
+impl Rcode {
+    /// RCODE from the low 4 bits of the header flags, None for NOERROR
+    pub fn from_flags(flags: u16) -> Option<Rcode> {
+        match (flags & 0x0f) as u8 {
+            0 => None,
+            1 => Some(Rcode::FormErr),
+            2 => Some(Rcode::ServFail),
+            3 => Some(Rcode::NxDomain),
+            4 => Some(Rcode::NotImp),
+            5 => Some(Rcode::Refused),
+            code => Some(Rcode::Other(code)),
+        }
+    }
+    /// Whether the server itself failed rather than the query, so that another server may do better
+    pub fn is_server_failure(&self) -> bool {
+        matches!(self, Rcode::ServFail | Rcode::NotImp | Rcode::Refused)
+    }
+    /// Ranks server failures for reporting the worst one, a server that broke beating one that declined
+    pub fn severity(&self) -> u8 {
+        match self {
+            Rcode::ServFail => 3,
+            Rcode::Refused => 2,
+            Rcode::NotImp => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_and_protocol_errors_are_distinct() {
+        let refused = DnsError::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(refused, DnsError::Transport(io::ErrorKind::ConnectionRefused));
+        assert!(refused.is_transient());
+
+        let formerr = DnsError::Protocol(Rcode::from_flags(0x8181).unwrap());
+        assert_eq!(formerr, DnsError::Protocol(Rcode::FormErr));
+        assert!(!formerr.is_transient());
+        assert_ne!(refused, formerr);
+
+        assert_eq!(Rcode::from_flags(0x8180), None);
+        assert_eq!(Rcode::from_flags(0x8183), Some(Rcode::NxDomain));
+    }
+}
@@ -2,3 +2,16 @@ pub mod ares;
 pub mod packets;
 pub mod sysconfig;
 pub mod servers_csv;
+pub mod record;
+pub mod error;
+pub mod replies;
+pub mod reuseport;
+pub mod resolver;
+pub mod reverse;
+pub mod socket;
+pub mod addrinfo;
+pub mod dns64;
+
+pub use replies::parse_addrinfo;
+#[cfg(test)]
+pub mod testing;
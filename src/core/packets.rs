@@ -1,6 +1,61 @@
 use std::io::Cursor;
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
 use bytes::{ Buf, BufMut };
 
+macro_rules! record_types {
+    ($($name:ident = $value:literal),* $(,)?) => {
+        /// Record and query types by mnemonic; a value listed twice fails the build as an unreachable pattern
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum RecordType {
+            $($name,)*
+            /// Any other type, never holding one of the values above
+            Unknown(u16),
+        }
+
+        impl RecordType {
+            pub const KNOWN: &'static [RecordType] = &[$(RecordType::$name),*];
+
+            pub const fn as_u16(self) -> u16 {
+                match self {
+                    $(RecordType::$name => $value,)*
+                    RecordType::Unknown(value) => value,
+                }
+            }
+            #[deny(unreachable_patterns)]
+            pub const fn from_u16(value: u16) -> RecordType {
+                match value {
+                    $($value => RecordType::$name,)*
+                    value => RecordType::Unknown(value),
+                }
+            }
+        }
+    };
+}
+
+record_types! {
+    A = 1,
+    Ns = 2,
+    Cname = 5,
+    Soa = 6,
+    Wks = 11,
+    Ptr = 12,
+    Minfo = 14,
+    Mx = 15,
+    Txt = 16,
+    Afsdb = 18,
+    Aaaa = 28,
+    Srv = 33,
+    Naptr = 35,
+    Opt = 41,
+    Ds = 43,
+    Rrsig = 46,
+    Dnskey = 48,
+    Openpgpkey = 61,
+    Svcb = 64,
+    Https = 65,
+    Any = 255,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct DnsHeader {
     pub transaction_id: u16,
@@ -12,6 +67,23 @@ pub struct DnsHeader {
 }
 
 impl DnsHeader {
+    /// Query/response bit
+    pub const QR: u16 = 0x8000;
+    /// Truncation bit
+    pub const TC: u16 = 0x0200;
+    /// Recursion desired bit
+    pub const RD: u16 = 0x0100;
+    /// Recursion available bit
+    pub const RA: u16 = 0x0080;
+
+    /// Flags of a standard query (opcode QUERY)
+    pub fn query_flags(recursion_desired: bool) -> u16 {
+        if recursion_desired { DnsHeader::RD } else { 0 }
+    }
+    /// Flags of a recursive server's response carrying `rcode` (0 for NOERROR)
+    pub fn response_flags(rcode: u8) -> u16 {
+        DnsHeader::QR | DnsHeader::RD | DnsHeader::RA | (rcode & 0x0f) as u16
+    }
     pub fn parse<B: Buf>(buf: &mut B) -> Option<DnsHeader> {
         let h = DnsHeader {
             transaction_id: buf.try_get_u16().ok()?,
@@ -50,11 +122,22 @@ impl DnsQuery {
             qclass
         }
     }
+    /// Reads a question on its own, a compression pointer cutting the name short
     pub fn parse<B: Buf>(buf: &mut B) -> Option<DnsQuery> {
-        let label = DnsLabel::parse(buf)?;
+        DnsQuery::parse_with(buf, |label| Some(label.labels()))
+    }
+    /// Reads a question of the message in `main_buf`, following a compression pointer in its name.
+    /// Servers have no earlier name to point to, yet a few still compress the question
+    pub fn parse_in<B: Buf>(buf: &mut B, main_buf: &[u8]) -> Option<DnsQuery> {
+        DnsQuery::parse_with(buf, |label| {
+            Some(label.expand(main_buf)?.iter().map(|label| String::from_utf8_lossy(label).into_owned()).collect())
+        })
+    }
+    fn parse_with<B: Buf>(buf: &mut B, name: impl FnOnce(DnsLabel) -> Option<Vec<String>>) -> Option<DnsQuery> {
+        let name = name(DnsLabel::parse(buf)?)?;
         let qtype = buf.try_get_u16().ok()?;
         let qclass = buf.try_get_u16().ok()?;
-        Some(DnsQuery { name: label.name.into_iter().collect(), qtype, qclass })
+        Some(DnsQuery { name, qtype, qclass })
     }
     pub fn write<B: BufMut>(&self, b: &mut B) {
         for label in &self.name {
@@ -65,22 +148,32 @@ impl DnsQuery {
         b.put_u16(self.qtype);
         b.put_u16(self.qclass);
     }
+    /// Exact size of `write`'s output
+    pub fn encoded_len(&self) -> usize {
+        self.name.iter().map(|label| 1 + label.len()).sum::<usize>() + 1 + 4
+    }
 }
 
+/// Labels are kept as raw octets, DNS allowing any byte in them
 #[derive(Debug, PartialEq, Eq)]
 pub struct DnsLabel {
-    pub name: Vec<String>,
+    pub name: Vec<Vec<u8>>,
     pub offset: Option<u16>,
 }
 
 impl DnsLabel {
     #[cfg(test)]
     pub fn new(name: &[&str], offset: Option<u16>) -> DnsLabel {
-        DnsLabel { name: name.iter().map(ToString::to_string).collect(), offset }
+        DnsLabel { name: name.iter().map(|label| label.as_bytes().to_vec()).collect(), offset }
     }
+    /// The labels before any compression pointer, with bytes that aren't UTF-8 replaced for display
+    pub fn labels(&self) -> Vec<String> {
+        self.name.iter().map(|label| String::from_utf8_lossy(label).into_owned()).collect()
+    }
+    /// A compression pointer always ends the name, whatever follows it belongs to the next field
     pub fn parse<B: Buf>(buf: &mut B) -> Option<DnsLabel> {
         let mut cur = Cursor::new(buf.chunk());
-        let mut name: Vec<String> = vec![];
+        let mut name: Vec<Vec<u8>> = vec![];
         let mut offset: Option<u16> = None;
 
         loop {
@@ -88,29 +181,76 @@ impl DnsLabel {
             if len == 0 {
                 break;
             }
-            if len & 0xc0 > 0 {
+            if len & 0xc0 == 0xc0 {
                 let high_byte = len & 0x3f;
                 let low_byte = cur.try_get_u8().ok()?;
                 offset = Some(((high_byte as u16) << 8) | (low_byte as u16));
                 break;
             }
+            if len & 0xc0 != 0 {
+                return None; // 0x40 and 0x80 label types are reserved or obsolete
+            }
 
             let mut dst: Vec<u8> = vec![0; len as usize];
             cur.try_copy_to_slice(&mut dst[..]).ok()?;
-            name.push(String::from_utf8(dst).ok()?);
+            name.push(dst);
         }
 
         let bytes_read = cur.position() as usize;
         buf.advance(bytes_read);
         Some(DnsLabel { name, offset })
     }
+    pub fn write<B: BufMut>(&self, b: &mut B) {
+        for label in &self.name {
+            b.put_u8(label.len() as u8);
+            b.put_slice(label);
+        }
+        match self.offset {
+            Some(offset) => b.put_u16(0xc000 | offset),
+            None => b.put_u8(0),
+        }
+    }
+    pub fn encoded_len(&self) -> usize {
+        let terminator = if self.offset.is_some() { 2 } else { 1 };
+        self.name.iter().map(|label| 1 + label.len()).sum::<usize>() + terminator
+    }
+    /// Length of the name on the wire once decompressed, as far as its pointers can be followed
+    pub fn expanded_len(&self, main_buf: &[u8]) -> usize {
+        let mut len: usize = self.name.iter().map(|label| 1 + label.len()).sum();
+        let mut next_offset = self.offset;
+        for _ in 0..MAX_POINTER_JUMPS {
+            let Some(offset) = next_offset else { break };
+            let rest = main_buf.get(offset as usize..).unwrap_or_default();
+            let Some(label) = DnsLabel::parse(&mut Cursor::new(rest)) else { break };
+            len += label.name.iter().map(|label| 1 + label.len()).sum::<usize>();
+            next_offset = label.offset.filter(|&next| next < offset);
+        }
+        len
+    }
     pub fn build_string(&self, main_buf: &[u8]) -> Option<String> {
+        let name = self.expand(main_buf)?;
+        Some(name.iter().map(|label| String::from_utf8_lossy(label)).collect::<Vec<_>>().join("."))
+    }
+    /// All labels of the name, following its compression pointers through `main_buf`
+    pub fn expand(&self, main_buf: &[u8]) -> Option<Vec<Vec<u8>>> {
         let mut name = self.name.clone();
-        if let Some(offset) = self.offset {
-            let mut label = DnsLabel::parse(&mut Cursor::new(&main_buf[offset as usize..]))?;
+        let mut next_offset = self.offset;
+        let mut jumps = 0;
+        while let Some(offset) = next_offset {
+            // Chains of tiny backward hops could still take quadratic time
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return None;
+            }
+            let mut label = DnsLabel::parse(&mut Cursor::new(main_buf.get(offset as usize..)?))?;
             name.append(&mut label.name);
+            // Chained pointers must go backwards, so that following them always terminates
+            next_offset = match label.offset {
+                Some(next) if next >= offset => return None,
+                next => next,
+            };
         }
-        Some(name.join("."))
+        Some(name)
     }
 }
 
@@ -130,11 +270,37 @@ impl DnsAnswer {
         let class = buf.try_get_u16().ok()?;
         let ttl = buf.try_get_u32().ok()?;
         let data_length = buf.try_get_u16().ok()?;
+        // Checked first, so that a bogus length can't make us allocate
+        if buf.remaining() < data_length as usize {
+            return None;
+        }
 
         let mut data: Vec<u8> = vec![0; data_length as usize];
         buf.try_copy_to_slice(&mut data[..]).ok()?;
         Some(DnsAnswer { name, record_type, class, ttl, data })
     }
+    /// EDNS0 OPT pseudo-record advertising our UDP payload size (RFC 6891)
+    pub fn opt(udp_payload_size: u16) -> DnsAnswer {
+        DnsAnswer { name: DnsLabel { name: vec![], offset: None }, record_type: RecordType::Opt.as_u16(), class: udp_payload_size, ttl: 0, data: vec![] }
+    }
+    pub fn rtype(&self) -> RecordType {
+        RecordType::from_u16(self.record_type)
+    }
+    /// OPT is a pseudo-record wherever a server puts it, never answer data
+    pub fn is_opt(&self) -> bool {
+        self.rtype() == RecordType::Opt
+    }
+    pub fn write<B: BufMut>(&self, b: &mut B) {
+        self.name.write(b);
+        b.put_u16(self.record_type);
+        b.put_u16(self.class);
+        b.put_u32(self.ttl);
+        b.put_u16(self.data.len() as u16);
+        b.put_slice(&self.data);
+    }
+    pub fn encoded_len(&self) -> usize {
+        self.name.encoded_len() + 10 + self.data.len()
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -143,39 +309,235 @@ pub struct DnsFrame {
     pub flags: u16,
     pub queries: Vec<DnsQuery>,
     pub answers: Vec<DnsAnswer>,
-    // pub authority_responses: Vec<DnsAuthorityResponse>
-    // pub additional_responses: Vec<DnsAdditionalResponse>
+    pub authorities: Vec<DnsAnswer>,
+    pub additionals: Vec<DnsAnswer>,
 }
 
+/// Compression pointers followed at most to decompress one name, no valid name needing more than 127
+pub const MAX_POINTER_JUMPS: usize = 128;
+
+/// Cap on the bytes the names of a frame expand to taken together, against replies pointing thousands
+/// of records at one long name
+pub const MAX_EXPANDED_NAME_BYTES: usize = 256 * 1024;
+
 impl DnsFrame {
+    /// Parses a whole message, which `buf` is expected to hold in one chunk for the name budget to be checked
     pub fn parse<B: Buf>(buf: &mut B) -> Option<DnsFrame> {
+        let main_buf = buf.chunk();
+        let mut cur = Cursor::new(main_buf);
+        let frame = DnsFrame::parse_sections(&mut cur, main_buf)?;
+        let queries = frame.queries.iter().map(|query| query.name.iter().map(|label| 1 + label.len()).sum::<usize>());
+        let records = frame.records();
+        let expanded: usize = queries.chain(records.map(|record| record.name.expanded_len(main_buf))).sum();
+        if expanded > MAX_EXPANDED_NAME_BYTES {
+            return None;
+        }
+        let bytes_read = cur.position() as usize;
+        buf.advance(bytes_read);
+        Some(frame)
+    }
+    fn parse_sections<B: Buf>(buf: &mut B, main_buf: &[u8]) -> Option<DnsFrame> {
         let header = DnsHeader::parse(buf)?;
         let mut queries: Vec<DnsQuery> = vec![];
         let mut answers: Vec<DnsAnswer> = vec![];
+        let mut authorities: Vec<DnsAnswer> = vec![];
+        let mut additionals: Vec<DnsAnswer> = vec![];
         for _ in 0..header.qdcount {
-            queries.push(DnsQuery::parse(buf)?);
+            queries.push(DnsQuery::parse_in(buf, main_buf)?);
         }
         for _ in 0..header.ancount {
             answers.push(DnsAnswer::parse(buf)?);
         }
-        Some(DnsFrame { transaction_id: header.transaction_id, flags: header.flags, queries, answers })
+        for _ in 0..header.nscount {
+            authorities.push(DnsAnswer::parse(buf)?);
+        }
+        for _ in 0..header.arcount {
+            additionals.push(DnsAnswer::parse(buf)?);
+        }
+        Some(DnsFrame { transaction_id: header.transaction_id, flags: header.flags, queries, answers, authorities, additionals })
+    }
+    /// Like `parse`, but records left over after the counted sections, as sent by servers
+    /// under-reporting ancount, are taken as further answers if they run exactly to the end
+    pub fn parse_tolerant<B: Buf>(buf: &mut B) -> Option<DnsFrame> {
+        let mut frame = DnsFrame::parse(buf)?;
+        let mut extra: Vec<DnsAnswer> = vec![];
+        let mut rest = Cursor::new(buf.chunk());
+        while rest.has_remaining() {
+            let Some(answer) = DnsAnswer::parse(&mut rest) else { return Some(frame) };
+            extra.push(answer);
+        }
+        buf.advance(rest.position() as usize);
+        frame.answers.append(&mut extra);
+        Some(frame)
     }
     pub fn write<B: BufMut>(&self, b: &mut B) {
         let header = DnsHeader {
             transaction_id: self.transaction_id,
             flags: self.flags,
             qdcount: self.queries.len() as u16,
-            ancount: 0,
-            nscount: 0,
-            arcount: 0,
+            ancount: self.answers.len() as u16,
+            nscount: self.authorities.len() as u16,
+            arcount: self.additionals.len() as u16,
         };
         header.write(b);
         for query in &self.queries {
             query.write(b);
         }
+        for record in self.records() {
+            record.write(b);
+        }
+    }
+    /// Exact size of `write`'s output, for presizing buffers
+    pub fn encoded_len(&self) -> usize {
+        12 + self.queries.iter().map(DnsQuery::encoded_len).sum::<usize>()
+            + self.records().map(DnsAnswer::encoded_len).sum::<usize>()
+    }
+    /// Answer, authority and additional records, in wire order
+    fn records(&self) -> impl Iterator<Item = &DnsAnswer> {
+        self.answers.iter().chain(&self.authorities).chain(&self.additionals)
+    }
+    pub fn is_truncated(&self) -> bool {
+        self.flags & DnsHeader::TC != 0
+    }
+    /// UDP payload size advertised by the OPT record, if any
+    pub fn edns_udp_size(&self) -> Option<u16> {
+        self.additionals.iter().find(|record| record.is_opt()).map(|opt| opt.class)
+    }
+    /// Adds, resizes (keeping its options) or removes the OPT record
+    pub fn set_edns_udp_size(&mut self, udp_payload_size: Option<u16>) {
+        let Some(size) = udp_payload_size else {
+            self.additionals.retain(|record| !record.is_opt());
+            return;
+        };
+        match self.additionals.iter_mut().find(|record| record.is_opt()) {
+            Some(opt) => opt.class = size,
+            None => self.additionals.push(DnsAnswer::opt(size)),
+        }
+    }
+    /// Replaces the EDNS Client Subnet option of the OPT record, which must already be present
+    pub fn set_client_subnet(&mut self, subnet: Option<&ClientSubnet>) {
+        let Some(opt) = self.additionals.iter_mut().find(|record| record.is_opt()) else { return };
+        let mut data = edns_options(&opt.data).filter(|(code, _)| *code != ClientSubnet::OPTION_CODE)
+            .flat_map(|(code, value)| [&code.to_be_bytes()[..], &(value.len() as u16).to_be_bytes(), value].concat())
+            .collect::<Vec<u8>>();
+        if let Some(subnet) = subnet {
+            subnet.write(&mut data);
+        }
+        opt.data = data;
+    }
+    /// EDNS Client Subnet option echoed by the server, carrying its scope prefix
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        let opt = self.additionals.iter().find(|record| record.is_opt())?;
+        let (_, value) = edns_options(&opt.data).find(|(code, _)| *code == ClientSubnet::OPTION_CODE)?;
+        ClientSubnet::parse(value)
+    }
+    /// The first question as echoed in `main_buf`, its name decompressed should the server have used a pointer
+    pub fn question(&self, main_buf: &[u8]) -> Option<DnsQuery> {
+        self.queries.first()?;
+        DnsQuery::parse_in(&mut Cursor::new(main_buf.get(12..)?), main_buf)
+    }
+    /// Follows CNAME answers from the queried name to the terminal target
+    pub fn canonical_name(&self, main_buf: &[u8]) -> Option<String> {
+        match self.cname_chain(main_buf)?.pop() {
+            Some(hop) => Some(hop.name),
+            None => Some(self.question(main_buf)?.name.join(".")),
+        }
+    }
+    /// Follows the CNAME answers starting at the question name, one hop per alias
+    pub fn cname_chain(&self, main_buf: &[u8]) -> Option<Vec<CnameHop>> {
+        let mut alias = self.question(main_buf)?.name.join(".");
+        let mut chain = vec![];
+        // Each hop consumes one answer, which also bounds CNAME loops
+        for _ in 0..self.answers.len() {
+            let next = self.answers.iter()
+                .filter(|answer| answer.rtype() == RecordType::Cname)
+                .find(|answer| answer.name.build_string(main_buf).is_some_and(|owner| owner.eq_ignore_ascii_case(&alias)));
+            let Some(cname) = next else { break };
+            let name = DnsLabel::parse(&mut Cursor::new(&cname.data))?.build_string(main_buf)?;
+            chain.push(CnameHop { alias, name: name.clone(), ttl: cname.ttl });
+            alias = name;
+        }
+        Some(chain)
+    }
+    /// Whether `answer` belongs to the question name or to a name its CNAME chain leads to,
+    /// anything else in the answer section being unrelated to the query
+    pub fn owns_answer(&self, answer: &DnsAnswer, main_buf: &[u8]) -> bool {
+        let (Some(query), Some(chain)) = (self.question(main_buf), self.cname_chain(main_buf)) else { return false };
+        let Some(owner) = answer.name.build_string(main_buf) else { return false };
+        owner.eq_ignore_ascii_case(&query.name.join(".")) || chain.iter().any(|hop| owner.eq_ignore_ascii_case(&hop.name))
     }
 }
 
+/// Splits OPT record data into (option code, value) pairs, stopping at the first malformed option
+fn edns_options(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let code = data.try_get_u16().ok()?;
+        let len = data.try_get_u16().ok()? as usize;
+        let value = data.get(0..len)?;
+        data = &data[len..];
+        Some((code, value))
+    })
+}
+
+/// EDNS Client Subnet option (RFC 7871)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSubnet {
+    pub addr: IpAddr,
+    pub source_prefix: u8,
+    pub scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    pub const OPTION_CODE: u16 = 8;
+
+    pub fn write<B: BufMut>(&self, b: &mut B) {
+        let (family, octets) = match self.addr {
+            IpAddr::V4(v4) => (1, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (2, v6.octets().to_vec()),
+        };
+        // Only the prefix is sent, with the bits past it zeroed
+        let prefix = (self.source_prefix as usize).min(octets.len() * 8);
+        let mut address = octets[..prefix.div_ceil(8)].to_vec();
+        #[allow(clippy::manual_is_multiple_of)]
+        if let Some(last) = address.last_mut().filter(|_| prefix % 8 != 0) {
+            *last &= 0xffu8 << (8 - prefix % 8);
+        }
+        b.put_u16(Self::OPTION_CODE);
+        b.put_u16(4 + address.len() as u16);
+        b.put_u16(family);
+        b.put_u8(prefix as u8);
+        b.put_u8(self.scope_prefix);
+        b.put_slice(&address);
+    }
+    pub fn parse(mut value: &[u8]) -> Option<ClientSubnet> {
+        let family = value.try_get_u16().ok()?;
+        let source_prefix = value.try_get_u8().ok()?;
+        let scope_prefix = value.try_get_u8().ok()?;
+        let addr = match family {
+            1 if value.len() <= 4 => {
+                let mut octets = [0u8; 4];
+                octets[..value.len()].copy_from_slice(value);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 if value.len() <= 16 => {
+                let mut octets = [0u8; 16];
+                octets[..value.len()].copy_from_slice(value);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        Some(ClientSubnet { addr, source_prefix, scope_prefix })
+    }
+}
+
+/// One step of a CNAME chain, as in c-ares' ares_addrinfo_cname
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CnameHop {
+    pub alias: String,
+    pub name: String,
+    pub ttl: u32,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct MxReply {
     pub priority: u16,
@@ -183,10 +545,12 @@ pub struct MxReply {
 }
 
 pub trait Parser {
+    const RECORD_TYPE: RecordType;
     fn parse<B: Buf>(buf: &mut B) -> Option<Self> where Self: Sized;
 }
 
 impl Parser for MxReply {
+    const RECORD_TYPE: RecordType = RecordType::Mx;
     fn parse<B: Buf>(buf: &mut B) -> Option<MxReply> {
         let priority = buf.try_get_u16().ok()?;
         let label = DnsLabel::parse(buf)?;
@@ -194,18 +558,294 @@ impl Parser for MxReply {
     }
 }
 
+/// Every <character-string> of a TXT record, as raw bytes: long values (DKIM keys...) span several
 #[derive(Debug, PartialEq)]
 pub struct TxtReply {
-    pub txt: String,
-    pub length: u8,
+    pub segments: Vec<Vec<u8>>,
+}
+
+impl TxtReply {
+    /// The segments joined together, the way SPF and DKIM read them
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.segments.concat()).to_string()
+    }
+    /// Byte length of the joined segments, which `text` may exceed when replacing bytes that aren't UTF-8.
+    /// Each AresTxtReply node holds one segment, its `length` being that segment's
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(Vec::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl Parser for TxtReply {
+    const RECORD_TYPE: RecordType = RecordType::Txt;
     fn parse<B: Buf>(buf: &mut B) -> Option<TxtReply> {
-        let length = buf.try_get_u8().ok()?;
-        let txt_slice = buf.copy_to_bytes(std::cmp::min(length as usize, buf.remaining()));
-        let txt = String::from_utf8_lossy(&txt_slice).to_string();
-        Some(TxtReply { txt, length })
+        let mut segments = vec![];
+        while buf.has_remaining() {
+            let length = buf.try_get_u8().ok()?;
+            segments.push(buf.copy_to_bytes(std::cmp::min(length as usize, buf.remaining())).to_vec());
+        }
+        Some(TxtReply { segments })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AfsdbReply {
+    pub subtype: u16,
+    pub hostname: DnsLabel,
+}
+
+impl Parser for AfsdbReply {
+    const RECORD_TYPE: RecordType = RecordType::Afsdb;
+    fn parse<B: Buf>(buf: &mut B) -> Option<AfsdbReply> {
+        let subtype = buf.try_get_u16().ok()?;
+        let hostname = DnsLabel::parse(buf)?;
+        Some(AfsdbReply { subtype, hostname })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SrvReply {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub label: DnsLabel,
+}
+
+impl Parser for SrvReply {
+    const RECORD_TYPE: RecordType = RecordType::Srv;
+    fn parse<B: Buf>(buf: &mut B) -> Option<SrvReply> {
+        let priority = buf.try_get_u16().ok()?;
+        let weight = buf.try_get_u16().ok()?;
+        let port = buf.try_get_u16().ok()?;
+        let label = DnsLabel::parse(buf)?;
+        Some(SrvReply { priority, weight, port, label })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NaptrReply {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    pub services: String,
+    pub regexp: String,
+    pub replacement: DnsLabel,
+}
+
+/// A length-prefixed <character-string>, which has to fit the record entirely
+fn parse_character_string<B: Buf>(buf: &mut B) -> Option<String> {
+    let length = buf.try_get_u8().ok()? as usize;
+    if length > buf.remaining() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf.copy_to_bytes(length)).to_string())
+}
+
+impl Parser for NaptrReply {
+    const RECORD_TYPE: RecordType = RecordType::Naptr;
+    fn parse<B: Buf>(buf: &mut B) -> Option<NaptrReply> {
+        let order = buf.try_get_u16().ok()?;
+        let preference = buf.try_get_u16().ok()?;
+        let flags = parse_character_string(buf)?;
+        let services = parse_character_string(buf)?;
+        let regexp = parse_character_string(buf)?;
+        let replacement = DnsLabel::parse(buf)?;
+        Some(NaptrReply { order, preference, flags, services, regexp, replacement })
+    }
+}
+
+/// SVCB record (RFC 9460); priority 0 is AliasMode, which points at `target` alone.
+/// Parameters are kept as (SvcParamKey, raw value), known keys or not
+#[derive(Debug, PartialEq)]
+pub struct SvcbReply {
+    pub priority: u16,
+    pub target: DnsLabel,
+    pub params: Vec<(u16, Vec<u8>)>,
+}
+
+impl Parser for SvcbReply {
+    const RECORD_TYPE: RecordType = RecordType::Svcb;
+    fn parse<B: Buf>(buf: &mut B) -> Option<SvcbReply> {
+        let priority = buf.try_get_u16().ok()?;
+        let target = DnsLabel::parse(buf)?;
+        let mut params = vec![];
+        while buf.has_remaining() {
+            let key = buf.try_get_u16().ok()?;
+            let length = buf.try_get_u16().ok()? as usize;
+            if length > buf.remaining() {
+                return None;
+            }
+            params.push((key, buf.copy_to_bytes(length).to_vec()));
+        }
+        Some(SvcbReply { priority, target, params })
+    }
+}
+
+/// HTTPS record, an SVCB record under its own type
+#[derive(Debug, PartialEq)]
+pub struct HttpsReply(pub SvcbReply);
+
+impl Parser for HttpsReply {
+    const RECORD_TYPE: RecordType = RecordType::Https;
+    fn parse<B: Buf>(buf: &mut B) -> Option<HttpsReply> {
+        SvcbReply::parse(buf).map(HttpsReply)
+    }
+}
+
+impl From<HttpsReply> for SvcbReply {
+    fn from(https: HttpsReply) -> SvcbReply {
+        https.0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MinfoReply {
+    pub rmailbx: DnsLabel,
+    pub emailbx: DnsLabel,
+}
+
+impl Parser for MinfoReply {
+    const RECORD_TYPE: RecordType = RecordType::Minfo;
+    fn parse<B: Buf>(buf: &mut B) -> Option<MinfoReply> {
+        let rmailbx = DnsLabel::parse(buf)?;
+        let emailbx = DnsLabel::parse(buf)?;
+        Some(MinfoReply { rmailbx, emailbx })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WksReply {
+    pub addr: Ipv4Addr,
+    pub protocol: u8,
+    pub ports: Vec<u16>,
+}
+
+impl Parser for WksReply {
+    const RECORD_TYPE: RecordType = RecordType::Wks;
+    fn parse<B: Buf>(buf: &mut B) -> Option<WksReply> {
+        let addr = Ipv4Addr::from(buf.try_get_u32().ok()?);
+        let protocol = buf.try_get_u8().ok()?;
+        // Bit N of the bitmap (most significant bit first) stands for port N
+        let mut ports = vec![];
+        // A full 8192-byte bitmap ends at port 65535, so only set bits past it are rejected
+        let mut base: u32 = 0;
+        while buf.has_remaining() {
+            let bits = buf.get_u8();
+            for bit in (0..8).filter(|bit| bits & (0x80 >> bit) != 0) {
+                ports.push(u16::try_from(base + bit).ok()?);
+            }
+            base += 8;
+        }
+        Some(WksReply { addr, protocol, ports })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SoaReply {
+    pub mname: DnsLabel,
+    pub rname: DnsLabel,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minttl: u32,
+}
+
+impl Parser for SoaReply {
+    const RECORD_TYPE: RecordType = RecordType::Soa;
+    fn parse<B: Buf>(buf: &mut B) -> Option<SoaReply> {
+        let mname = DnsLabel::parse(buf)?;
+        let rname = DnsLabel::parse(buf)?;
+        let serial = buf.try_get_u32().ok()?;
+        let refresh = buf.try_get_u32().ok()?;
+        let retry = buf.try_get_u32().ok()?;
+        let expire = buf.try_get_u32().ok()?;
+        let minttl = buf.try_get_u32().ok()?;
+        Some(SoaReply { mname, rname, serial, refresh, retry, expire, minttl })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DsReply {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+impl Parser for DsReply {
+    const RECORD_TYPE: RecordType = RecordType::Ds;
+    fn parse<B: Buf>(buf: &mut B) -> Option<DsReply> {
+        let key_tag = buf.try_get_u16().ok()?;
+        let algorithm = buf.try_get_u8().ok()?;
+        let digest_type = buf.try_get_u8().ok()?;
+        let digest = buf.copy_to_bytes(buf.remaining()).to_vec();
+        Some(DsReply { key_tag, algorithm, digest_type, digest })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DnskeyReply {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+impl Parser for DnskeyReply {
+    const RECORD_TYPE: RecordType = RecordType::Dnskey;
+    fn parse<B: Buf>(buf: &mut B) -> Option<DnskeyReply> {
+        let flags = buf.try_get_u16().ok()?;
+        let protocol = buf.try_get_u8().ok()?;
+        let algorithm = buf.try_get_u8().ok()?;
+        let public_key = buf.copy_to_bytes(buf.remaining()).to_vec();
+        Some(DnskeyReply { flags, protocol, algorithm, public_key })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RrsigReply {
+    pub type_covered: u16,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: DnsLabel,
+    pub signature: Vec<u8>,
+}
+
+impl Parser for RrsigReply {
+    const RECORD_TYPE: RecordType = RecordType::Rrsig;
+    fn parse<B: Buf>(buf: &mut B) -> Option<RrsigReply> {
+        let type_covered = buf.try_get_u16().ok()?;
+        let algorithm = buf.try_get_u8().ok()?;
+        let labels = buf.try_get_u8().ok()?;
+        let original_ttl = buf.try_get_u32().ok()?;
+        let expiration = buf.try_get_u32().ok()?;
+        let inception = buf.try_get_u32().ok()?;
+        let key_tag = buf.try_get_u16().ok()?;
+        let signer = DnsLabel::parse(buf)?;
+        // The signature runs to the end of the RDATA
+        let signature = buf.copy_to_bytes(buf.remaining()).to_vec();
+        Some(RrsigReply { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature })
+    }
+}
+
+/// DANE OpenPGP key (RFC 7929), kept as the raw transferable public key
+#[derive(Debug, PartialEq)]
+pub struct OpenpgpkeyReply {
+    pub key: Vec<u8>,
+}
+
+impl Parser for OpenpgpkeyReply {
+    const RECORD_TYPE: RecordType = RecordType::Openpgpkey;
+    fn parse<B: Buf>(buf: &mut B) -> Option<OpenpgpkeyReply> {
+        Some(OpenpgpkeyReply { key: buf.copy_to_bytes(buf.remaining()).to_vec() })
     }
 }
 
@@ -228,6 +868,41 @@ mod tests {
         assert_eq!(cur.chunk(), b"ASDF");
     }
     #[test]
+    fn test_record_type_round_trip() {
+        for &rtype in RecordType::KNOWN {
+            assert_eq!(RecordType::from_u16(rtype.as_u16()), rtype);
+        }
+        assert_eq!(RecordType::from_u16(28), RecordType::Aaaa);
+        assert_eq!(RecordType::Mx.as_u16(), 15);
+        assert_eq!(RecordType::from_u16(65280), RecordType::Unknown(65280));
+        assert_eq!(RecordType::Unknown(65280).as_u16(), 65280);
+    }
+    #[test]
+    fn test_parse_tolerant_takes_uncounted_answers() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+        ].concat();
+        assert_eq!(DnsFrame::parse(&mut Cursor::new(&buf)).unwrap().answers.len(), 1);
+        let frame = DnsFrame::parse_tolerant(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(frame.answers.len(), 2);
+        assert_eq!(frame.answers[1].data, b"\x05\x06\x07\x08");
+
+        // Trailing garbage isn't mistaken for records
+        let mut garbage = buf[0..buf.len() - 16].to_vec();
+        garbage.extend_from_slice(b"\xc0\x0c\x00");
+        assert_eq!(DnsFrame::parse_tolerant(&mut Cursor::new(&garbage)).unwrap().answers.len(), 1);
+    }
+    #[test]
+    fn test_header_flag_constructors() {
+        assert_eq!(DnsHeader::query_flags(true), 0x0100);
+        assert_eq!(DnsHeader::query_flags(false), 0x0000);
+        assert_eq!(DnsHeader::response_flags(0), 0x8180);
+        assert_eq!(DnsHeader::response_flags(3), 0x8183);
+        assert_eq!(DnsHeader::response_flags(0x13), 0x8183);
+    }
+    #[test]
     fn test_write_dns_header() {
         let header = DnsHeader {
             transaction_id: 0x8a70,
@@ -263,6 +938,26 @@ mod tests {
         let mut cur = Cursor::new(&buf);
         assert_eq!(DnsLabel::parse(&mut cur), Some(DnsLabel::new(&[], Some(0x3fff))));
         assert_eq!(cur.chunk(), b"asdf");
+
+        // Labels after a pointer aren't part of the name
+        let buf: Vec<u8> = b"\x01a\xc0\x0c\x01c\x00".to_vec();
+        let mut cur = Cursor::new(&buf);
+        assert_eq!(DnsLabel::parse(&mut cur), Some(DnsLabel::new(&["a"], Some(0x0c))));
+        assert_eq!(cur.chunk(), b"\x01c\x00");
+    }
+    #[test]
+    fn test_pointer_mid_name_ends_answer_owner() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\x01a\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(frame.answers.len(), 2);
+        assert_eq!(frame.answers[0].name.build_string(&buf).as_deref(), Some("a.example.com"));
+        assert_eq!(frame.answers[0].data, b"\x01\x02\x03\x04");
+        assert_eq!(frame.answers[1].name.build_string(&buf).as_deref(), Some("example.com"));
+        assert_eq!(frame.answers[1].data, b"\x05\x06\x07\x08");
     }
     #[test]
     fn test_parse_dns_query() {
@@ -272,6 +967,16 @@ mod tests {
         assert_eq!(cur.chunk(), b"ASDF");
     }
     #[test]
+    fn test_parse_compressed_dns_query() {
+        // "www" then a pointer to "example.com" at offset 10, past the question
+        let buf: Vec<u8> = b"\x03www\xc0\x0a\x00\x01\x00\x01\x07example\x03com\x00".to_vec();
+        let mut cur = Cursor::new(&buf);
+        assert_eq!(DnsQuery::parse_in(&mut cur, &buf), Some(DnsQuery::new("www.example.com", 1, 1)));
+        assert_eq!(cur.position(), 10);
+        assert_eq!(DnsQuery::parse(&mut Cursor::new(&buf)), Some(DnsQuery::new("www", 1, 1)));
+        assert_eq!(DnsQuery::parse_in(&mut Cursor::new(&buf), &buf[..10]), None);
+    }
+    #[test]
     fn test_write_dns_query() {
         let question = DnsQuery::new("google.com", 1, 1);
         let mut vec: Vec<u8> = vec![];
@@ -309,6 +1014,8 @@ mod tests {
             flags: 0x8180,
             queries: vec![query],
             answers: vec![answer],
+            authorities: vec![],
+            additionals: vec![],
         };
         assert_eq!(DnsFrame::parse(&mut cur), Some(expected));
     }
@@ -320,12 +1027,98 @@ mod tests {
             flags: 0x100,
             queries: vec![query],
             answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
         };
         let mut vec: Vec<u8> = vec![];
         frame.write(&mut vec);
         assert_eq!(&vec[..], b"\x8a\x70\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06\x67\x6f\x6f\x67\x6c\x65\x03\x63\x6f\x6d\x00\x00\x01\x00\x01");
     }
     #[test]
+    fn test_write_dns_frame_round_trip() {
+        let buf: Vec<u8> = b"\x8a\x70\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x06\x67\x6f\x6f\x67\x6c\x65\x03\x63\x6f\x6d\x00\x00\x01\x00\x01\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x8e\xfa\xb8\x8e".to_vec();
+        let mut vec: Vec<u8> = vec![];
+        DnsFrame::parse(&mut Cursor::new(&buf)).unwrap().write(&mut vec);
+        assert_eq!(vec, buf);
+
+        let record = |name: &[&str], record_type: u16, data: &[u8]| {
+            DnsAnswer { name: DnsLabel::new(name, None), record_type, class: 1, ttl: 300, data: data.to_vec() }
+        };
+        let mut frame = DnsFrame {
+            transaction_id: 0x1234,
+            flags: DnsHeader::response_flags(0),
+            queries: vec![DnsQuery::new("example.test", 1, 1)],
+            answers: vec![record(&["example", "test"], 1, &[192, 0, 2, 1]), record(&["example", "test"], 1, &[192, 0, 2, 2])],
+            authorities: vec![record(&["test"], 2, b"\x02ns\x04test\x00")],
+            additionals: vec![record(&["ns", "test"], 1, &[192, 0, 2, 53])],
+        };
+        frame.set_edns_udp_size(Some(1232));
+        let mut vec: Vec<u8> = vec![];
+        frame.write(&mut vec);
+        assert_eq!(vec.len(), frame.encoded_len());
+        assert_eq!(&vec[4..12], b"\x00\x01\x00\x02\x00\x01\x00\x02");
+        assert_eq!(DnsFrame::parse(&mut Cursor::new(&vec)), Some(frame));
+    }
+    #[test]
+    fn test_write_dns_frame_honors_flags() {
+        let frame = DnsFrame {
+            transaction_id: 0x8a70,
+            flags: 0x0000,
+            queries: vec![DnsQuery::new("google.com", 1, 1)],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        let mut vec: Vec<u8> = vec![];
+        frame.write(&mut vec);
+        assert_eq!(&vec[2..4], b"\x00\x00");
+        assert_eq!(u16::from_be_bytes([vec[2], vec[3]]) & DnsHeader::RD, 0);
+    }
+    #[test]
+    fn test_write_dns_frame_with_opt() {
+        let mut frame = DnsFrame {
+            transaction_id: 0x8a70,
+            flags: 0x100,
+            queries: vec![DnsQuery::new("google.com", 1, 1)],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        frame.set_edns_udp_size(Some(1232));
+        let mut vec: Vec<u8> = vec![];
+        frame.write(&mut vec);
+        assert_eq!(&vec[..12], b"\x8a\x70\x01\x00\x00\x01\x00\x00\x00\x00\x00\x01");
+        assert_eq!(&vec[vec.len() - 11..], b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00");
+        let parsed = DnsFrame::parse(&mut Cursor::new(&vec)).unwrap();
+        assert_eq!(parsed.edns_udp_size(), Some(1232));
+    }
+    #[test]
+    fn test_question_follows_name_pointer() {
+        // "www" then a pointer to "example.com" past the question, in the CNAME's rdata. The owner spells
+        // "www" out too, a pointer to the question chaining forward from there
+        let buf: Vec<u8> = b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x03www\xc0\x26\x00\x05\x00\x01\x03www\xc0\x26\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x0d\x07example\x03com\x00".to_vec();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(frame.queries[0], DnsQuery::new("www.example.com", 5, 1));
+        assert_eq!(frame.question(&buf), Some(DnsQuery::new("www.example.com", 5, 1)));
+        assert_eq!(frame.canonical_name(&buf).as_deref(), Some("example.com"));
+    }
+    #[test]
+    fn test_canonical_name_two_hop_cname() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x06\x03cdn\xc0\x10", // www.example.com -> cdn.example.com
+            b"\xc0\x2d\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x07\x04edge\xc0\x2d", // cdn.example.com -> edge.cdn.example.com
+            b"\xc0\x3f\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(frame.canonical_name(&buf), Some("edge.cdn.example.com".to_string()));
+        assert_eq!(frame.cname_chain(&buf), Some(vec![
+            CnameHop { alias: "www.example.com".to_string(), name: "cdn.example.com".to_string(), ttl: 300 },
+            CnameHop { alias: "cdn.example.com".to_string(), name: "edge.cdn.example.com".to_string(), ttl: 300 },
+        ]));
+        assert!(frame.answers.iter().all(|answer| frame.owns_answer(answer, &buf)));
+    }
+    #[test]
     fn test_parse_mx_response() {
         let buf: Vec<u8> = b"\x00\x14\x07\x73\x6d\x74\x70\x69\x6e\x32\xc0\x0c".to_vec();
         let mut cur = Cursor::new(&buf);
@@ -333,10 +1126,279 @@ mod tests {
         assert_eq!(MxReply::parse(&mut cur), Some(expected));
     }
     #[test]
+    fn test_parse_afsdb_response() {
+        let buf: Vec<u8> = b"\x00\x01\x04afs1\xc0\x0c".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = AfsdbReply { subtype: 1, hostname: DnsLabel::new(&["afs1"], Some(0x0c)) };
+        assert_eq!(AfsdbReply::parse(&mut cur), Some(expected));
+    }
+    #[test]
+    fn test_client_subnet_option() {
+        let mut frame = DnsFrame { transaction_id: 1, flags: 0x100, queries: vec![], answers: vec![], authorities: vec![], additionals: vec![] };
+        frame.set_edns_udp_size(Some(1232));
+        let subnet = ClientSubnet { addr: "192.0.2.77".parse().unwrap(), source_prefix: 24, scope_prefix: 0 };
+        frame.set_client_subnet(Some(&subnet));
+        assert_eq!(frame.additionals[0].data, b"\x00\x08\x00\x07\x00\x01\x18\x00\xc0\x00\x02");
+        frame.set_edns_udp_size(Some(4096));
+        assert_eq!(frame.additionals[0].data.len(), 11);
+
+        // A server reply scoping the answer to a /16
+        frame.additionals[0].data[7] = 16;
+        let echoed = ClientSubnet { addr: "192.0.2.0".parse().unwrap(), source_prefix: 24, scope_prefix: 16 };
+        assert_eq!(frame.client_subnet(), Some(echoed));
+        frame.set_client_subnet(None);
+        assert!(frame.additionals[0].data.is_empty());
+
+        // A prefix longer than the address is sent as the whole address
+        let subnet = ClientSubnet { addr: "192.0.2.77".parse().unwrap(), source_prefix: 200, scope_prefix: 0 };
+        frame.set_client_subnet(Some(&subnet));
+        assert_eq!(frame.additionals[0].data, b"\x00\x08\x00\x08\x00\x01\x20\x00\xc0\x00\x02\x4d");
+    }
+    #[test]
+    fn test_parse_minfo_response() {
+        let buf: Vec<u8> = b"\x05admin\xc0\x0c\x06errors\xc0\x0c".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = MinfoReply { rmailbx: DnsLabel::new(&["admin"], Some(0x0c)), emailbx: DnsLabel::new(&["errors"], Some(0x0c)) };
+        assert_eq!(MinfoReply::parse(&mut cur), Some(expected));
+    }
+    #[test]
+    fn test_parse_wks_response() {
+        // TCP (6) with ports 21, 22, 25 and 80
+        let buf: Vec<u8> = b"\xc0\x00\x02\x01\x06\x00\x00\x06\x40\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = WksReply { addr: Ipv4Addr::new(192, 0, 2, 1), protocol: 6, ports: vec![21, 22, 25, 80] };
+        assert_eq!(WksReply::parse(&mut cur), Some(expected));
+
+        let mut full = b"\xc0\x00\x02\x01\x11".to_vec();
+        full.extend([0u8; 8192]);
+        full[5 + 8191] = 0x01;
+        assert_eq!(WksReply::parse(&mut Cursor::new(&full)).map(|wks| wks.ports), Some(vec![65535]));
+        full.push(0x80);
+        assert_eq!(WksReply::parse(&mut Cursor::new(&full)), None);
+    }
+    #[test]
+    fn test_parse_ds_response() {
+        // Key tag 20326, RSA/SHA-256 (8), SHA-256 digest (2)
+        let buf: Vec<u8> = b"\x4f\x66\x08\x02\xe0\x6d\x44\xb8\x0b\x8f\x1d\x39".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = DsReply { key_tag: 20326, algorithm: 8, digest_type: 2, digest: b"\xe0\x6d\x44\xb8\x0b\x8f\x1d\x39".to_vec() };
+        assert_eq!(DsReply::parse(&mut cur), Some(expected));
+        assert_eq!(DsReply::parse(&mut Cursor::new(b"\x4f\x66\x08")), None);
+    }
+    #[test]
+    fn test_parse_dnskey_response() {
+        // Zone key with the secure entry point bit (257), protocol 3, Ed25519 (15)
+        let buf: Vec<u8> = b"\x01\x01\x03\x0f\x11\x22\x33\x44".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = DnskeyReply { flags: 257, protocol: 3, algorithm: 15, public_key: b"\x11\x22\x33\x44".to_vec() };
+        assert_eq!(DnskeyReply::parse(&mut cur), Some(expected));
+    }
+    #[test]
+    fn test_parse_rrsig_response() {
+        // Covers A, Ed25519, 2 labels, TTL 300, signed by the compressed owner name
+        let buf: Vec<u8> = b"\x00\x01\x0f\x02\x00\x00\x01\x2c\x66\x00\x00\x00\x65\x00\x00\x00\x30\x39\xc0\x0c\xaa\xbb\xcc".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = RrsigReply {
+            type_covered: 1,
+            algorithm: 15,
+            labels: 2,
+            original_ttl: 300,
+            expiration: 0x66000000,
+            inception: 0x65000000,
+            key_tag: 12345,
+            signer: DnsLabel::new(&[], Some(0x0c)),
+            signature: b"\xaa\xbb\xcc".to_vec(),
+        };
+        assert_eq!(RrsigReply::parse(&mut cur), Some(expected));
+    }
+    #[test]
+    fn test_expanded_name_budget() {
+        // Every answer's owner points at the question name, 4 labels of 63 bytes
+        let label = [&[63u8][..], &[b'a'; 63]].concat();
+        let question = [&label[..], &label, &label, &label, b"\x00\x00\xff\x00\x01"].concat();
+        let name_len = 4 * 64;
+        let record = b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x00";
+        let response = |count: usize| {
+            let header = [&b"\x12\x34\x81\x80\x00\x01"[..], &(count as u16).to_be_bytes(), b"\x00\x00\x00\x00"].concat();
+            [header, question.clone(), record.repeat(count)].concat()
+        };
+        let fitting = MAX_EXPANDED_NAME_BYTES / name_len - 1;
+        let buf = response(fitting);
+        assert_eq!(DnsFrame::parse(&mut Cursor::new(&buf)).unwrap().answers.len(), fitting);
+        let buf = response(fitting + 1);
+        assert_eq!(DnsFrame::parse(&mut Cursor::new(&buf)), None);
+    }
+    #[test]
+    fn test_non_utf8_labels_parse() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x01\x34\x07in-addr\x04arpa\x00\x00\x0c\x00\x01"[..],
+            b"\xc0\x0c\x00\x0c\x00\x01\x00\x00\x01\x2c\x00\x0d\x03h\xffs\x07example\x00",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        let target = DnsLabel::parse(&mut Cursor::new(&frame.answers[0].data)).unwrap();
+        assert_eq!(target.name[0], b"h\xffs");
+        assert_eq!(target.labels(), vec!["h\u{fffd}s", "example"]);
+        assert_eq!(target.build_string(&buf).as_deref(), Some("h\u{fffd}s.example"));
+        let mut written: Vec<u8> = vec![];
+        target.write(&mut written);
+        assert_eq!(written, frame.answers[0].data);
+    }
+    #[test]
+    fn test_parse_openpgpkey_response() {
+        // Start of an old-format public key packet
+        let buf: Vec<u8> = b"\x99\x01\x0d\x04\x5a\x1b".to_vec();
+        let mut cur = Cursor::new(&buf);
+        assert_eq!(OpenpgpkeyReply::parse(&mut cur), Some(OpenpgpkeyReply { key: buf.clone() }));
+        assert!(!cur.has_remaining());
+    }
+    #[test]
+    fn test_parse_srv_response() {
+        let buf: Vec<u8> = b"\x00\x0a\x00\x05\x13\xc4\x03sip\xc0\x0c".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = SrvReply { priority: 10, weight: 5, port: 5060, label: DnsLabel::new(&["sip"], Some(0x0c)) };
+        assert_eq!(SrvReply::parse(&mut cur), Some(expected));
+    }
+    #[test]
+    fn test_parse_svcb_response() {
+        // ServiceMode with alpn=h2 and a key from the private range, then AliasMode
+        let buf: Vec<u8> = b"\x00\x01\x03svc\xc0\x0c\x00\x01\x00\x03\x02h2\xfd\xe8\x00\x02\xab\xcd".to_vec();
+        let expected = SvcbReply {
+            priority: 1,
+            target: DnsLabel::new(&["svc"], Some(0x0c)),
+            params: vec![(1, b"\x02h2".to_vec()), (65000, vec![0xab, 0xcd])],
+        };
+        assert_eq!(SvcbReply::parse(&mut Cursor::new(&buf)), Some(expected));
+        let alias = SvcbReply::parse(&mut Cursor::new(b"\x00\x00\x03cdn\xc0\x0c")).unwrap();
+        assert_eq!((alias.priority, alias.params.len()), (0, 0));
+        assert_eq!(SvcbReply::parse(&mut Cursor::new(&buf[..buf.len() - 1])), None);
+    }
+    #[test]
+    fn test_parse_naptr_response() {
+        let buf: Vec<u8> = b"\x00\x64\x00\x0a\x01u\x07E2U+sip\x00\x04_sip\x04_udp\xc0\x0c".to_vec();
+        let mut cur = Cursor::new(&buf);
+        let expected = NaptrReply {
+            order: 100,
+            preference: 10,
+            flags: "u".to_string(),
+            services: "E2U+sip".to_string(),
+            regexp: "".to_string(),
+            replacement: DnsLabel::new(&["_sip", "_udp"], Some(0x0c)),
+        };
+        assert_eq!(NaptrReply::parse(&mut cur), Some(expected));
+        assert_eq!(NaptrReply::parse(&mut Cursor::new(&buf[..8])), None);
+    }
+    #[test]
     fn test_parse_txt_response() {
         let buf: Vec<u8> = b"\x04abcd".to_vec();
         let mut cur = Cursor::new(&buf);
-        let expected = TxtReply { length: 4, txt: "abcd".to_string() };
+        let expected = TxtReply { segments: vec![b"abcd".to_vec()] };
         assert_eq!(TxtReply::parse(&mut cur), Some(expected));
+
+        let buf: Vec<u8> = [&[255u8][..], &[b'k'; 255], b"\x0a0123456789"].concat();
+        let txt = TxtReply::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(txt.segments.iter().map(Vec::len).collect::<Vec<_>>(), vec![255, 10]);
+        assert_eq!(txt.text().len(), 265);
+        assert_eq!(txt.len(), 265);
+
+        let txt = TxtReply::parse(&mut Cursor::new(b"\x02\xff\xfe\x00")).unwrap();
+        assert_eq!(txt.len(), 2);
+        assert_eq!(txt.text().len(), 6);
+        assert!(!txt.is_empty());
+    }
+    #[test]
+    fn test_encoded_len_matches_write() {
+        let query = DnsQuery::new("www.example.com", 1, 1);
+        let mut buf: Vec<u8> = vec![];
+        query.write(&mut buf);
+        assert_eq!(query.encoded_len(), buf.len());
+
+        let mut frame = DnsFrame { transaction_id: 1, flags: 0x100, queries: vec![query], answers: vec![], authorities: vec![], additionals: vec![] };
+        frame.set_edns_udp_size(Some(1232));
+        frame.set_client_subnet(Some(&ClientSubnet { addr: "192.0.2.1".parse().unwrap(), source_prefix: 24, scope_prefix: 0 }));
+        let mut buf: Vec<u8> = vec![];
+        frame.write(&mut buf);
+        assert_eq!(frame.encoded_len(), buf.len());
+
+        let label = DnsLabel::new(&["mail"], Some(0x0c));
+        let mut buf: Vec<u8> = vec![];
+        label.write(&mut buf);
+        assert_eq!(label.encoded_len(), buf.len());
+    }
+    #[test]
+    fn test_parse_malformed_frames() {
+        let corpus: &[&[u8]] = &[
+            b"",
+            b"\x12\x34\x81", // truncated header
+            b"\x12\x34\x81\x80\xff\xff\xff\xff\xff\xff\xff\xff", // huge counts, no records
+            b"\x12\x34\x81\x80\x00\x01\x00\x00\x00\x00\x00\x00\x07exam", // truncated label
+            b"\x12\x34\x81\x80\x00\x01\x00\x00\x00\x00\x00\x00\x40abc\x00\x00\x01\x00\x01", // reserved label type
+            b"\x12\x34\x81\x80\x00\x00\x00\x01\x00\x00\x00\x00\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\xff\xff\x01", // rdlength past the end
+        ];
+        for packet in corpus {
+            assert_eq!(DnsFrame::parse(&mut Cursor::new(packet)), None);
+        }
+    }
+    #[test]
+    fn test_bad_pointers_dont_panic() {
+        // Self-referencing, forward and out-of-bounds pointers in owner names and CNAME data
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x03www\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x15\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x02\xc0\x15",
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x02\xc0\x40",
+            b"\xff\xff\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(frame.answers[0].name.build_string(&buf), None);
+        assert_eq!(frame.answers[2].name.build_string(&buf), None);
+        assert_eq!(frame.canonical_name(&buf), None);
+    }
+    #[test]
+    fn test_pointer_loops_and_long_chains() {
+        // The question name at 0x0c is a pointer to itself
+        let buf: Vec<u8> = b"\x12\x34\x81\x80\x00\x01\x00\x00\x00\x00\x00\x00\xc0\x0c\x00\x01\x00\x01".to_vec();
+        let label = DnsLabel::parse(&mut Cursor::new(&buf[12..])).unwrap();
+        assert_eq!(label.build_string(&buf), None);
+        assert_eq!(label.expanded_len(&buf), 0);
+
+        // Backward hops onto the root label at 0, ending in one hop more than the budget
+        let mut chain: Vec<u8> = vec![0];
+        let hop_offset = |hop: usize| (1 + 2 * hop) as u16;
+        for hop in 0..MAX_POINTER_JUMPS {
+            let target = if hop == 0 { 0 } else { hop_offset(hop - 1) };
+            chain.extend_from_slice(&(0xc000 | target).to_be_bytes());
+        }
+        let longest = DnsLabel::new(&[], Some(hop_offset(MAX_POINTER_JUMPS - 2)));
+        assert_eq!(longest.build_string(&chain), Some(String::new()));
+        let too_long = DnsLabel::new(&[], Some(hop_offset(MAX_POINTER_JUMPS - 1)));
+        assert_eq!(too_long.build_string(&chain), None);
+    }
+    #[test]
+    fn test_parse_truncations_and_mutations() {
+        let valid: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x06\x03cdn\xc0\x10",
+            b"\xc0\x2d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        for len in 0..valid.len() {
+            assert_eq!(DnsFrame::parse(&mut Cursor::new(&valid[..len])), None);
+        }
+        // Deterministic byte flips over the valid packet, everything derived must stay panic-free
+        let mut state: u32 = 0x2545f491;
+        for _ in 0..10_000 {
+            let mut packet = valid.clone();
+            for _ in 0..3 {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let idx = state as usize % packet.len();
+                packet[idx] = (state >> 24) as u8;
+            }
+            if let Some(frame) = DnsFrame::parse(&mut Cursor::new(&packet)) {
+                let _ = frame.canonical_name(&packet);
+                for answer in &frame.answers {
+                    let _ = crate::core::record::Record::decode(answer, &packet);
+                }
+            }
+        }
     }
 }
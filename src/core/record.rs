@@ -0,0 +1,167 @@
+use std::io::Cursor;
+use std::net::{ Ipv4Addr, Ipv6Addr };
+use crate::core::packets::*;
+use crate::core::replies::Soa;
+
+/// Answer data decoded according to its record type, with names decompressed
+#[derive(Debug, PartialEq)]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(String),
+    Cname(String),
+    Soa(Soa),
+    Mx { priority: u16, exchange: String },
+    Txt(String),
+    Afsdb { subtype: u16, hostname: String },
+    Wks { addr: Ipv4Addr, protocol: u8, ports: Vec<u16> },
+    Minfo { rmailbx: String, emailbx: String },
+    Ds { key_tag: u16, algorithm: u8, digest_type: u8, digest: Vec<u8> },
+    Dnskey { flags: u16, protocol: u8, algorithm: u8, public_key: Vec<u8> },
+    Rrsig {
+        type_covered: RecordType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        signature: Vec<u8>,
+    },
+    Openpgpkey(Vec<u8>),
+    Unknown { record_type: u16, data: Vec<u8> },
+}
+
+impl Record {
+    /// Decodes one answer by its type. Names in the RDATA come out with compression pointers resolved against
+    /// `main_buf` and no trailing dot; a name sent uncompressed is already complete on the wire and is
+    /// taken as is, never qualified against the queried zone
+    pub fn decode(answer: &DnsAnswer, main_buf: &[u8]) -> Option<Record> {
+        let mut rdata = Cursor::new(&answer.data[..]);
+        let record = match answer.rtype() {
+            RecordType::A => Record::A(Ipv4Addr::from(<[u8; 4]>::try_from(&answer.data[..]).ok()?)),
+            RecordType::Aaaa => Record::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(&answer.data[..]).ok()?)),
+            RecordType::Ns => Record::Ns(DnsLabel::parse(&mut rdata)?.build_string(main_buf)?),
+            RecordType::Cname => Record::Cname(DnsLabel::parse(&mut rdata)?.build_string(main_buf)?),
+            RecordType::Soa => Record::Soa(answer.as_soa(main_buf)?),
+            RecordType::Mx => {
+                let mx = MxReply::parse(&mut rdata)?;
+                Record::Mx { priority: mx.priority, exchange: mx.label.build_string(main_buf)? }
+            }
+            RecordType::Txt => Record::Txt(TxtReply::parse(&mut rdata)?.text()),
+            RecordType::Afsdb => {
+                let afsdb = AfsdbReply::parse(&mut rdata)?;
+                Record::Afsdb { subtype: afsdb.subtype, hostname: afsdb.hostname.build_string(main_buf)? }
+            }
+            RecordType::Wks => {
+                let wks = WksReply::parse(&mut rdata)?;
+                Record::Wks { addr: wks.addr, protocol: wks.protocol, ports: wks.ports }
+            }
+            RecordType::Minfo => {
+                let minfo = MinfoReply::parse(&mut rdata)?;
+                Record::Minfo { rmailbx: minfo.rmailbx.build_string(main_buf)?, emailbx: minfo.emailbx.build_string(main_buf)? }
+            }
+            RecordType::Ds => {
+                let ds = DsReply::parse(&mut rdata)?;
+                Record::Ds { key_tag: ds.key_tag, algorithm: ds.algorithm, digest_type: ds.digest_type, digest: ds.digest }
+            }
+            RecordType::Dnskey => {
+                let key = DnskeyReply::parse(&mut rdata)?;
+                Record::Dnskey { flags: key.flags, protocol: key.protocol, algorithm: key.algorithm, public_key: key.public_key }
+            }
+            RecordType::Rrsig => {
+                let sig = RrsigReply::parse(&mut rdata)?;
+                Record::Rrsig {
+                    type_covered: RecordType::from_u16(sig.type_covered),
+                    algorithm: sig.algorithm,
+                    labels: sig.labels,
+                    original_ttl: sig.original_ttl,
+                    expiration: sig.expiration,
+                    inception: sig.inception,
+                    key_tag: sig.key_tag,
+                    signer: sig.signer.build_string(main_buf)?,
+                    signature: sig.signature,
+                }
+            }
+            RecordType::Openpgpkey => Record::Openpgpkey(OpenpgpkeyReply::parse(&mut rdata)?.key),
+            _ => Record::Unknown { record_type: answer.record_type, data: answer.data.clone() },
+        };
+        Some(record)
+    }
+    /// Every answer of a reply decoded by its own type, as wanted for the mixed answers to ANY queries
+    pub fn decode_answers(main_buf: &[u8]) -> Option<Vec<Record>> {
+        let frame = DnsFrame::parse(&mut Cursor::new(main_buf))?;
+        frame.answers.iter()
+            .filter(|answer| !answer.is_opt())
+            .map(|answer| Record::decode(answer, main_buf))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_afsdb() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03org\x00\x00\x12\x00\x01"[..],
+            b"\xc0\x0c\x00\x12\x00\x01\x00\x00\x01\x2c\x00\x09\x00\x01\x04afs1\xc0\x0c",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        let record = Record::decode(&frame.answers[0], &buf);
+        assert_eq!(record, Some(Record::Afsdb { subtype: 1, hostname: "afs1.example.org".to_string() }));
+    }
+
+    #[test]
+    fn test_decode_minfo_compressed() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03org\x00\x00\x0e\x00\x01"[..],
+            b"\xc0\x0c\x00\x0e\x00\x01\x00\x00\x01\x2c\x00\x11\x05admin\xc0\x0c\x06errors\xc0\x0c",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        let record = Record::decode(&frame.answers[0], &buf);
+        assert_eq!(record, Some(Record::Minfo { rmailbx: "admin.example.org".to_string(), emailbx: "errors.example.org".to_string() }));
+    }
+
+    #[test]
+    fn test_decode_rrsig_compressed_signer() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03org\x00\x00\x2e\x00\x01"[..],
+            b"\xc0\x0c\x00\x2e\x00\x01\x00\x00\x01\x2c\x00\x17",
+            b"\x00\x01\x0f\x02\x00\x00\x01\x2c\x66\x00\x00\x00\x65\x00\x00\x00\x30\x39\xc0\x0c\xaa\xbb\xcc",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        let Some(Record::Rrsig { type_covered, signer, signature, .. }) = Record::decode(&frame.answers[0], &buf) else { panic!() };
+        assert_eq!(type_covered, RecordType::A);
+        assert_eq!(signer, "example.org");
+        assert_eq!(signature, b"\xaa\xbb\xcc");
+    }
+
+    #[test]
+    fn test_decode_any_answers() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x07example\x03org\x00\x00\xff\x00\x01"[..],
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x07\x00\x0a\x02mx\xc0\x0c",
+            b"\xc0\x0c\x00\x10\x00\x01\x00\x00\x01\x2c\x00\x06\x05hello",
+        ].concat();
+        let records = Record::decode_answers(&buf).unwrap();
+        assert_eq!(records, vec![
+            Record::A(Ipv4Addr::new(1, 2, 3, 4)),
+            Record::Mx { priority: 10, exchange: "mx.example.org".to_string() },
+            Record::Txt("hello".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_decode_uncompressed_cname_target() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x03www\x07example\x03org\x00\x00\x05\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x0f\x03cdn\x05other\x03net\x00",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(Record::decode(&frame.answers[0], &buf), Some(Record::Cname("cdn.other.net".to_string())));
+    }
+}
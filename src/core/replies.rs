@@ -0,0 +1,254 @@
+use std::io::Cursor;
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
+use crate::core::packets::*;
+use crate::core::error::DnsError;
+use crate::core::ares::Family;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mx {
+    pub priority: u16,
+    pub host: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Txt {
+    pub txt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl DnsAnswer {
+    pub fn as_soa(&self, main_buf: &[u8]) -> Option<Soa> {
+        if self.rtype() != SoaReply::RECORD_TYPE {
+            return None;
+        }
+        let soa = SoaReply::parse(&mut Cursor::new(&self.data))?;
+        Some(Soa {
+            mname: soa.mname.build_string(main_buf)?,
+            rname: soa.rname.build_string(main_buf)?,
+            serial: soa.serial,
+            refresh: soa.refresh,
+            retry: soa.retry,
+            expire: soa.expire,
+            minimum: soa.minttl,
+        })
+    }
+}
+
+/// Parses every answer of the parser's record type, with names left compressed against `buf`
+pub fn parse_answers<T: Parser>(buf: &[u8]) -> Result<Vec<T>, DnsError> {
+    let frame = DnsFrame::parse(&mut Cursor::new(buf)).ok_or(DnsError::BadResponse)?;
+    let replies = frame.answers.iter()
+        .filter(|answer| answer.rtype() == T::RECORD_TYPE)
+        .map(|answer| T::parse(&mut Cursor::new(&answer.data)).ok_or(DnsError::BadResponse))
+        .collect::<Result<Vec<T>, DnsError>>()?;
+    if replies.is_empty() {
+        return Err(DnsError::NoData);
+    }
+    Ok(replies)
+}
+
+pub fn parse_mx(buf: &[u8]) -> Result<Vec<Mx>, DnsError> {
+    parse_answers::<MxReply>(buf)?.into_iter().map(|mx| {
+        let host = mx.label.build_string(buf).ok_or(DnsError::BadResponse)?;
+        Ok(Mx { priority: mx.priority, host })
+    }).collect()
+}
+
+pub fn parse_txt(buf: &[u8]) -> Result<Vec<Txt>, DnsError> {
+    Ok(parse_answers::<TxtReply>(buf)?.into_iter().map(|txt| Txt { txt: txt.text() }).collect())
+}
+
+pub fn parse_srv(buf: &[u8]) -> Result<Vec<Srv>, DnsError> {
+    parse_answers::<SrvReply>(buf)?.into_iter().map(|srv| {
+        let target = srv.label.build_string(buf).ok_or(DnsError::BadResponse)?;
+        Ok(Srv { priority: srv.priority, weight: srv.weight, port: srv.port, target })
+    }).collect()
+}
+
+/// Returns the canonical name (the CNAME chain tail) and every address of `family` in the reply
+pub fn parse_addrinfo(buf: &[u8], family: Family) -> Result<(Option<String>, Vec<IpAddr>), DnsError> {
+    let frame = DnsFrame::parse(&mut Cursor::new(buf)).ok_or(DnsError::BadResponse)?;
+    let mut addrs: Vec<IpAddr> = vec![];
+    for answer in frame.answers.iter().filter(|answer| frame.owns_answer(answer, buf)) {
+        let addr = match (answer.rtype(), family) {
+            (RecordType::A, Family::Ipv4 | Family::Unspec) => {
+                let octets: [u8; 4] = answer.data.as_slice().try_into().map_err(|_| DnsError::BadResponse)?;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            (RecordType::Aaaa, Family::Ipv6 | Family::Unspec) => {
+                let octets: [u8; 16] = answer.data.as_slice().try_into().map_err(|_| DnsError::BadResponse)?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err(DnsError::NoData);
+    }
+    Ok((frame.canonical_name(buf), addrs))
+}
+
+/// Pairs each nameserver of an NS reply (answers or referral authorities) with its glue addresses
+pub fn parse_ns_glue(buf: &[u8]) -> Result<Vec<(String, IpAddr)>, DnsError> {
+    let frame = DnsFrame::parse(&mut Cursor::new(buf)).ok_or(DnsError::BadResponse)?;
+    let nameservers = frame.answers.iter().chain(&frame.authorities)
+        .filter(|record| record.rtype() == RecordType::Ns)
+        .map(|record| DnsLabel::parse(&mut Cursor::new(&record.data))?.build_string(buf))
+        .collect::<Option<Vec<String>>>()
+        .ok_or(DnsError::BadResponse)?;
+    if nameservers.is_empty() {
+        return Err(DnsError::NoData);
+    }
+    let mut pairs = vec![];
+    for additional in &frame.additionals {
+        let addr = match (additional.rtype(), additional.data.len()) {
+            (RecordType::A, 4) => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&additional.data[..]).unwrap())),
+            (RecordType::Aaaa, 16) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&additional.data[..]).unwrap())),
+            _ => continue,
+        };
+        let owner = additional.name.build_string(buf).ok_or(DnsError::BadResponse)?;
+        if let Some(ns) = nameservers.iter().find(|ns| ns.eq_ignore_ascii_case(&owner)) {
+            pairs.push((ns.clone(), addr));
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_MX: &[u8] = b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x07example\x03com\x00\x00\x0f\x00\x01";
+
+    #[test]
+    fn test_parse_mx() {
+        let buf: Vec<u8> = [
+            HEADER_MX,
+            b"\xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x09\x00\x0a\x04mail\xc0\x0c",
+            b"\xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x0a\x00\x14\x05mail2\xc0\x0c",
+        ].concat();
+        assert_eq!(parse_mx(&buf), Ok(vec![
+            Mx { priority: 10, host: "mail.example.com".to_string() },
+            Mx { priority: 20, host: "mail2.example.com".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_mx_skips_interleaved_opt() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x07example\x03com\x00\x00\x0f\x00\x01"[..],
+            b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00",
+            b"\xc0\x0c\x00\x0f\x00\x01\x00\x00\x01\x2c\x00\x09\x00\x0a\x04mail\xc0\x0c",
+        ].concat();
+        assert_eq!(parse_mx(&buf), Ok(vec![Mx { priority: 10, host: "mail.example.com".to_string() }]));
+    }
+
+    #[test]
+    fn test_parse_txt() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x10\x00\x01"[..],
+            b"\xc0\x0c\x00\x10\x00\x01\x00\x00\x01\x2c\x00\x0c\x0bv=spf1 -all",
+        ].concat();
+        assert_eq!(parse_txt(&buf), Ok(vec![Txt { txt: "v=spf1 -all".to_string() }]));
+    }
+
+    #[test]
+    fn test_parse_srv() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x04_sip\x04_udp\x07example\x03com\x00\x00\x21\x00\x01"[..],
+            b"\xc0\x0c\x00\x21\x00\x01\x00\x00\x01\x2c\x00\x0c\x00\x0a\x00\x05\x13\xc4\x03sip\xc0\x16",
+        ].concat();
+        assert_eq!(parse_srv(&buf), Ok(vec![Srv { priority: 10, weight: 5, port: 5060, target: "sip.example.com".to_string() }]));
+    }
+
+    #[test]
+    fn test_answer_as_soa() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x06\x00\x01"[..],
+            b"\xc0\x0c\x00\x06\x00\x01\x00\x00\x0e\x10\x00\x2c",
+            b"\x02ns\x05icann\x03org\x00\x03noc\x03dns\xc0\x2c",
+            b"\x78\xa5\x07\xf9\x00\x00\x1c\x20\x00\x00\x0e\x10\x00\x12\x75\x00\x00\x00\x0e\x10",
+        ].concat();
+        let frame = DnsFrame::parse(&mut Cursor::new(&buf)).unwrap();
+        let expected = Soa {
+            mname: "ns.icann.org".to_string(),
+            rname: "noc.dns.icann.org".to_string(),
+            serial: 2024081401,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 3600,
+        };
+        assert_eq!(frame.answers[0].as_soa(&buf), Some(expected));
+    }
+
+    #[test]
+    fn test_parse_addrinfo_cname_and_a() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x06\x03cdn\xc0\x10", // www.example.com -> cdn.example.com
+            b"\xc0\x2d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x2d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+        ].concat();
+        let expected = (Some("cdn.example.com".to_string()), vec!["1.2.3.4".parse().unwrap(), "5.6.7.8".parse().unwrap()]);
+        assert_eq!(parse_addrinfo(&buf, Family::Ipv4), Ok(expected));
+        assert_eq!(parse_addrinfo(&buf, Family::Ipv6), Err(DnsError::NoData));
+    }
+
+    #[test]
+    fn test_parse_addrinfo_aaaa_only() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x1c\x00\x01"[..],
+            b"\xc0\x0c\x00\x1c\x00\x01\x00\x00\x01\x2c\x00\x10\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01",
+        ].concat();
+        let expected = (Some("example.com".to_string()), vec!["2001:db8::1".parse().unwrap()]);
+        assert_eq!(parse_addrinfo(&buf, Family::Unspec), Ok(expected.clone()));
+        assert_eq!(parse_addrinfo(&buf, Family::Ipv6), Ok(expected));
+        assert_eq!(parse_addrinfo(&buf, Family::Ipv4), Err(DnsError::NoData));
+    }
+
+    #[test]
+    fn test_parse_root_priming_glue() {
+        let buf: Vec<u8> = [
+            &b"\x00\x01\x84\x00\x00\x01\x00\x02\x00\x00\x00\x03\x00\x00\x02\x00\x01"[..],
+            b"\x00\x00\x02\x00\x01\x00\x07\xe9\x00\x00\x14\x01a\x0croot-servers\x03net\x00", // . NS a.root-servers.net
+            b"\x00\x00\x02\x00\x01\x00\x07\xe9\x00\x00\x04\x01b\xc0\x1e", // . NS b.root-servers.net
+            b"\xc0\x1c\x00\x01\x00\x01\x00\x36\xee\x80\x00\x04\xc6\x29\x00\x04",
+            b"\xc0\x1c\x00\x1c\x00\x01\x00\x36\xee\x80\x00\x10\x20\x01\x05\x03\xba\x3e\x00\x00\x00\x00\x00\x00\x00\x02\x00\x30",
+            b"\xc0\x3b\x00\x01\x00\x01\x00\x36\xee\x80\x00\x04\xaa\xf7\xaa\x02",
+        ].concat();
+        assert_eq!(parse_ns_glue(&buf), Ok(vec![
+            ("a.root-servers.net".to_string(), "198.41.0.4".parse().unwrap()),
+            ("a.root-servers.net".to_string(), "2001:503:ba3e::2:30".parse().unwrap()),
+            ("b.root-servers.net".to_string(), "170.247.170.2".parse().unwrap()),
+        ]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_mx(b"\x12\x34"), Err(DnsError::BadResponse));
+        let buf: Vec<u8> = b"\x12\x34\x81\x80\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x0f\x00\x01".to_vec();
+        assert_eq!(parse_mx(&buf), Err(DnsError::NoData));
+    }
+}
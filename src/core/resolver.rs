@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::os::fd::AsRawFd;
+
+use crate::core::ares::{ Ares, Family, Status };
+use crate::core::error::{ DnsError, Rcode };
+use crate::core::packets::DnsFrame;
+use crate::core::replies::parse_addrinfo;
+use crate::core::sysconfig::SysConfig;
+
+/// Queries kept in flight at once by `resolve_all`, unless configured otherwise
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+/// Blocking resolver driving an `Ares` channel by itself
+pub struct Resolver {
+    pub ares: Ares<String>,
+    pub max_in_flight: usize,
+}
+
+impl Resolver {
+    pub fn new(config: SysConfig) -> Self {
+        Resolver { ares: Ares::new(config), max_in_flight: DEFAULT_MAX_IN_FLIGHT }
+    }
+    pub fn from_sysconfig() -> Self {
+        Resolver { ares: Ares::from_sysconfig(), max_in_flight: DEFAULT_MAX_IN_FLIGHT }
+    }
+    /// Pipelines lookups of all `names` through the channel, yielding results in completion order
+    pub fn resolve_all(&mut self, names: &[&str], family: Family) -> ResolveAll<'_> {
+        let queued = names.iter().map(|name| name.to_string()).collect();
+        ResolveAll { resolver: self, family, queued, ready: VecDeque::new() }
+    }
+}
+
+pub struct ResolveAll<'a> {
+    resolver: &'a mut Resolver,
+    family: Family,
+    queued: VecDeque<String>,
+    ready: VecDeque<(String, Result<Vec<IpAddr>, DnsError>)>,
+}
+
+impl ResolveAll<'_> {
+    fn submit(&mut self) {
+        let ares = &mut self.resolver.ares;
+        while ares.tasks.len() < self.resolver.max_in_flight.max(1) {
+            let Some(name) = self.queued.pop_front() else { break };
+            if let Some(addrs) = ares.static_host(&name, self.family) {
+                self.ready.push_back((name, Ok(addrs)));
+                continue;
+            }
+            if let Err(err) = ares.gethostbyname(&name, self.family, name.clone()) {
+                self.ready.push_back((name, Err(err.into())));
+            }
+        }
+    }
+    /// Waits for socket readiness once and advances every task that can make progress
+    fn step(&mut self) {
+        let ares = &mut self.resolver.ares;
+        let mut pollfds: Vec<libc::pollfd> = ares.tasks.iter().map(|task| libc::pollfd {
+            fd: task.sock.as_raw_fd(),
+            events: if task.status == Status::Writing { libc::POLLOUT } else { libc::POLLIN },
+            revents: 0,
+        }).collect();
+        let timeout_ms = ares.max_wait_time().unwrap_or_default().as_millis().min(i32::MAX as u128) as i32;
+        unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms) };
+
+        ares.retry_expired();
+        let mut tasks = std::mem::take(&mut ares.tasks);
+        for (task, pollfd) in tasks.iter_mut().zip(&pollfds) {
+            if task.status == Status::Writing && pollfd.revents & libc::POLLOUT != 0 {
+                if let Err(err) = ares.write_impl(task) {
+                    self.ready.push_back((task.userdata.clone(), Err(err)));
+                }
+            } else if task.status == Status::Reading && (!task.grouped || task.tcp.is_some()) && pollfd.revents & libc::POLLIN != 0 {
+                match ares.read_impl(task) {
+                    Some((buf, frame)) => self.ready.push_back((task.userdata.clone(), addrs_of(&buf, &frame, self.family))),
+                    // Unparseable reply, as opposed to a re-armed retry
+                    None if task.status == Status::Completed => self.ready.push_back((task.userdata.clone(), Err(DnsError::BadResponse))),
+                    None => {}
+                }
+            }
+        }
+        // A read of a shared socket of the receive group may get any task's reply; the sockets don't block
+        for (idx, buf, frame) in ares.drain_receive_group(&mut tasks, |_sock| true) {
+            self.ready.push_back((tasks[idx].userdata.clone(), addrs_of(&buf, &frame, self.family)));
+        }
+        for task in tasks.iter_mut().filter(|task| task.is_expired() && !task.is_done()) {
+            task.status = Status::Completed;
+            self.ready.push_back((task.userdata.clone(), Err(DnsError::Timeout)));
+        }
+        ares.tasks = tasks;
+        ares.remove_completed();
+    }
+}
+
+fn addrs_of(buf: &[u8], frame: &DnsFrame, family: Family) -> Result<Vec<IpAddr>, DnsError> {
+    match Rcode::from_flags(frame.flags) {
+        Some(rcode) => Err(rcode.into()),
+        None => parse_addrinfo(buf, family).map(|(_cname, addrs)| addrs),
+    }
+}
+
+impl Iterator for ResolveAll<'_> {
+    type Item = (String, Result<Vec<IpAddr>, DnsError>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.submit();
+            if let Some(result) = self.ready.pop_front() {
+                return Some(result);
+            }
+            if self.resolver.ares.tasks.is_empty() {
+                return None;
+            }
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::*;
+
+    #[test]
+    fn test_resolve_all_fifty_names() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut resolver = Resolver::new(server.sysconfig());
+        resolver.max_in_flight = 16;
+        let names: Vec<String> = (0..50).map(|i| format!("host{}.example.test", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let mut resolved: Vec<String> = vec![];
+        for (name, result) in resolver.resolve_all(&names, Family::Ipv4) {
+            assert_eq!(result, Ok(vec!["1.2.3.4".parse().unwrap()]));
+            resolved.push(name);
+        }
+        resolved.sort();
+        let mut expected: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        expected.sort();
+        assert_eq!(resolved, expected);
+        assert!(resolver.ares.tasks.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_all_through_receive_group() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut resolver = Resolver::new(server.sysconfig());
+        resolver.ares.udp_receive_sockets = 2;
+        let names: Vec<String> = (0..10).map(|i| format!("host{}.example.test", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let results: Vec<_> = resolver.resolve_all(&names, Family::Ipv4).collect();
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|(_name, result)| result == &Ok(vec!["1.2.3.4".parse().unwrap()])));
+    }
+
+    #[test]
+    fn test_resolve_all_reports_formerr_as_protocol_error() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8181, &[])));
+        let mut resolver = Resolver::new(server.sysconfig());
+        let results: Vec<_> = resolver.resolve_all(&["example.test"], Family::Ipv4).collect();
+        assert_eq!(results, vec![("example.test".to_string(), Err(DnsError::Protocol(Rcode::FormErr)))]);
+    }
+
+    #[test]
+    fn test_resolve_all_tells_nxdomain_from_nodata() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8183, &[])));
+        let mut resolver = Resolver::new(server.sysconfig());
+        let results: Vec<_> = resolver.resolve_all(&["missing.test"], Family::Ipv4).collect();
+        assert_eq!(results, vec![("missing.test".to_string(), Err(DnsError::NotFound))]);
+
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[])));
+        let mut resolver = Resolver::new(server.sysconfig());
+        let results: Vec<_> = resolver.resolve_all(&["v6only.test"], Family::Ipv4).collect();
+        assert_eq!(results, vec![("v6only.test".to_string(), Err(DnsError::NoData))]);
+    }
+
+    #[test]
+    fn test_resolve_all_answers_static_hosts_locally() {
+        let mut resolver = Resolver::new(SysConfig::default());
+        resolver.ares.add_static_host("Example.Test.", vec!["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()]);
+        let results: Vec<_> = resolver.resolve_all(&["example.test"], Family::Ipv4).collect();
+        assert_eq!(results, vec![("example.test".to_string(), Ok(vec!["10.0.0.1".parse().unwrap()]))]);
+        let results: Vec<_> = resolver.resolve_all(&["example.test"], Family::Unspec).collect();
+        assert_eq!(results, vec![("example.test".to_string(), Ok(vec!["fd00::1".parse().unwrap()]))]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+use std::net::UdpSocket;
+use std::os::fd::{ AsRawFd, FromRawFd };
+
+/// Binds `count` UDP sockets sharing one local port (`port`, or an ephemeral one for 0) via SO_REUSEPORT,
+/// so that the kernel load-balances incoming replies across them
+pub fn bind_group(count: usize, mut port: u16) -> io::Result<Vec<UdpSocket>> {
+    let mut group: Vec<UdpSocket> = vec![];
+    for _ in 0..count {
+        let sock = bind_reuseport(port)?;
+        port = sock.local_addr()?.port();
+        group.push(sock);
+    }
+    Ok(group)
+}
+
+fn bind_reuseport(port: u16) -> io::Result<UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { UdpSocket::from_raw_fd(fd) }; // owns fd from now on
+
+    let one: libc::c_int = 1;
+    let optval = &one as *const libc::c_int as *const libc::c_void;
+    let optlen = std::mem::size_of_val(&one) as libc::socklen_t;
+    if unsafe { libc::setsockopt(sock.as_raw_fd(), libc::SOL_SOCKET, libc::SO_REUSEPORT, optval, optlen) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = port.to_be();
+    addr.sin_addr = libc::in_addr { s_addr: libc::INADDR_ANY };
+    let addrptr = &addr as *const libc::sockaddr_in as *const libc::sockaddr;
+    let addrlen = std::mem::size_of_val(&addr) as libc::socklen_t;
+    if unsafe { libc::bind(sock.as_raw_fd(), addrptr, addrlen) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    sock.set_nonblocking(true)?;
+    Ok(sock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_bind_two_reuseport_sockets() {
+        let group = bind_group(2, 0).unwrap();
+        assert_eq!(group.len(), 2);
+        let port = group[0].local_addr().unwrap().port();
+        assert_ne!(port, 0);
+        assert_eq!(group[1].local_addr().unwrap().port(), port);
+        assert_ne!(group[0].as_raw_fd(), group[1].as_raw_fd());
+
+        let sender = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        sender.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let mut buf = [0u8; 16];
+        let received: Vec<_> = group.iter().filter_map(|sock| sock.recv_from(&mut buf).ok()).collect();
+        assert_eq!(received.len(), 1);
+    }
+}
@@ -0,0 +1,43 @@
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr };
+
+/// `d.c.b.a.in-addr.arpa` name queried for PTR records of `addr`
+pub fn reverse_name_v4(addr: &Ipv4Addr) -> String {
+    let [a, b, c, d] = addr.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+}
+
+/// Nibble-format reverse name, under `ip6.arpa` or, with `dotint`, the legacy `ip6.int`
+pub fn reverse_name_v6(addr: &Ipv6Addr, dotint: bool) -> String {
+    let mut name = String::with_capacity(72);
+    for byte in addr.octets().iter().rev() {
+        name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+    }
+    name.push_str(if dotint { "ip6.int" } else { "ip6.arpa" });
+    name
+}
+
+pub fn reverse_name(addr: &IpAddr, dotint: bool) -> String {
+    match addr {
+        IpAddr::V4(v4) => reverse_name_v4(v4),
+        IpAddr::V6(v6) => reverse_name_v6(v6, dotint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_name_v4() {
+        assert_eq!(reverse_name_v4(&"192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+    }
+
+    #[test]
+    fn test_reverse_name_v6_nibbles() {
+        let addr: Ipv6Addr = "2001:db8::567:89ab".parse().unwrap();
+        let name = reverse_name_v6(&addr, false);
+        assert_eq!(name, "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+        assert_eq!(name.split('.').count(), 32 + 2);
+        assert_eq!(reverse_name_v6(&addr, true), name.replace("ip6.arpa", "ip6.int"));
+    }
+}
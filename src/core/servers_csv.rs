@@ -1,29 +1,46 @@
 use std::io::Read;
 use std::net::IpAddr;
-use crate::core::sysconfig::parse_ns_addr;
+use crate::core::sysconfig::{ parse_ns_addr, format_ns_addr, dedup_nameservers, NsAddrError };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvError {
+    /// Input couldn't be read or isn't UTF-8
+    Unreadable,
+    /// `item` isn't a usable server address
+    BadServer { item: String, error: NsAddrError },
+}
 
 /// Parse from any `Read` (e.g., `Cursor<&[u8]>`).
-/// Accepts CSV and/or newline separators.
+/// Accepts `,`, `;` and/or newline separators; `#` starts a comment running to the end of the line.
 /// Rules (std-only):
 /// - `IP`           => port defaults to 53
 /// - `IP:port`      => OK for IPv4
 /// - `[IPv6]:port`  => OK for IPv6 with port
 /// - `IPv6`         => OK (no port) -> defaults to 53
-pub fn parse_from_reader<R: Read>(mut r: R) -> Option<Vec<(IpAddr, Option<u16>)>> {
+pub fn parse_from_reader<R: Read>(mut r: R) -> Result<Vec<(IpAddr, Option<u16>)>, CsvError> {
     let mut buf = Vec::new();
-    r.read_to_end(&mut buf).ok()?;
-    let s = String::from_utf8(buf).ok()?;
+    r.read_to_end(&mut buf).map_err(|_| CsvError::Unreadable)?;
+    let s = String::from_utf8(buf).map_err(|_| CsvError::Unreadable)?;
     parse_servers_str(&s)
 }
 
-pub fn parse_servers_str(s: &str) -> Option<Vec<(IpAddr, Option<u16>)>> {
+pub fn parse_servers_str(s: &str) -> Result<Vec<(IpAddr, Option<u16>)>, CsvError> {
     let mut out = Vec::new();
 
-    for item in s.split([',', '\n']).filter(|t| !t.is_empty()) {
-        out.push(parse_ns_addr(item.trim())?);
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for item in line.split([',', ';']).map(str::trim).filter(|t| !t.is_empty()) {
+            let server = parse_ns_addr(item).map_err(|error| CsvError::BadServer { item: item.to_string(), error })?;
+            out.push(server);
+        }
     }
 
-    Some(out)
+    dedup_nameservers(&mut out);
+    Ok(out)
+}
+
+pub fn to_csv(servers: &[(IpAddr, Option<u16>)]) -> String {
+    servers.iter().map(format_ns_addr).collect::<Vec<_>>().join(",")
 }
 
 #[cfg(test)]
@@ -91,6 +108,17 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_duplicates_removed_in_order() {
+        let input = Cursor::new("8.8.8.8,1.1.1.1,8.8.8.8,1.1.1.1:5353,1.1.1.1".as_bytes());
+        let out = parse_from_reader(input).unwrap();
+        assert_eq!(out, vec![
+            (addr("8.8.8.8"), None),
+            (addr("1.1.1.1"), None),
+            (addr("1.1.1.1"), Some(5353)),
+        ]);
+    }
+
     #[test]
     fn test_mixed_separators_and_whitespace() {
         let input = Cursor::new(
@@ -107,4 +135,63 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_semicolon_separators() {
+        let input = Cursor::new("8.8.8.8;[::1]:5353; 1.1.1.1:5300".as_bytes());
+        let out = parse_from_reader(input).unwrap();
+        assert_eq!(out, vec![
+            (addr("8.8.8.8"), None),
+            (addr("::1"), Some(5353)),
+            (addr("1.1.1.1"), Some(5300)),
+        ]);
+    }
+
+    #[test]
+    fn test_comments_ignored() {
+        let input = Cursor::new("# primary resolvers\n8.8.8.8,1.1.1.1 # public\n  # 9.9.9.9\n[::1]:5353".as_bytes());
+        let out = parse_from_reader(input).unwrap();
+        assert_eq!(out, vec![
+            (addr("8.8.8.8"), None),
+            (addr("1.1.1.1"), None),
+            (addr("::1"), Some(5353)),
+        ]);
+    }
+
+    fn server_error(input: &str) -> Option<NsAddrError> {
+        match parse_servers_str(input) {
+            Err(CsvError::BadServer { error, .. }) => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_malformed_brackets_rejected() {
+        assert_eq!(server_error("[::1"), Some(NsAddrError::UnclosedBracket));
+        assert_eq!(server_error("[::1]:"), Some(NsAddrError::BadPort));
+        assert_eq!(server_error("[::1]:99999"), Some(NsAddrError::BadPort));
+        assert_eq!(server_error("[::1]x"), Some(NsAddrError::UnclosedBracket));
+        assert_eq!(parse_servers_str("[::1]"), Ok(vec![(addr("::1"), None)]));
+    }
+
+    #[test]
+    fn test_hostnames_rejected_as_not_an_ip() {
+        assert_eq!(server_error("dns.example.com"), Some(NsAddrError::NotAnIp));
+        assert_eq!(server_error("dns.example.com."), Some(NsAddrError::NotAnIp));
+        assert_eq!(server_error("dns.example.com:53"), Some(NsAddrError::NotAnIp));
+        assert_eq!(server_error("8.8.8.8:dns"), Some(NsAddrError::BadPort));
+        assert_eq!(parse_servers_str("dns.example.com"), Err(CsvError::BadServer {
+            item: "dns.example.com".to_string(),
+            error: NsAddrError::NotAnIp,
+        }));
+    }
+
+    #[test]
+    fn test_trailing_whitespace_and_tabs() {
+        assert_eq!(parse_servers_str("8.8.8.8 "), Ok(vec![(addr("8.8.8.8"), None)]));
+        assert_eq!(parse_servers_str("\t1.1.1.1:5353\t,\t8.8.8.8"), Ok(vec![
+            (addr("1.1.1.1"), Some(5353)),
+            (addr("8.8.8.8"), None),
+        ]));
+    }
 }
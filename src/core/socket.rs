@@ -0,0 +1,230 @@
+use std::ffi::{ c_int, c_void };
+use std::io::{ self, Read, Write };
+use std::mem::{ size_of, zeroed };
+use std::net::{ Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream, UdpSocket };
+use std::os::fd::{ AsRawFd, FromRawFd, RawFd };
+
+/// Caller-provided socket primitives, laid out like c-ares' `struct ares_socket_functions`.
+/// Missing entries fall back to the matching libc call
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SocketFunctions {
+    pub asocket: Option<unsafe extern "C" fn(c_int, c_int, c_int, *mut c_void) -> c_int>,
+    pub aclose: Option<unsafe extern "C" fn(c_int, *mut c_void) -> c_int>,
+    pub aconnect: Option<unsafe extern "C" fn(c_int, *const libc::sockaddr, libc::socklen_t, *mut c_void) -> c_int>,
+    pub arecvfrom: Option<unsafe extern "C" fn(c_int, *mut c_void, libc::size_t, c_int, *mut libc::sockaddr, *mut libc::socklen_t, *mut c_void) -> libc::ssize_t>,
+    pub asendv: Option<unsafe extern "C" fn(c_int, *const libc::iovec, c_int, *mut c_void) -> libc::ssize_t>,
+}
+
+/// Socket functions along with the opaque pointer handed back to each of them
+#[derive(Clone, Copy)]
+pub struct SocketHooks {
+    pub funcs: SocketFunctions,
+    pub user_data: *mut c_void,
+}
+
+/// A task's socket: UDP, either owned by std or created and driven through `SocketHooks`,
+/// or a TCP connection to the server once a reply came back truncated
+pub enum Socket {
+    Std(UdpSocket),
+    Hooked { fd: RawFd, hooks: SocketHooks },
+    Tcp(TcpStream),
+}
+
+impl Socket {
+    /// Opens a datagram socket via `asocket`, for servers of the given address family
+    pub fn open(hooks: SocketHooks, domain: c_int) -> io::Result<Socket> {
+        let fd = match hooks.funcs.asocket {
+            Some(asocket) => unsafe { asocket(domain, libc::SOCK_DGRAM, 0, hooks.user_data) },
+            None => unsafe { libc::socket(domain, libc::SOCK_DGRAM, 0) },
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Socket::Hooked { fd, hooks })
+    }
+    /// Starts connecting to `addr` over TCP without waiting, the socket turning writable once connected
+    pub fn connect_tcp(addr: SocketAddr) -> io::Result<Socket> {
+        let domain = if addr.is_ipv6() { libc::AF_INET6 } else { libc::AF_INET };
+        let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = unsafe { TcpStream::from_raw_fd(fd) }; // owns fd from now on
+        stream.set_nonblocking(true)?;
+        let (storage, len) = to_sockaddr(addr);
+        let storage = &storage as *const libc::sockaddr_storage as *const libc::sockaddr;
+        if unsafe { libc::connect(fd, storage, len) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err);
+            }
+        }
+        Ok(Socket::Tcp(stream))
+    }
+    pub fn is_tcp(&self) -> bool {
+        matches!(self, Socket::Tcp(_))
+    }
+    /// Sends a datagram to `addr`, or writes to the stream of a TCP socket, which is connected already
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let (fd, hooks) = match self {
+            Socket::Std(sock) => return sock.send_to(buf, addr),
+            Socket::Tcp(stream) => return (&*stream).write(buf),
+            Socket::Hooked { fd, hooks } => (*fd, hooks),
+        };
+        // c-ares connects its UDP sockets, so hooked ones are connected before each send
+        let (storage, len) = to_sockaddr(addr);
+        let storage = &storage as *const libc::sockaddr_storage as *const libc::sockaddr;
+        let connected = match hooks.funcs.aconnect {
+            Some(aconnect) => unsafe { aconnect(fd, storage, len, hooks.user_data) },
+            None => unsafe { libc::connect(fd, storage, len) },
+        };
+        if connected < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let iov = libc::iovec { iov_base: buf.as_ptr() as *mut c_void, iov_len: buf.len() };
+        let sent = match hooks.funcs.asendv {
+            Some(asendv) => unsafe { asendv(fd, &iov, 1, hooks.user_data) },
+            None => unsafe { libc::writev(fd, &iov, 1) },
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+    /// Receives one datagram, or what's next in the stream of a TCP socket (0 bytes once closed).
+    /// The source is None for TCP and when the hooks don't report an IP address
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Option<SocketAddr>)> {
+        let (fd, hooks) = match self {
+            Socket::Std(sock) => return sock.recv_from(buf).map(|(len, src)| (len, Some(src))),
+            Socket::Tcp(stream) => return (&*stream).read(buf).map(|len| (len, None)),
+            Socket::Hooked { fd, hooks } => (*fd, hooks),
+        };
+        let mut storage: libc::sockaddr_storage = unsafe { zeroed() };
+        let mut storage_len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let from = &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr;
+        let buf_ptr = buf.as_mut_ptr() as *mut c_void;
+        let received = match hooks.funcs.arecvfrom {
+            Some(arecvfrom) => unsafe { arecvfrom(fd, buf_ptr, buf.len(), 0, from, &mut storage_len, hooks.user_data) },
+            None => unsafe { libc::recvfrom(fd, buf_ptr, buf.len(), 0, from, &mut storage_len) },
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((received as usize, from_sockaddr(&storage, storage_len)))
+    }
+}
+
+impl From<UdpSocket> for Socket {
+    fn from(sock: UdpSocket) -> Socket {
+        Socket::Std(sock)
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Std(sock) => sock.as_raw_fd(),
+            Socket::Hooked { fd, .. } => *fd,
+            Socket::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Socket::Hooked { fd, hooks } = self {
+            match hooks.funcs.aclose {
+                Some(aclose) => unsafe { aclose(*fd, hooks.user_data) },
+                None => unsafe { libc::close(*fd) },
+            };
+        }
+    }
+}
+
+pub fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = libc::in_addr { s_addr: u32::from(*addr.ip()).to_be() };
+            size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+fn from_sockaddr(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> Option<SocketAddr> {
+    match storage.ss_family as c_int {
+        libc::AF_INET if len as usize >= size_of::<libc::sockaddr_in>() => {
+            let sin = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 if len as usize >= size_of::<libc::sockaddr_in6>() => {
+            let sin6 = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sockaddr_round_trip() {
+        for addr in ["192.0.2.1:53", "[fe80::1%3]:5353"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let (storage, len) = to_sockaddr(addr);
+            assert_eq!(from_sockaddr(&storage, len), Some(addr));
+        }
+    }
+
+    #[test]
+    fn test_default_hooks_use_libc() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let funcs = SocketFunctions { asocket: None, aclose: None, aconnect: None, arecvfrom: None, asendv: None };
+        let hooks = SocketHooks { funcs, user_data: std::ptr::null_mut() };
+        let sock = Socket::open(hooks, libc::AF_INET).unwrap();
+        assert_eq!(sock.send_to(b"ping", server.local_addr().unwrap()).unwrap(), 4);
+        let mut buf = [0u8; 16];
+        let (len, src) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[0..len], b"ping");
+        server.send_to(b"pong", src).unwrap();
+        let (len, from) = sock.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[0..len], b"pong");
+        assert_eq!(from, Some(server.local_addr().unwrap()));
+    }
+
+    #[test]
+    fn test_connect_tcp_without_waiting() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let sock = Socket::connect_tcp(listener.local_addr().unwrap()).unwrap();
+        assert!(sock.is_tcp());
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut pollfd = libc::pollfd { fd: sock.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+        assert_eq!(sock.send_to(b"ping", listener.local_addr().unwrap()).unwrap(), 4);
+        let mut buf = [0u8; 16];
+        peer.read_exact(&mut buf[0..4]).unwrap();
+        assert_eq!(&buf[0..4], b"ping");
+        drop(peer);
+        let mut pollfd = libc::pollfd { fd: sock.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+        assert_eq!(sock.recv_from(&mut buf).unwrap(), (0, None));
+    }
+}
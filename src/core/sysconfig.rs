@@ -1,4 +1,7 @@
-use std::net::{IpAddr, SocketAddr};
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -18,11 +21,14 @@ pub struct SysConfigOptions {
     pub rotate: bool,
     pub inet6: bool,
     pub edns0: bool,
+    pub no_aaaa: bool,
+    /// Reverse IPv6 lookups under the legacy ip6.int instead of ip6.arpa
+    pub ip6_dotint: bool,
 }
 
 impl Default for SysConfigOptions {
     fn default() -> Self {
-        SysConfigOptions { ndots: 0, attempts: 4, timeout_secs: 5, use_vc: false, rotate: false, inet6: false, edns0: false }
+        SysConfigOptions { ndots: 1, attempts: 4, timeout_secs: 5, use_vc: false, rotate: false, inet6: false, edns0: false, no_aaaa: false, ip6_dotint: false }
     }
 }
 
@@ -49,9 +55,8 @@ impl FromStr for SysConfig {
 
             match keyword {
                 "nameserver" => {
-                    for tok in rest {
-                        conf.nameservers.push(parse_ns_addr(tok).unwrap());
-                    }
+                    // Like c-ares, unusable entries are skipped rather than failing the whole file
+                    conf.nameservers.extend(rest.iter().filter_map(|tok| parse_ns_addr(tok).ok()));
                 }
                 "domain" => conf.domain = Some(arg1.to_string()),
                 "search" => conf.search.extend(rest.iter().map(|s| s.to_string())),
@@ -64,23 +69,113 @@ impl FromStr for SysConfig {
     }
 }
 
+impl SysConfig {
+    /// Names to query for `name`, in order, applying the search list and the `ndots` rule
+    pub fn search_candidates(&self, name: &str) -> Vec<String> {
+        if let Some(fqdn) = name.strip_suffix('.') {
+            return vec![fqdn.to_string()];
+        }
+        let suffixes = match (&self.search[..], &self.domain) {
+            ([], Some(domain)) => std::slice::from_ref(domain),
+            (search, _) => search,
+        };
+        let suffixed = suffixes.iter().map(|suffix| format!("{}.{}", name, suffix));
+        let dots = name.matches('.').count() as u32;
+        if dots >= self.options.ndots {
+            std::iter::once(name.to_string()).chain(suffixed).collect()
+        } else {
+            suffixed.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+/// Where and how the system resolver configuration gets loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigSource {
+    pub resolv_conf: PathBuf,
+    /// Use 127.0.0.1:53 when no nameserver is configured, rather than leaving the list empty
+    pub localhost_fallback: bool,
+    /// File listing systemd-resolved's upstream servers (see `SYSTEMD_RESOLVED_CONF`), whose nameservers replace
+    /// those of `resolv_conf` when it only lists the local stub
+    pub resolved_upstreams: Option<PathBuf>,
+}
+
+/// Where systemd-resolved writes the servers it forwards to, in resolv.conf format
+pub const SYSTEMD_RESOLVED_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+fn is_resolved_stub(ns: &(IpAddr, Option<u16>)) -> bool {
+    ns.0 == IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53)) || ns.0 == IpAddr::V4(Ipv4Addr::new(127, 0, 0, 54))
+}
+
+impl Default for ConfigSource {
+    fn default() -> Self {
+        ConfigSource { resolv_conf: PathBuf::from("/etc/resolv.conf"), localhost_fallback: false, resolved_upstreams: None }
+    }
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> SysConfig {
+        let try_resolv_conf = || std::fs::read_to_string(&self.resolv_conf).ok()?.parse::<SysConfig>().ok();
+        let mut conf = try_resolv_conf().unwrap_or_else(SysConfig::default);
+        let stub_only = !conf.nameservers.is_empty() && conf.nameservers.iter().all(is_resolved_stub);
+        if let (true, Some(path)) = (stub_only, &self.resolved_upstreams) {
+            // Search domains and options stay those of resolv_conf, only the servers are swapped
+            let upstreams = std::fs::read_to_string(path).ok().and_then(|s| s.parse::<SysConfig>().ok());
+            if let Some(upstreams) = upstreams.filter(|upstreams| !upstreams.nameservers.is_empty()) {
+                conf.nameservers = upstreams.nameservers;
+            }
+        }
+        if self.localhost_fallback && conf.nameservers.is_empty() {
+            conf.nameservers.push((IpAddr::V4(Ipv4Addr::LOCALHOST), None));
+        }
+        conf
+    }
+}
+
+/// System properties holding the nameservers on Android, which has no readable resolv.conf
+pub const ANDROID_DNS_PROPERTIES: [&str; 2] = ["net.dns1", "net.dns2"];
+
+/// Nameservers listed in the `ANDROID_DNS_PROPERTIES`, looked up through `get_property`.
+/// Unset and unparsable properties are skipped
+pub fn android_nameservers(get_property: impl Fn(&str) -> Option<String>) -> Vec<(IpAddr, Option<u16>)> {
+    ANDROID_DNS_PROPERTIES.iter()
+        .filter_map(|name| get_property(name))
+        .filter_map(|value| parse_ns_addr(value.trim()).ok())
+        .collect()
+}
+
+/// Reads an Android system property, None when it is unset or empty
+#[cfg(target_os = "android")]
+pub fn system_property(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    let mut value = [0 as libc::c_char; libc::PROP_VALUE_MAX as usize];
+    let len = unsafe { libc::__system_property_get(name.as_ptr(), value.as_mut_ptr()) };
+    (len > 0).then(|| unsafe { CStr::from_ptr(value.as_ptr()) }.to_string_lossy().into_owned())
+}
+
 fn strip_comment(line: &str) -> &str {
     let idx = line.find(|c| ";#".contains(c)).unwrap_or(line.len());
     line[..idx].trim()
 }
 
+/// Upper bound for `ndots`, larger values are clamped the way glibc does
+pub const MAX_NDOTS: u32 = 15;
+
 fn parse_options_into(opts: &mut SysConfigOptions, src: &str) -> Result<(), ParseError> {
     for token in src.split_whitespace() {
         let (key, val) = token.split_once(['=', ':']).unwrap_or((token, ""));
 
         match key {
-            "ndots" => opts.ndots = take_num_arg(key, val)?,
+            "ndots" => opts.ndots = take_num_arg::<u32>(key, val)?.min(MAX_NDOTS),
             "attempts" => opts.attempts = take_num_arg(key, val)?,
             "timeout" | "retrans" => opts.timeout_secs = take_num_arg(key, val)?,
             "use-vc" | "usevc" => opts.use_vc = true,
             "rotate" => opts.rotate = true,
             "inet6" => opts.inet6 = true,
             "edns0" => opts.edns0 = true,
+            "no-aaaa" => opts.no_aaaa = true,
+            "ip6-dotint" => opts.ip6_dotint = true,
+            "no-ip6-dotint" => opts.ip6_dotint = false,
             _ => {},
         }
     }
@@ -95,16 +190,118 @@ fn take_num_arg<T: FromStr>(keyword: &str, val: &str) -> Result<T, ParseError> {
     
 }
 
-pub fn parse_ns_addr(s: &str) -> Option<(IpAddr, Option<u16>)> {
-    if let Ok(sa) = SocketAddr::from_str(s) {
-        return Some((sa.ip(), Some(sa.port())));
+/// Drops repeated nameserver entries, keeping the first occurrence of each
+pub fn dedup_nameservers(nameservers: &mut Vec<(IpAddr, Option<u16>)>) {
+    let mut seen = HashSet::new();
+    nameservers.retain(|ns| seen.insert(*ns));
+}
+
+/// Why a nameserver address such as `[::1]:53` was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsAddrError {
+    /// `[` without its closing `]`, or garbage after it
+    UnclosedBracket,
+    /// Port is empty, not a number or above 65535
+    BadPort,
+    /// Not an IP address; server hostnames aren't resolved
+    NotAnIp,
+    /// Unknown interface or zone in `addr%scope`
+    BadScope,
+}
+
+/// `IP`, `IPv4:port`, `[IPv6]:port` and `IPv6%scope` (also bracketed), with `None` for a default port
+pub fn parse_ns_addr(s: &str) -> Result<(IpAddr, Option<u16>), NsAddrError> {
+    let (host, port) = match s.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']').ok_or(NsAddrError::UnclosedBracket)?;
+            let port = match rest {
+                "" => None,
+                _ => Some(parse_port(rest.strip_prefix(':').ok_or(NsAddrError::UnclosedBracket)?)?),
+            };
+            (host, port)
+        }
+        None => match s.rsplit_once(':') {
+            // One colon separates an IPv4 address from its port, more make an unbracketed IPv6 address
+            Some((host, port)) if !host.contains(':') => (host, Some(parse_port(port)?)),
+            _ => (s, None),
+        },
+    };
+    Ok((parse_ns_host(host)?, port))
+}
+
+fn parse_port(s: &str) -> Result<u16, NsAddrError> {
+    s.parse::<u16>().map_err(|_| NsAddrError::BadPort)
+}
+
+fn parse_ns_host(host: &str) -> Result<IpAddr, NsAddrError> {
+    let Some((addr, scope)) = host.split_once('%') else {
+        return IpAddr::from_str(host).map_err(|_| NsAddrError::NotAnIp);
+    };
+    let addr = Ipv6Addr::from_str(addr).map_err(|_| NsAddrError::NotAnIp)?;
+    let scope_id = scope.parse::<u32>().ok().or_else(|| interface_index(scope)).ok_or(NsAddrError::BadScope)?;
+    Ok(IpAddr::V6(embed_scope(addr, scope_id).ok_or(NsAddrError::BadScope)?))
+}
+
+pub fn format_ns_addr(ns: &(IpAddr, Option<u16>)) -> String {
+    let (ip, scope_id) = split_scope(ns.0);
+    let host = match scope_id {
+        0 => ip.to_string(),
+        _ => format!("{}%{}", ip, interface_name(scope_id).unwrap_or_else(|| scope_id.to_string())),
+    };
+    match (ip, ns.1) {
+        (_, None) => host,
+        (IpAddr::V4(_), Some(port)) => format!("{}:{}", host, port),
+        (IpAddr::V6(_), Some(port)) => format!("[{}]:{}", host, port),
     }
+}
+
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
 
-    if let Ok(ip) = IpAddr::from_str(s) {
-        return Some((ip, None))
+/// `IpAddr` has no room for an interface scope, so link-local nameservers carry it
+/// embedded in the second 16-bit word of the address, the way KAME-derived stacks do
+pub fn embed_scope(ip: Ipv6Addr, scope_id: u32) -> Option<Ipv6Addr> {
+    if !is_link_local(&ip) || ip.segments()[1] != 0 {
+        return None;
     }
+    let mut segments = ip.segments();
+    segments[1] = u16::try_from(scope_id).ok()?;
+    Some(Ipv6Addr::from(segments))
+}
 
-    None
+/// Reverse of `embed_scope`, giving back the plain address and its scope id (0 if unscoped)
+pub fn split_scope(ip: IpAddr) -> (IpAddr, u32) {
+    match ip {
+        IpAddr::V6(v6) if is_link_local(&v6) && v6.segments()[1] != 0 => {
+            let mut segments = v6.segments();
+            let scope_id = std::mem::take(&mut segments[1]) as u32;
+            (IpAddr::V6(Ipv6Addr::from(segments)), scope_id)
+        }
+        _ => (ip, 0),
+    }
+}
+
+pub fn ns_socket_addr(ip: IpAddr, port: u16) -> SocketAddr {
+    match split_scope(ip) {
+        (IpAddr::V6(v6), scope_id) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, scope_id)),
+        (v4, _) => SocketAddr::new(v4, port),
+    }
+}
+
+fn interface_index(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let ret = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if ret.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
 }
 
 #[cfg(test)]
@@ -150,6 +347,106 @@ mod tests {
         assert!(conf.options.edns0);
     }
 
+    #[test]
+    fn parse_no_aaaa_option() {
+        let conf: SysConfig = "nameserver 8.8.8.8\noptions no-aaaa\n".parse().unwrap();
+        assert!(conf.options.no_aaaa);
+        let conf: SysConfig = "nameserver 8.8.8.8\n".parse().unwrap();
+        assert!(!conf.options.no_aaaa);
+    }
+
+    #[test]
+    fn parse_ip6_dotint_option() {
+        let conf: SysConfig = "options ip6-dotint".parse().unwrap();
+        assert!(conf.options.ip6_dotint);
+        let conf: SysConfig = "options ip6-dotint no-ip6-dotint".parse().unwrap();
+        assert!(!conf.options.ip6_dotint);
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cares-rs-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn localhost_fallback_on_empty_resolv_conf() {
+        let path = temp_file("empty-resolv.conf", "# no servers\n");
+        let mut source = ConfigSource { resolv_conf: path.clone(), ..ConfigSource::default() };
+        assert!(source.load().nameservers.is_empty());
+        source.localhost_fallback = true;
+        assert_eq!(source.load().nameservers, vec![(IpAddr::V4(Ipv4Addr::LOCALHOST), None)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resolved_upstreams_replace_stub() {
+        let stub = temp_file("stub-resolv.conf", "nameserver 127.0.0.53\noptions edns0 trust-ad\nsearch lan\n");
+        let upstreams = temp_file("resolved-resolv.conf", "nameserver 192.0.2.1\nnameserver 2001:db8::1\nsearch lan\n");
+        let mut source = ConfigSource { resolv_conf: stub.clone(), ..ConfigSource::default() };
+        assert_eq!(source.load().nameservers, vec![("127.0.0.53".parse().unwrap(), None)]);
+
+        source.resolved_upstreams = Some(upstreams.clone());
+        let conf = source.load();
+        assert_eq!(conf.nameservers, vec![("192.0.2.1".parse().unwrap(), None), ("2001:db8::1".parse().unwrap(), None)]);
+        assert!(conf.options.edns0);
+
+        // Other servers next to the stub are used as they are
+        std::fs::write(&stub, "nameserver 127.0.0.53\nnameserver 198.51.100.1\n").unwrap();
+        assert_eq!(source.load().nameservers.len(), 2);
+        assert_eq!(source.load().nameservers[0].0, "127.0.0.53".parse::<IpAddr>().unwrap());
+        std::fs::remove_file(stub).unwrap();
+        std::fs::remove_file(upstreams).unwrap();
+    }
+
+    #[test]
+    fn android_properties_list_nameservers() {
+        let props = |name: &str| match name {
+            "net.dns1" => Some("192.0.2.1".to_string()),
+            "net.dns2" => Some("2001:db8::1\n".to_string()),
+            _ => None,
+        };
+        assert_eq!(android_nameservers(props), vec![("192.0.2.1".parse().unwrap(), None), ("2001:db8::1".parse().unwrap(), None)]);
+        let unset = |name: &str| (name == "net.dns2").then(String::new);
+        assert!(android_nameservers(unset).is_empty());
+    }
+
+    #[test]
+    fn search_candidates_order() {
+        let mut conf: SysConfig = "search corp.local example.org\noptions ndots:2".parse().unwrap();
+        assert_eq!(conf.search_candidates("host"), vec!["host.corp.local", "host.example.org", "host"]);
+        assert_eq!(conf.search_candidates("a.b.c"), vec!["a.b.c", "a.b.c.corp.local", "a.b.c.example.org"]);
+        assert_eq!(conf.search_candidates("host."), vec!["host"]);
+
+        conf.search.clear();
+        conf.domain = Some("example.com".to_string());
+        assert_eq!(conf.search_candidates("host"), vec!["host.example.com", "host"]);
+    }
+
+    #[test]
+    fn scoped_ipv6_nameserver_round_trip() {
+        let ns = parse_ns_addr("[fe80::1%lo]:53").unwrap();
+        assert_eq!(split_scope(ns.0), ("fe80::1".parse().unwrap(), 1));
+        assert_eq!(ns.1, Some(53));
+        assert_eq!(format_ns_addr(&ns), "[fe80::1%lo]:53");
+        assert_eq!(ns_socket_addr(ns.0, 53), "[fe80::1%1]:53".parse().unwrap());
+        assert_eq!(parse_ns_addr("fe80::1%1"), Ok((ns.0, None)));
+    }
+
+    #[test]
+    fn ndots_defaults_to_one() {
+        let conf: SysConfig = "search corp.local".parse().unwrap();
+        assert_eq!(conf.options.ndots, 1);
+        assert_eq!(conf.search_candidates("host"), vec!["host.corp.local", "host"]);
+        assert_eq!(conf.search_candidates("a.b"), vec!["a.b", "a.b.corp.local"]);
+    }
+
+    #[test]
+    fn ndots_clamped_to_max() {
+        let conf: SysConfig = "options ndots:99".parse().unwrap();
+        assert_eq!(conf.options.ndots, 15);
+    }
+
     #[test]
     fn missing_value_errors() {
         let input = "domain\nsearch\noptions ndots";
@@ -0,0 +1,208 @@
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::cell::Cell;
+use std::io::{ Cursor, Read, Write };
+use std::net::{ IpAddr, Ipv4Addr, UdpSocket, TcpListener, SocketAddr };
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread::JoinHandle;
+use std::time::Duration;
+use bytes::BufMut;
+
+use crate::core::packets::*;
+use crate::core::sysconfig::SysConfig;
+use crate::ffi::{ ares_fds, ares_init, ares_process, Channel };
+use crate::ffi::error::ARES_SUCCESS;
+
+/// Loopback DNS server answering each datagram through `handler`
+pub struct StubServer {
+    pub addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl StubServer {
+    pub fn spawn<F>(handler: F) -> StubServer
+    where F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static
+    {
+        StubServer::spawn_on(Ipv4Addr::LOCALHOST.into(), handler)
+    }
+    /// Like `spawn`, listening on `ip` (e.g. `::1`) instead of 127.0.0.1
+    pub fn spawn_on<F>(ip: IpAddr, mut handler: F) -> StubServer
+    where F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static
+    {
+        let sock = UdpSocket::bind((ip, 0)).unwrap();
+        sock.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let addr = sock.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 65_535];
+            while !stop_flag.load(Ordering::Relaxed) {
+                let Ok((len, src)) = sock.recv_from(&mut buf) else { continue };
+                if let Some(reply) = handler(&buf[0..len]) {
+                    let _ = sock.send_to(&reply, src);
+                }
+            }
+        });
+        StubServer { addr, stop, handles: vec![handle] }
+    }
+    /// Also answers DNS over TCP on the same port, through `handler`
+    pub fn with_tcp<F>(mut self, mut handler: F) -> StubServer
+    where F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static
+    {
+        let listener = TcpListener::bind(self.addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let stop_flag = self.stop.clone();
+        let handle = std::thread::spawn(move || {
+            while !stop_flag.load(Ordering::Relaxed) {
+                let Ok((mut stream, _src)) = listener.accept() else {
+                    std::thread::sleep(Duration::from_millis(5));
+                    continue;
+                };
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let mut len = [0u8; 2];
+                if stream.read_exact(&mut len).is_err() {
+                    continue;
+                }
+                let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+                if stream.read_exact(&mut query).is_err() {
+                    continue;
+                }
+                if let Some(reply) = handler(&query) {
+                    let _ = stream.write_all(&[&(reply.len() as u16).to_be_bytes()[..], &reply].concat());
+                }
+            }
+        });
+        self.handles.push(handle);
+        self
+    }
+    pub fn sysconfig(&self) -> SysConfig {
+        SysConfig { nameservers: vec![(self.addr.ip(), Some(self.addr.port()))], ..SysConfig::default() }
+    }
+}
+
+impl Drop for StubServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Builds a reply echoing the question of `query`, followed by raw answer records
+pub fn reply(query: &[u8], flags: u16, answers: &[&[u8]]) -> Vec<u8> {
+    let mut cur = Cursor::new(&query[12..]);
+    DnsQuery::parse(&mut cur).unwrap();
+    let question = &query[12..12 + cur.position() as usize];
+
+    let mut out: Vec<u8> = vec![];
+    DnsHeader {
+        transaction_id: u16::from_be_bytes([query[0], query[1]]),
+        flags,
+        qdcount: 1,
+        ancount: answers.len() as u16,
+        nscount: 0,
+        arcount: 0,
+    }.write(&mut out);
+    out.put_slice(question);
+    for answer in answers {
+        out.put_slice(answer);
+    }
+    out
+}
+
+/// An A record for the question name, pointing to 1.2.3.4
+pub const A_ANSWER: &[u8] = b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04";
+
+/// A reply for `example` with two `qtype` records, each holding `rdata_prefix` (e.g. an MX preference)
+/// and a name. The second name points past the end of the packet, so only the first one parses
+pub fn dangling_second_name_reply(qtype: u16, rdata_prefix: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    DnsHeader { transaction_id: 0x1234, flags: 0x8180, qdcount: 1, ancount: 2, nscount: 0, arcount: 0 }.write(&mut out);
+    out.put_slice(b"\x07example\x00");
+    out.put_u16(qtype);
+    out.put_u16(1);
+    for pointer in [0x0c, 0xff] {
+        out.put_slice(b"\xc0\x0c");
+        out.put_u16(qtype);
+        out.put_slice(b"\x00\x01\x00\x00\x01\x2c");
+        out.put_u16(rdata_prefix.len() as u16 + 7);
+        out.put_slice(rdata_prefix);
+        out.put_slice(b"\x04host\xc0");
+        out.put_u8(pointer);
+    }
+    out
+}
+
+pub fn wait_readable(sock: &impl AsRawFd) -> bool {
+    let mut pollfd = libc::pollfd { fd: sock.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut pollfd, 1, 1000) > 0 }
+}
+
+pub fn wait_writable(sock: &impl AsRawFd) -> bool {
+    let mut pollfd = libc::pollfd { fd: sock.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+    unsafe { libc::poll(&mut pollfd, 1, 1000) > 0 }
+}
+
+/// Channel from ares_init, sending its queries to `server`
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn channel_for(server: &StubServer) -> Channel {
+    let mut channel: Channel = std::ptr::null_mut();
+    unsafe {
+        assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+        (*channel).ares.config = server.sysconfig();
+    }
+    channel
+}
+
+/// One ares_process pass over every fd ares_fds reports, as if they all were ready
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn fds_and_process(channel: Channel) {
+    unsafe {
+        let mut read_fds: libc::fd_set = std::mem::zeroed();
+        let mut write_fds: libc::fd_set = std::mem::zeroed();
+        ares_fds(channel, &mut read_fds, &mut write_fds);
+        ares_process(channel, &mut read_fds, &mut write_fds);
+    }
+}
+
+/// One round trip on `channel`: sends what's queued, waits for every task's reply and reads them
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn pump_once(channel: Channel) {
+    unsafe {
+        fds_and_process(channel);
+        for task in &(*channel).ares.tasks {
+            assert!(wait_readable(&task.sock));
+        }
+        fds_and_process(channel);
+    }
+}
+
+thread_local! {
+    static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
+}
+
+/// System allocator keeping a per-thread count of live allocations, for leak checks
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = LIVE_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE_ALLOCATIONS.try_with(|count| count.set(count.get() - 1));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations made and not yet freed by the current thread
+pub fn live_allocations() -> isize {
+    LIVE_ALLOCATIONS.with(Cell::get)
+}
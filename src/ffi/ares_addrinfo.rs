@@ -0,0 +1,464 @@
+#![allow(non_camel_case_types)]
+
+use std::cell::RefCell;
+use std::ffi::{ c_char, c_int, c_void, CStr, CString };
+use std::net::{ IpAddr, SocketAddr };
+use std::os::fd::AsRawFd;
+use std::rc::Rc;
+
+use crate::core::addrinfo::{ AddrInfo, Hints };
+use crate::core::ares::Family;
+use crate::core::error::Rcode;
+use crate::core::packets::{ CnameHop, DnsFrame, RecordType };
+use crate::core::socket::to_sockaddr;
+use crate::ffi::{ Callback, Channel, FFIData };
+use crate::ffi::clinkedlist::{ self, CLinkedList };
+use crate::ffi::error::*;
+use crate::cstr;
+
+/// Service names are rejected rather than looked up in the services database
+pub const ARES_AI_NUMERICSERV: c_int = 1 << 3;
+
+#[repr(C)]
+pub struct ares_addrinfo_hints {
+    pub ai_flags: c_int,
+    pub ai_family: c_int,
+    pub ai_socktype: c_int,
+    pub ai_protocol: c_int,
+}
+
+#[repr(C)]
+pub struct ares_addrinfo_node {
+    pub ai_ttl: c_int,
+    pub ai_flags: c_int,
+    pub ai_family: c_int,
+    pub ai_socktype: c_int,
+    pub ai_protocol: c_int,
+    pub ai_addrlen: libc::socklen_t,
+    pub ai_addr: *mut libc::sockaddr,
+    pub ai_next: *mut ares_addrinfo_node,
+}
+
+#[repr(C)]
+pub struct ares_addrinfo_cname {
+    pub ttl: c_int,
+    pub alias: *mut c_char,
+    pub name: *mut c_char,
+    pub next: *mut ares_addrinfo_cname,
+}
+
+#[repr(C)]
+pub struct ares_addrinfo {
+    pub cnames: *mut ares_addrinfo_cname,
+    pub nodes: *mut ares_addrinfo_node,
+    pub name: *mut c_char,
+}
+
+pub type AresAddrinfoCallback = unsafe extern "C" fn(arg: *mut c_void, status: c_int, timeouts: c_int, res: *mut ares_addrinfo);
+
+impl ares_addrinfo_node {
+    /// `ai_addr` always points to a whole sockaddr_storage, `ai_addrlen` telling how much of it is used
+    fn new(addr: SocketAddr, ttl: u32, socktype: c_int, protocol: c_int) -> ares_addrinfo_node {
+        let (storage, len) = to_sockaddr(addr);
+        let family = if addr.is_ipv6() { libc::AF_INET6 } else { libc::AF_INET };
+        ares_addrinfo_node {
+            ai_ttl: ttl.min(c_int::MAX as u32) as c_int,
+            ai_flags: 0,
+            ai_family: family,
+            ai_socktype: socktype,
+            ai_protocol: protocol,
+            ai_addrlen: len,
+            ai_addr: Box::into_raw(Box::new(storage)) as *mut libc::sockaddr,
+            ai_next: std::ptr::null_mut(),
+        }
+    }
+}
+
+impl Drop for ares_addrinfo_node {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.ai_addr as *mut libc::sockaddr_storage) });
+        if !self.ai_next.is_null() {
+            drop(unsafe { Box::from_raw(self.ai_next) })
+        }
+    }
+}
+
+impl Drop for ares_addrinfo_cname {
+    fn drop(&mut self) {
+        drop(unsafe { CString::from_raw(self.alias) });
+        drop(unsafe { CString::from_raw(self.name) });
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for ares_addrinfo {
+    fn drop(&mut self) {
+        if !self.cnames.is_null() {
+            drop(unsafe { Box::from_raw(self.cnames) });
+        }
+        if !self.nodes.is_null() {
+            drop(unsafe { Box::from_raw(self.nodes) });
+        }
+        drop(unsafe { CString::from_raw(self.name) });
+    }
+}
+
+impl CLinkedList for ares_addrinfo_node {
+    fn next(&mut self) -> &mut *mut Self { &mut self.ai_next }
+}
+
+impl CLinkedList for ares_addrinfo_cname {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+/// Service hints and port applied to every address of a lookup
+#[derive(Debug, Clone, Copy)]
+struct Service {
+    port: u16,
+    socktype: c_int,
+    protocol: c_int,
+}
+
+/// What the A and AAAA replies of one lookup brought so far
+#[derive(Debug, Default)]
+struct Gathered {
+    addrs: Vec<(IpAddr, u32)>,
+    cnames: Vec<CnameHop>,
+    canonname: Option<String>,
+    /// First failure other than ENODATA, which is what's reported if no query found addresses
+    status: Option<c_int>,
+    timeouts: u32,
+    pending: usize,
+}
+
+/// One ares_getaddrinfo call, whose callback fires once all of its queries are done
+#[derive(Debug)]
+pub struct AddrinfoLookup {
+    name: String,
+    hints: Hints,
+    service: Service,
+    callback: AresAddrinfoCallback,
+    arg: *mut c_void,
+    gathered: RefCell<Gathered>,
+}
+
+impl AddrinfoLookup {
+    pub(crate) fn reply(&self, buf: Vec<u8>, frame: DnsFrame, timeouts: u32) {
+        let result = match Rcode::from_flags(frame.flags) {
+            Some(rcode) => Err(rcode.into()),
+            None => Hints { family: Family::Unspec, ..self.hints }.from_reply(&buf),
+        };
+        match result {
+            Ok(info) => {
+                let mut gathered = self.gathered.borrow_mut();
+                let ttl_of = |addr: &IpAddr| record_ttl(&frame, addr).unwrap_or(0);
+                gathered.addrs.extend(info.addrs.iter().map(|addr| (*addr, ttl_of(addr))));
+                if gathered.cnames.is_empty() {
+                    gathered.cnames = frame.cname_chain(&buf).unwrap_or_default();
+                }
+                gathered.canonname = gathered.canonname.take().or(info.canonname);
+            }
+            Err(err) => self.record_error(ares_status(err)),
+        }
+        self.finish_one(timeouts);
+    }
+    pub(crate) fn fail(&self, status: c_int, timeouts: u32) {
+        self.record_error(status);
+        self.finish_one(timeouts);
+    }
+    fn record_error(&self, status: c_int) {
+        let mut gathered = self.gathered.borrow_mut();
+        if status != ARES_ENODATA && gathered.status.is_none() {
+            gathered.status = Some(status);
+        }
+    }
+    fn finish_one(&self, timeouts: u32) {
+        let gathered = {
+            let mut gathered = self.gathered.borrow_mut();
+            gathered.timeouts += timeouts;
+            gathered.pending -= 1;
+            if gathered.pending > 0 {
+                return;
+            }
+            std::mem::take(&mut *gathered)
+        };
+        let timeouts = gathered.timeouts as c_int;
+        if gathered.addrs.is_empty() {
+            let status = gathered.status.unwrap_or(ARES_ENODATA);
+            return unsafe { (self.callback)(self.arg, status, timeouts, std::ptr::null_mut()) };
+        }
+        let Some(res) = self.build(gathered) else {
+            return unsafe { (self.callback)(self.arg, ARES_EBADNAME, timeouts, std::ptr::null_mut()) };
+        };
+        unsafe { (self.callback)(self.arg, ARES_SUCCESS, timeouts, Box::into_raw(Box::new(res))) };
+    }
+    /// IPv6 addresses come first, like the AAAA-first order of ares_gethostbyname with AF_UNSPEC
+    fn build(&self, mut gathered: Gathered) -> Option<ares_addrinfo> {
+        gathered.addrs.sort_by_key(|(addr, _)| addr.is_ipv4());
+        let Service { port, socktype, protocol } = self.service;
+        let nodes = gathered.addrs.into_iter()
+            .map(|(addr, ttl)| ares_addrinfo_node::new(SocketAddr::new(addr, port), ttl, socktype, protocol))
+            .collect::<Vec<_>>();
+        let cnames = gathered.cnames.into_iter().map(|hop| {
+            let (alias, name) = (CString::new(hop.alias).ok()?, CString::new(hop.name).ok()?);
+            let ttl = hop.ttl.min(c_int::MAX as u32) as c_int;
+            Some(ares_addrinfo_cname { ttl, alias: alias.into_raw(), name: name.into_raw(), next: std::ptr::null_mut() })
+        }).collect::<Option<Vec<_>>>()?;
+        let name = CString::new(gathered.canonname.unwrap_or_else(|| self.name.clone())).ok()?;
+        Some(ares_addrinfo {
+            cnames: chain_or_null(cnames),
+            nodes: chain_or_null(nodes),
+            name: name.into_raw(),
+        })
+    }
+}
+
+/// TTL of the A or AAAA record holding `addr`
+fn record_ttl(frame: &DnsFrame, addr: &IpAddr) -> Option<u32> {
+    let data = match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    frame.answers.iter()
+        .filter(|answer| matches!(answer.rtype(), RecordType::A | RecordType::Aaaa))
+        .find(|answer| answer.data == data)
+        .map(|answer| answer.ttl)
+}
+
+fn chain_or_null<T: CLinkedList>(nodes: Vec<T>) -> *mut T {
+    if nodes.is_empty() {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(clinkedlist::chain_nodes(nodes)))
+}
+
+/// Port of `service`: a number, or with `socktype` picking the protocol, a name from the services database
+fn parse_service(service: Option<&str>, flags: c_int, socktype: c_int) -> Result<u16, c_int> {
+    let Some(service) = service else { return Ok(0) };
+    if let Ok(port) = service.parse::<u16>() {
+        return Ok(port);
+    }
+    if flags & ARES_AI_NUMERICSERV != 0 {
+        return Err(ARES_ESERVICE);
+    }
+    let name = CString::new(service).map_err(|_| ARES_ESERVICE)?;
+    let proto = if socktype == libc::SOCK_DGRAM { cstr!("udp") } else { cstr!("tcp") };
+    let entry = unsafe { libc::getservbyname(name.as_ptr(), proto) };
+    match unsafe { entry.as_ref() } {
+        Some(entry) => Ok(u16::from_be(entry.s_port as u16)),
+        None => Err(ARES_ESERVICE),
+    }
+}
+
+/// Looks `name` up with an A and an AAAA query at once (per the `ai_family` hint), or from static hosts
+/// and literal addresses without any, calling back once with the merged result.
+/// The result is released with ares_freeaddrinfo
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_getaddrinfo(channel: Channel, name: *const c_char, service: *const c_char, hints: *const ares_addrinfo_hints, callback: AresAddrinfoCallback, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    let fail = |status: c_int| unsafe { callback(arg, status, 0, std::ptr::null_mut()) };
+    let default_hints = ares_addrinfo_hints { ai_flags: 0, ai_family: libc::AF_UNSPEC, ai_socktype: 0, ai_protocol: 0 };
+    let hints = unsafe { hints.as_ref() }.unwrap_or(&default_hints);
+    let family = match hints.ai_family {
+        libc::AF_INET => Family::Ipv4,
+        libc::AF_INET6 => Family::Ipv6,
+        libc::AF_UNSPEC => Family::Unspec,
+        _ => return fail(ARES_EBADFAMILY),
+    };
+    let name = unsafe { name.as_ref() }.map(|name| unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned());
+    let service = unsafe { service.as_ref() }.map(|service| unsafe { CStr::from_ptr(service) }.to_string_lossy().into_owned());
+    let port = match parse_service(service.as_deref(), hints.ai_flags, hints.ai_socktype) {
+        Ok(port) => port,
+        Err(status) => return fail(status),
+    };
+    let lookup = AddrinfoLookup {
+        name: name.clone().unwrap_or_default(),
+        hints: Hints { family, flags: hints.ai_flags },
+        service: Service { port, socktype: hints.ai_socktype, protocol: hints.ai_protocol },
+        callback,
+        arg,
+        gathered: RefCell::new(Gathered { pending: 1, ..Gathered::default() }),
+    };
+
+    let local = lookup.hints.resolve_locally(name.as_deref()).or_else(|| {
+        let addrs = channeldata.ares.static_host(name.as_deref()?, family)?;
+        Some(Ok(AddrInfo { canonname: None, addrs }))
+    });
+    match local {
+        Some(Ok(info)) => {
+            lookup.gathered.borrow_mut().addrs = info.addrs.into_iter().map(|addr| (addr, 0)).collect();
+            lookup.gathered.borrow_mut().canonname = info.canonname;
+            return lookup.finish_one(0);
+        }
+        Some(Err(err)) => return fail(ares_status(err)),
+        None => {}
+    }
+
+    let Some(name) = name else { return fail(ARES_ENONAME) };
+    let families = match family {
+        Family::Unspec if channeldata.ares.config.options.no_aaaa => vec![Family::Ipv4],
+        Family::Unspec => vec![Family::Ipv6, Family::Ipv4],
+        family => vec![family],
+    };
+    lookup.gathered.borrow_mut().pending = families.len();
+    let lookup = Rc::new(lookup);
+    for family in families {
+        let ffidata = FFIData { callback: Callback::Addrinfo(lookup.clone()), arg };
+        match channeldata.ares.gethostbyname(&name, family, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+            Ok(fd) => channeldata.socket_created(fd),
+            Err(err) => lookup.fail(io_status(&err), 0),
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_freeaddrinfo(ai: *mut ares_addrinfo) {
+    if !ai.is_null() {
+        drop(unsafe { Box::from_raw(ai) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::packets::DnsFrame;
+    use crate::core::testing::*;
+    use crate::ffi::clinkedlist::iter_clinked;
+    use crate::ffi::{ ares_cancel, ares_destroy, ares_init };
+    use std::io::Cursor;
+    use std::net::{ Ipv4Addr, Ipv6Addr };
+
+    #[derive(Default)]
+    struct Outcome {
+        calls: Vec<(c_int, c_int)>,
+        addrs: Vec<SocketAddr>,
+        socktypes: Vec<c_int>,
+        name: String,
+    }
+
+    unsafe extern "C" fn record_addrinfo(arg: *mut c_void, status: c_int, timeouts: c_int, res: *mut ares_addrinfo) {
+        let outcome = unsafe { &mut *(arg as *mut Outcome) };
+        outcome.calls.push((status, timeouts));
+        let Some(ai) = (unsafe { res.as_ref() }) else { return };
+        for node in unsafe { iter_clinked(ai.nodes) } {
+            let addr = match node.ai_family {
+                libc::AF_INET => {
+                    let sin = unsafe { &*(node.ai_addr as *const libc::sockaddr_in) };
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))), u16::from_be(sin.sin_port))
+                }
+                _ => {
+                    let sin6 = unsafe { &*(node.ai_addr as *const libc::sockaddr_in6) };
+                    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)), u16::from_be(sin6.sin6_port))
+                }
+            };
+            outcome.addrs.push(addr);
+            outcome.socktypes.push(node.ai_socktype);
+        }
+        outcome.name = unsafe { CStr::from_ptr(ai.name) }.to_string_lossy().into_owned();
+        unsafe { ares_freeaddrinfo(res) };
+    }
+
+    const AAAA_ANSWER: &[u8] = b"\xc0\x0c\x00\x1c\x00\x01\x00\x00\x01\x2c\x00\x10\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01";
+
+    #[test]
+    fn test_getaddrinfo_merges_a_and_aaaa() {
+        let server = StubServer::spawn(|query| {
+            let frame = DnsFrame::parse(&mut Cursor::new(query))?;
+            match frame.queries[0].qtype {
+                0x01 => Some(reply(query, 0x8180, &[A_ANSWER])),
+                _ => Some(reply(query, 0x8180, &[AAAA_ANSWER])),
+            }
+        });
+        let mut outcome = Outcome::default();
+        let hints = ares_addrinfo_hints { ai_flags: 0, ai_family: libc::AF_UNSPEC, ai_socktype: libc::SOCK_STREAM, ai_protocol: 0 };
+        unsafe {
+            let channel = channel_for(&server);
+            let arg = &mut outcome as *mut Outcome as *mut c_void;
+            ares_getaddrinfo(channel, cstr!("example.test"), cstr!("443"), &hints, record_addrinfo, arg);
+            assert_eq!((*channel).ares.tasks.len(), 2);
+            pump_once(channel);
+            ares_destroy(channel);
+        }
+        assert_eq!(outcome.calls, vec![(ARES_SUCCESS, 0)]);
+        assert_eq!(outcome.addrs, vec!["[2001:db8::1]:443".parse().unwrap(), "1.2.3.4:443".parse().unwrap()]);
+        assert_eq!(outcome.socktypes, vec![libc::SOCK_STREAM; 2]);
+        assert_eq!(outcome.name, "example.test");
+    }
+
+    unsafe extern "C" fn ignore_addrinfo(_arg: *mut c_void, _status: c_int, _timeouts: c_int, _res: *mut ares_addrinfo) {}
+
+    #[test]
+    fn test_freeaddrinfo_frees_everything() {
+        let lookup = AddrinfoLookup {
+            name: "www.example.test".to_string(),
+            hints: Hints { family: Family::Unspec, flags: 0 },
+            service: Service { port: 80, socktype: libc::SOCK_STREAM, protocol: 0 },
+            callback: ignore_addrinfo,
+            arg: std::ptr::null_mut(),
+            gathered: RefCell::default(),
+        };
+        let before = live_allocations();
+        let hop = CnameHop { alias: "www.example.test".to_string(), name: "cdn.example.test".to_string(), ttl: 300 };
+        let gathered = Gathered {
+            addrs: vec![("192.0.2.1".parse().unwrap(), 300), ("2001:db8::1".parse().unwrap(), 60)],
+            cnames: vec![hop],
+            ..Gathered::default()
+        };
+        let res = Box::into_raw(Box::new(lookup.build(gathered).unwrap()));
+        unsafe {
+            let ttls: Vec<c_int> = iter_clinked((*res).nodes).map(|node| node.ai_ttl).collect();
+            assert_eq!(ttls, vec![60, 300]);
+            assert_eq!(CStr::from_ptr((*(*res).cnames).name).to_str(), Ok("cdn.example.test"));
+            ares_freeaddrinfo(res);
+        }
+        assert_eq!(live_allocations(), before);
+    }
+
+    #[test]
+    fn test_getaddrinfo_family_hint_sends_one_query() {
+        let mut outcome = Outcome::default();
+        let hints = ares_addrinfo_hints { ai_flags: 0, ai_family: libc::AF_INET, ai_socktype: 0, ai_protocol: 0 };
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            let arg = &mut outcome as *mut Outcome as *mut c_void;
+            ares_getaddrinfo(channel, cstr!("example.test"), std::ptr::null(), &hints, record_addrinfo, arg);
+            assert_eq!((*channel).ares.tasks.len(), 1);
+            assert_eq!((&(*channel).ares.tasks)[0].request.queries[0].qtype, 0x01);
+            ares_cancel(channel);
+            ares_destroy(channel);
+        }
+        assert_eq!(outcome.calls, vec![(ARES_ECANCELLED, 0)]);
+
+        let mut outcome = Outcome::default();
+        let hints = ares_addrinfo_hints { ai_flags: ARES_AI_NUMERICSERV, ai_family: libc::AF_INET, ai_socktype: 0, ai_protocol: 0 };
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            let arg = &mut outcome as *mut Outcome as *mut c_void;
+            ares_getaddrinfo(channel, cstr!("192.0.2.1"), cstr!("http"), &hints, record_addrinfo, arg);
+            ares_destroy(channel);
+        }
+        assert_eq!(outcome.calls, vec![(ARES_ESERVICE, 0)]);
+    }
+
+    #[test]
+    fn test_getaddrinfo_literal_address_needs_no_query() {
+        let mut outcome = Outcome::default();
+        let hints = ares_addrinfo_hints { ai_flags: 0, ai_family: libc::AF_UNSPEC, ai_socktype: libc::SOCK_DGRAM, ai_protocol: libc::IPPROTO_UDP };
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            let arg = &mut outcome as *mut Outcome as *mut c_void;
+            ares_getaddrinfo(channel, cstr!("192.0.2.1"), cstr!("53"), &hints, record_addrinfo, arg);
+            assert!((*channel).ares.tasks.is_empty());
+            ares_destroy(channel);
+        }
+        assert_eq!(outcome.calls, vec![(ARES_SUCCESS, 0)]);
+        assert_eq!(outcome.addrs, vec!["192.0.2.1:53".parse().unwrap()]);
+        assert_eq!(outcome.socktypes, vec![libc::SOCK_DGRAM]);
+    }
+}
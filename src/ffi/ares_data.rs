@@ -1,25 +1,87 @@
-use std::ffi::{ CString, c_void, c_char, c_ushort, c_int };
-use crate::core::packets::{ TxtReply, MxReply };
+use std::ffi::{ CString, c_void, c_char, c_uchar, c_ushort, c_uint, c_int };
+use crate::core::packets::{ TxtReply, MxReply, SrvReply, SoaReply, NaptrReply, SvcbReply };
+use crate::ffi::ares_addr_node;
 use crate::ffi::clinkedlist::*;
 use crate::offset_of;
 
+/// Conversion into a C reply node owning its strings; `None` if the reply can't be represented
 pub trait IntoAresData<T> {
-    fn into_ares_data(self, main_buf: &[u8]) -> T;
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<T>;
 }
 
-impl IntoAresData<AresTxtReply> for TxtReply {
-    fn into_ares_data(self, _main_buf: &[u8]) -> AresTxtReply {
-        let length = self.txt.len();
-        let txt = CString::new(self.txt).unwrap().into_raw();
-        AresTxtReply { next: std::ptr::null_mut(), txt, length }
+impl AresTxtReply {
+    /// A node per segment of `txt`, like c-ares, each holding the raw bytes plus a NUL terminator
+    pub fn from_segments(txt: TxtReply) -> Vec<AresTxtReply> {
+        txt.segments.into_iter().map(|mut segment| {
+            let length = segment.len();
+            segment.push(0);
+            let txt = Box::into_raw(segment.into_boxed_slice()) as *const c_char;
+            AresTxtReply { next: std::ptr::null_mut(), txt, length }
+        }).collect()
     }
 }
 
 impl IntoAresData<AresMxReply> for MxReply {
-    fn into_ares_data(self, main_buf: &[u8]) -> AresMxReply {
-        let name = self.label.build_cstring(main_buf).unwrap();
-        let raw_ptr = name.into_raw();
-        AresMxReply { next: std::ptr::null_mut(), host: raw_ptr, priority: self.priority }
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<AresMxReply> {
+        let name = self.label.build_cstring(main_buf)?;
+        Some(AresMxReply { next: std::ptr::null_mut(), host: name.into_raw(), priority: self.priority })
+    }
+}
+
+impl IntoAresData<AresSrvReply> for SrvReply {
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<AresSrvReply> {
+        let name = self.label.build_cstring(main_buf)?;
+        Some(AresSrvReply { next: std::ptr::null_mut(), host: name.into_raw(), priority: self.priority, weight: self.weight, port: self.port })
+    }
+}
+
+impl IntoAresData<AresNaptrReply> for NaptrReply {
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<AresNaptrReply> {
+        let flags = CString::new(self.flags).ok()?;
+        let service = CString::new(self.services).ok()?;
+        let regexp = CString::new(self.regexp).ok()?;
+        let replacement = self.replacement.build_cstring(main_buf)?;
+        Some(AresNaptrReply {
+            next: std::ptr::null_mut(),
+            flags: flags.into_raw() as *const c_uchar,
+            service: service.into_raw() as *const c_uchar,
+            regexp: regexp.into_raw() as *const c_uchar,
+            replacement: replacement.into_raw(),
+            order: self.order,
+            preference: self.preference,
+        })
+    }
+}
+
+impl IntoAresData<AresSoaReply> for SoaReply {
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<AresSoaReply> {
+        let nsname = self.mname.build_cstring(main_buf)?;
+        let hostmaster = self.rname.build_cstring(main_buf)?;
+        Some(AresSoaReply {
+            nsname: nsname.into_raw(),
+            hostmaster: hostmaster.into_raw(),
+            serial: self.serial,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minttl: self.minttl,
+        })
+    }
+}
+
+impl IntoAresData<AresSvcbReply> for SvcbReply {
+    fn into_ares_data(self, main_buf: &[u8]) -> Option<AresSvcbReply> {
+        let target = self.target.build_cstring(main_buf)?;
+        let params = self.params.into_iter().map(|(key, value)| {
+            let length = value.len();
+            let value = Box::into_raw(value.into_boxed_slice()) as *const c_uchar;
+            AresSvcbParam { next: std::ptr::null_mut(), key, value, length }
+        }).collect::<Vec<_>>();
+        let params = match params.is_empty() {
+            true => std::ptr::null_mut(),
+            false => Box::into_raw(Box::new(chain_nodes(params))),
+        };
+        Some(AresSvcbReply { next: std::ptr::null_mut(), priority: self.priority, target: target.into_raw(), params })
     }
 }
 
@@ -34,6 +96,19 @@ pub unsafe extern "C" fn ares_free_data(dataptr: *mut c_void) {
         AresDataType::MxReply => drop(Box::from_raw(aresdata as *mut AresData<AresMxReply>)),
         AresDataType::TxtReply => drop(Box::from_raw(aresdata as *mut AresData<AresTxtReply>)),
         AresDataType::AddrPortNode => drop(Box::from_raw(aresdata as *mut AresData<AresAddrPortNode>)),
+        AresDataType::SrvReply => drop(Box::from_raw(aresdata as *mut AresData<AresSrvReply>)),
+        AresDataType::SoaReply => drop(Box::from_raw(aresdata as *mut AresData<AresSoaReply>)),
+        AresDataType::NaptrReply => drop(Box::from_raw(aresdata as *mut AresData<AresNaptrReply>)),
+        AresDataType::CaaReply => drop(Box::from_raw(aresdata as *mut AresData<AresCaaReply>)),
+        AresDataType::SvcbReply => drop(Box::from_raw(aresdata as *mut AresData<AresSvcbReply>)),
+        AresDataType::AddrNode => {
+            // Without a Drop impl, caller-built lists passed to ares_set_servers aren't ours to free
+            let aresdata = Box::from_raw(aresdata as *mut AresData<ares_addr_node>);
+            let mut next = aresdata.data.next;
+            while !next.is_null() {
+                next = Box::from_raw(next).next;
+            }
+        }
     }
 }
 
@@ -42,7 +117,13 @@ pub unsafe extern "C" fn ares_free_data(dataptr: *mut c_void) {
 pub enum AresDataType {
     MxReply,
     TxtReply,
-    AddrPortNode
+    AddrPortNode,
+    SrvReply,
+    SoaReply,
+    NaptrReply,
+    CaaReply,
+    SvcbReply,
+    AddrNode,
 }
 
 #[repr(C)]
@@ -66,6 +147,66 @@ pub struct AresTxtReply {
     pub length: usize, // null termination excluded
 }
 
+#[repr(C)]
+pub struct AresSrvReply {
+    next: *mut AresSrvReply,
+    pub host: *const c_char,
+    pub priority: c_ushort,
+    pub weight: c_ushort,
+    pub port: c_ushort,
+}
+
+/// A single record, unlike the other replies
+#[repr(C)]
+pub struct AresSoaReply {
+    pub nsname: *const c_char,
+    pub hostmaster: *const c_char,
+    pub serial: c_uint,
+    pub refresh: c_uint,
+    pub retry: c_uint,
+    pub expire: c_uint,
+    pub minttl: c_uint,
+}
+
+#[repr(C)]
+pub struct AresNaptrReply {
+    next: *mut AresNaptrReply,
+    pub flags: *const c_uchar,
+    pub service: *const c_uchar,
+    pub regexp: *const c_uchar,
+    pub replacement: *const c_char,
+    pub order: c_ushort,
+    pub preference: c_ushort,
+}
+
+#[repr(C)]
+pub struct AresCaaReply {
+    next: *mut AresCaaReply,
+    pub critical: c_int,
+    pub property: *const c_uchar,
+    pub plength: usize, // null termination excluded
+    pub value: *const c_uchar,
+    pub length: usize, // null termination excluded
+}
+
+/// SVCB or HTTPS record, `params` being null for none (AliasMode)
+#[repr(C)]
+pub struct AresSvcbReply {
+    next: *mut AresSvcbReply,
+    pub priority: c_ushort,
+    pub target: *const c_char,
+    pub params: *mut AresSvcbParam,
+}
+
+/// One SvcParam, its value left in wire format
+#[repr(C)]
+pub struct AresSvcbParam {
+    pub next: *mut AresSvcbParam,
+    pub key: c_ushort,
+    pub value: *const c_uchar,
+    pub length: usize,
+}
+
 // ares_addr_port_node
 
 #[repr(C)]
@@ -94,7 +235,76 @@ impl Drop for AresMxReply {
 
 impl Drop for AresTxtReply {
     fn drop(&mut self) {
-        drop(unsafe { CString::from_raw(self.txt as *mut c_char) });
+        let txt = std::ptr::slice_from_raw_parts_mut(self.txt as *mut u8, self.length + 1);
+        drop(unsafe { Box::from_raw(txt) });
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresAddrPortNode {
+    fn drop(&mut self) {
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresSrvReply {
+    fn drop(&mut self) {
+        drop(unsafe { CString::from_raw(self.host as *mut c_char) });
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresSoaReply {
+    fn drop(&mut self) {
+        drop(unsafe { CString::from_raw(self.nsname as *mut c_char) });
+        drop(unsafe { CString::from_raw(self.hostmaster as *mut c_char) });
+    }
+}
+
+impl Drop for AresNaptrReply {
+    fn drop(&mut self) {
+        for s in [self.flags, self.service, self.regexp] {
+            drop(unsafe { CString::from_raw(s as *mut c_char) });
+        }
+        drop(unsafe { CString::from_raw(self.replacement as *mut c_char) });
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresCaaReply {
+    fn drop(&mut self) {
+        drop(unsafe { CString::from_raw(self.property as *mut c_char) });
+        drop(unsafe { CString::from_raw(self.value as *mut c_char) });
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresSvcbReply {
+    fn drop(&mut self) {
+        drop(unsafe { CString::from_raw(self.target as *mut c_char) });
+        if !self.params.is_null() {
+            drop(unsafe { Box::from_raw(self.params) })
+        }
+        if !self.next.is_null() {
+            drop(unsafe { Box::from_raw(self.next) })
+        }
+    }
+}
+
+impl Drop for AresSvcbParam {
+    fn drop(&mut self) {
+        let value = std::ptr::slice_from_raw_parts_mut(self.value as *mut u8, self.length);
+        drop(unsafe { Box::from_raw(value) });
         if !self.next.is_null() {
             drop(unsafe { Box::from_raw(self.next) })
         }
@@ -113,6 +323,26 @@ impl CLinkedList for AresAddrPortNode {
     fn next(&mut self) -> &mut *mut Self { &mut self.next }
 }
 
+impl CLinkedList for AresSrvReply {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+impl CLinkedList for AresNaptrReply {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+impl CLinkedList for AresCaaReply {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+impl CLinkedList for AresSvcbReply {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+impl CLinkedList for AresSvcbParam {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
 pub trait DataType {
     fn datatype() -> AresDataType;
 }
@@ -129,9 +359,31 @@ impl DataType for AresAddrPortNode {
     fn datatype() -> AresDataType { AresDataType::AddrPortNode }
 }
 
+impl DataType for AresSrvReply {
+    fn datatype() -> AresDataType { AresDataType::SrvReply }
+}
+
+impl DataType for AresSoaReply {
+    fn datatype() -> AresDataType { AresDataType::SoaReply }
+}
+
+impl DataType for AresNaptrReply {
+    fn datatype() -> AresDataType { AresDataType::NaptrReply }
+}
+
+impl DataType for AresCaaReply {
+    fn datatype() -> AresDataType { AresDataType::CaaReply }
+}
+
+impl DataType for AresSvcbReply {
+    fn datatype() -> AresDataType { AresDataType::SvcbReply }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::packets::DnsLabel;
+    use crate::core::testing::live_allocations;
 
     impl Default for AresMxReply {
         fn default() -> Self {
@@ -141,7 +393,7 @@ mod tests {
 
     impl Default for AresTxtReply {
         fn default() -> Self {
-            AresTxtReply { next: std::ptr::null_mut(), txt: CString::new("default").unwrap().into_raw(), length: 0}
+            AresTxtReply::from_segments(TxtReply { segments: vec![b"default".to_vec()] }).pop().unwrap()
         }
     }
 
@@ -152,11 +404,79 @@ mod tests {
         }
     }
 
+    fn raw_string(s: &str) -> *const c_char {
+        CString::new(s).unwrap().into_raw()
+    }
+
+    impl Default for AresSrvReply {
+        fn default() -> Self {
+            AresSrvReply { next: std::ptr::null_mut(), host: raw_string("default"), priority: 1, weight: 1, port: 53 }
+        }
+    }
+
+    impl Default for AresSoaReply {
+        fn default() -> Self {
+            AresSoaReply { nsname: raw_string("ns"), hostmaster: raw_string("hostmaster"), serial: 1, refresh: 2, retry: 3, expire: 4, minttl: 5 }
+        }
+    }
+
+    impl Default for AresNaptrReply {
+        fn default() -> Self {
+            let (flags, service, regexp) = (raw_string("s") as *const c_uchar, raw_string("SIP+D2U") as *const c_uchar, raw_string("") as *const c_uchar);
+            AresNaptrReply { next: std::ptr::null_mut(), flags, service, regexp, replacement: raw_string("_sip._udp"), order: 1, preference: 1 }
+        }
+    }
+
+    impl Default for AresCaaReply {
+        fn default() -> Self {
+            let (property, value) = (raw_string("issue") as *const c_uchar, raw_string("ca.example") as *const c_uchar);
+            AresCaaReply { next: std::ptr::null_mut(), critical: 0, property, plength: 5, value, length: 10 }
+        }
+    }
+
+    impl Default for AresSvcbReply {
+        fn default() -> Self {
+            let params = vec![(1, b"\x02h2".to_vec()), (3, vec![0x01, 0xbb])];
+            let svcb = SvcbReply { priority: 1, target: DnsLabel::new(&["svc"], None), params };
+            svcb.into_ares_data(&[]).unwrap()
+        }
+    }
+
     #[test]
     fn test_restore_original_ptr() {
         test_restore_original_ptr_impl::<AresMxReply>();
         test_restore_original_ptr_impl::<AresTxtReply>();
         test_restore_original_ptr_impl::<AresAddrPortNode>();
+        test_restore_original_ptr_impl::<AresSrvReply>();
+        test_restore_original_ptr_impl::<AresSoaReply>();
+        test_restore_original_ptr_impl::<AresNaptrReply>();
+        test_restore_original_ptr_impl::<AresCaaReply>();
+        test_restore_original_ptr_impl::<AresSvcbReply>();
+    }
+
+    unsafe fn alloc_and_free<T: DataType>(data: T) {
+        let aresdata = Box::into_raw(Box::new(AresData { data_type: T::datatype(), data }));
+        unsafe { ares_free_data(std::ptr::addr_of_mut!((*aresdata).data) as *mut c_void) };
+    }
+
+    fn pair<T: Default + CLinkedList>() -> T {
+        chain_nodes(vec![T::default(), T::default()])
+    }
+
+    #[test]
+    fn test_free_data_every_type() {
+        let before = live_allocations();
+        unsafe {
+            alloc_and_free(pair::<AresMxReply>());
+            alloc_and_free(pair::<AresTxtReply>());
+            alloc_and_free(pair::<AresAddrPortNode>());
+            alloc_and_free(pair::<AresSrvReply>());
+            alloc_and_free(AresSoaReply::default());
+            alloc_and_free(pair::<AresNaptrReply>());
+            alloc_and_free(pair::<AresCaaReply>());
+            alloc_and_free(pair::<AresSvcbReply>());
+        }
+        assert_eq!(live_allocations(), before);
     }
 
     fn test_restore_original_ptr_impl<T>() where T: Default + DataType {
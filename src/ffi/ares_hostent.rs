@@ -1,67 +1,173 @@
-use std::ffi::{ c_void, c_int, CString };
+use std::ffi::{ c_void, c_int, CStr, CString };
 use std::io::Cursor;
+use std::net::IpAddr;
 use crate::ffi::cnullterminated;
 use crate::core::packets::*;
+use crate::core::replies;
 use crate::{ ARES_ENODATA, ARES_EFORMERR };
+use crate::ffi::error::{ ARES_EBADRESP, ARES_EBADFAMILY };
 
 #[derive(PartialEq)]
 pub enum HostentParseMode { Addrs, Addrs4, Addrs6, Aliases }
 
 pub unsafe fn parse_hostent(abuf: *const u8, alen: c_int, mode: HostentParseMode) -> Result<libc::hostent, i32> {
     let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
-    let frame = DnsFrame::parse(&mut Cursor::new(buf)).unwrap();
-
-    let Some(answer) = frame.answers.first() else { return Err(ARES_ENODATA) };
-    let name = answer.name.build_cstring(&buf).unwrap();
-    let h_addrtype = match answer.record_type {
-        0x01 => libc::AF_INET,
-        0x1c => libc::AF_INET6,
-        0x02 => 0x02,
-        _ => panic!("Unexpected DNS record type in answer: {}", answer.record_type),
-    };
+    let frame = DnsFrame::parse(&mut Cursor::new(buf)).ok_or(ARES_EBADRESP)?;
+
+    // Records owned by names off the question's CNAME chain are unrelated to it, if not spoofed
+    let answers: Vec<&DnsAnswer> = frame.answers.iter().filter(|answer| !answer.is_opt() && frame.owns_answer(answer, buf)).collect();
+    if answers.is_empty() {
+        return Err(ARES_ENODATA);
+    }
 
     let mut aliases: Vec<*mut i8> = vec![];
     let mut addr_list: Vec<*mut i8> = vec![];
-    match mode {
-        HostentParseMode::Addrs | HostentParseMode::Addrs4 | HostentParseMode::Addrs6 => for answer in &frame.answers {
-            if mode == HostentParseMode::Addrs4 && h_addrtype != libc::AF_INET {
-                continue;
-            }
-            if mode == HostentParseMode::Addrs6 && h_addrtype != libc::AF_INET6 {
-                continue;
-            }
-            let expected_length = match h_addrtype {
-                libc::AF_INET => 4,
-                libc::AF_INET6 => 16,
-                _ => return Err(ARES_EFORMERR),
+    let mut seen_addrs: Vec<&[u8]> = vec![];
+    let (name, h_addrtype, h_length) = match mode {
+        HostentParseMode::Addrs | HostentParseMode::Addrs4 | HostentParseMode::Addrs6 => {
+            // CNAME answers only lead up to the addresses, whose first one decides the family
+            let rtype = answers.iter().map(|answer| answer.rtype()).find(|rtype| match mode {
+                HostentParseMode::Addrs4 => *rtype == RecordType::A,
+                HostentParseMode::Addrs6 => *rtype == RecordType::Aaaa,
+                _ => *rtype != RecordType::Cname,
+            });
+            let Some(rtype) = rtype else { return Err(ARES_ENODATA) };
+            let (h_addrtype, expected_length) = match rtype {
+                RecordType::A => (libc::AF_INET, 4),
+                RecordType::Aaaa => (libc::AF_INET6, 16),
+                _ => return Err(ARES_EBADRESP),
             };
-            if answer.data.len() != expected_length {
-                return Err(ARES_EFORMERR);
+            // Named after the chain's tail, like c-ares, the names leading there being aliases
+            let chain = frame.cname_chain(buf).ok_or(ARES_EBADRESP)?;
+            let canonical = frame.canonical_name(buf).ok_or(ARES_EBADRESP)?;
+            let name = CString::new(canonical).map_err(|_| ARES_EBADRESP)?;
+            let chain_aliases = chain.into_iter().map(|hop| CString::new(hop.alias).ok()).collect::<Option<Vec<CString>>>();
+            aliases = chain_aliases.ok_or(ARES_EBADRESP)?.into_iter().map(CString::into_raw).collect();
+            for answer in answers.iter().filter(|answer| answer.rtype() == rtype) {
+                if answer.data.len() != expected_length {
+                    free_pointers(aliases, addr_list);
+                    return Err(ARES_EFORMERR);
+                }
+                // Some load balancers repeat records, keep the first occurrence only
+                if seen_addrs.contains(&&answer.data[..]) {
+                    continue;
+                }
+                seen_addrs.push(&answer.data[..]);
+                addr_list.push(malloc_addr(&answer.data));
             }
-            let dst = unsafe { libc::malloc(answer.data.len()) } as *mut u8;
-            unsafe { std::ptr::copy_nonoverlapping(answer.data.as_ptr(), dst, answer.data.len()) };
-            addr_list.push(dst as *mut i8);
+            (name, h_addrtype, expected_length as i32)
         },
-        HostentParseMode::Aliases => for answer in &frame.answers {
-            let label = DnsLabel::parse(&mut Cursor::new(&answer.data)).unwrap();
-            let alias = label.build_cstring(&buf).unwrap();
-            aliases.push(alias.into_raw());
-        },
-    }
+        HostentParseMode::Aliases => {
+            if answers[0].rtype() != RecordType::Ns {
+                return Err(ARES_EBADRESP);
+            }
+            let name = answers[0].name.build_cstring(buf).ok_or(ARES_EBADRESP)?;
+            // Every name is built before any of them turns into a raw pointer, so that a bad one leaks nothing
+            let ns_names = answers.iter()
+                .map(|answer| DnsLabel::parse(&mut Cursor::new(&answer.data))?.build_cstring(buf))
+                .collect::<Option<Vec<CString>>>();
+            aliases = ns_names.ok_or(ARES_EBADRESP)?.into_iter().map(CString::into_raw).collect();
+            // Glue of the first family found only, a hostent holding addresses of one family
+            let glue = replies::parse_ns_glue(buf).unwrap_or_default();
+            let ipv6 = glue.first().is_some_and(|(_, addr)| addr.is_ipv6());
+            let mut seen_glue: Vec<IpAddr> = vec![];
+            for (_, addr) in glue.into_iter().filter(|(_, addr)| addr.is_ipv6() == ipv6) {
+                if !seen_glue.contains(&addr) {
+                    seen_glue.push(addr);
+                    addr_list.push(malloc_addr(&ip_octets(&addr)));
+                }
+            }
+            if ipv6 { (name, libc::AF_INET6, 16) } else { (name, libc::AF_INET, 4) }
+        }
+    };
 
     let ret = libc::hostent {
         h_name: name.into_raw(),
         h_aliases: unsafe { cnullterminated::from_vec(aliases) },
         h_addrtype,
-        h_length: answer.data.len() as i32,
+        h_length,
         h_addr_list:  unsafe { cnullterminated::from_vec(addr_list) },
     };
     Ok(ret)
 }
 
+/// Hostent of a reverse lookup: every PTR name as an alias, the first one also as h_name,
+/// and the queried `addr` of `family` as the only address
+pub unsafe fn parse_ptr_hostent(abuf: *const u8, alen: c_int, addr: &[u8], family: c_int) -> Result<libc::hostent, i32> {
+    let h_length = match family {
+        libc::AF_INET => 4,
+        libc::AF_INET6 => 16,
+        _ => return Err(ARES_EBADFAMILY),
+    };
+    if addr.len() != h_length {
+        return Err(ARES_EBADFAMILY);
+    }
+    let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
+    let frame = DnsFrame::parse(&mut Cursor::new(buf)).ok_or(ARES_EBADRESP)?;
+
+    // Classless delegations (RFC 2317) put a CNAME ahead of the PTR records
+    let mut names: Vec<CString> = vec![];
+    for answer in frame.answers.iter().filter(|answer| answer.rtype() == RecordType::Ptr) {
+        let label = DnsLabel::parse(&mut Cursor::new(&answer.data)).ok_or(ARES_EBADRESP)?;
+        names.push(label.build_cstring(buf).ok_or(ARES_EBADRESP)?);
+    }
+    let Some(name) = names.first().cloned() else { return Err(ARES_ENODATA) };
+    Ok(libc::hostent {
+        h_name: name.into_raw(),
+        h_aliases: unsafe { cnullterminated::from_vec(names.into_iter().map(CString::into_raw).collect()) },
+        h_addrtype: family,
+        h_length: h_length as c_int,
+        h_addr_list: unsafe { cnullterminated::from_vec(vec![malloc_addr(addr)]) },
+    })
+}
+
+/// Releases the lists of a hostent that failed halfway through
+fn free_pointers(aliases: Vec<*mut i8>, addr_list: Vec<*mut i8>) {
+    for alias in aliases {
+        drop(unsafe { CString::from_raw(alias) });
+    }
+    for addr in addr_list {
+        unsafe { libc::free(addr as *mut c_void) };
+    }
+}
+
+fn ip_octets(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+/// Copies an address into h_addr_list storage, released with free()
+fn malloc_addr(octets: &[u8]) -> *mut i8 {
+    let dst = unsafe { libc::malloc(octets.len()) } as *mut u8;
+    unsafe { std::ptr::copy_nonoverlapping(octets.as_ptr(), dst, octets.len()) };
+    dst as *mut i8
+}
+
+/// Hostent for addresses known without a lookup, all of a single family
+pub fn build_hostent(name: &str, addrs: &[IpAddr]) -> Option<libc::hostent> {
+    let h_addrtype = if addrs.first()?.is_ipv6() { libc::AF_INET6 } else { libc::AF_INET };
+    let name = CString::new(name).ok()?;
+    let mut addr_list: Vec<*mut i8> = vec![];
+    for addr in addrs {
+        addr_list.push(malloc_addr(&ip_octets(addr)));
+    }
+    Some(libc::hostent {
+        h_name: name.into_raw(),
+        h_aliases: unsafe { cnullterminated::from_vec(vec![]) },
+        h_addrtype,
+        h_length: if h_addrtype == libc::AF_INET6 { 16 } else { 4 },
+        h_addr_list: unsafe { cnullterminated::from_vec(addr_list) },
+    })
+}
+
 pub unsafe fn free_hostent(hostent: *mut libc::hostent) {
+    unsafe { free_hostent_fields(&Box::from_raw(hostent)) };
+}
+
+unsafe fn free_hostent_fields(hostent: &libc::hostent) {
     unsafe {
-        let hostent = Box::from_raw(hostent);
         drop(CString::from_raw(hostent.h_name));
         let vec = cnullterminated::into_vec(hostent.h_aliases);
         for v in vec { drop(CString::from_raw(v)); }
@@ -69,3 +175,236 @@ pub unsafe fn free_hostent(hostent: *mut libc::hostent) {
         for v in vec { libc::free(v as *mut c_void) }
     }
 }
+
+/// Owned hostent for Rust callers, released along with its lists on drop
+pub struct HostEnt(libc::hostent);
+
+impl HostEnt {
+    /// Parses a reply the way the ares_parse_*_reply functions do, failing with an ARES_* status
+    pub fn parse(buf: &[u8], mode: HostentParseMode) -> Result<HostEnt, i32> {
+        unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, mode) }.map(HostEnt)
+    }
+    /// The name, empty if it isn't valid UTF-8
+    pub fn name(&self) -> &str {
+        unsafe { CStr::from_ptr(self.0.h_name) }.to_str().unwrap_or("")
+    }
+    /// Aliases that are valid UTF-8, in reply order
+    pub fn aliases(&self) -> impl Iterator<Item = &str> + '_ {
+        let aliases = self.0.h_aliases;
+        (0..).map(move |i| unsafe { *aliases.add(i) })
+            .take_while(|alias| !alias.is_null())
+            .filter_map(|alias| unsafe { CStr::from_ptr(alias) }.to_str().ok())
+    }
+    pub fn addrs(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        let (addr_list, ipv6) = (self.0.h_addr_list, self.0.h_addrtype == libc::AF_INET6);
+        (0..).map(move |i| unsafe { *addr_list.add(i) })
+            .take_while(|addr| !addr.is_null())
+            .map(move |addr| match ipv6 {
+                true => IpAddr::from(unsafe { *(addr as *const [u8; 16]) }),
+                false => IpAddr::from(unsafe { *(addr as *const [u8; 4]) }),
+            })
+    }
+}
+
+impl Drop for HostEnt {
+    fn drop(&mut self) {
+        unsafe { free_hostent_fields(&self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::{ dangling_second_name_reply, live_allocations };
+    use std::net::Ipv6Addr;
+
+    unsafe fn addr_list(hostent: &libc::hostent) -> Vec<Vec<u8>> {
+        let mut out = vec![];
+        let mut ptr = hostent.h_addr_list;
+        while unsafe { !(*ptr).is_null() } {
+            out.push(unsafe { std::slice::from_raw_parts(*ptr as *const u8, hostent.h_length as usize) }.to_vec());
+            ptr = unsafe { ptr.add(1) };
+        }
+        out
+    }
+
+    #[test]
+    fn test_parse_aaaa_hostent() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x02v6\x07example\x00\x00\x1c\x00\x01"[..],
+            b"\xc0\x0c\x00\x1c\x00\x01\x00\x00\x01\x2c\x00\x10\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01",
+        ].concat();
+        let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Addrs) }.unwrap();
+        assert_eq!(hostent.h_addrtype, libc::AF_INET6);
+        assert_eq!(hostent.h_length, 16);
+        unsafe {
+            assert_eq!(CStr::from_ptr(hostent.h_name).to_str(), Ok("v6.example"));
+            let addr: [u8; 16] = std::slice::from_raw_parts(*hostent.h_addr_list as *const u8, 16).try_into().unwrap();
+            assert_eq!(Ipv6Addr::from(addr), "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+            assert!((*hostent.h_addr_list.add(1)).is_null());
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+    }
+
+    #[test]
+    fn test_parse_hostent_deduplicates_addrs() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x02lb\x07example\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Addrs) }.unwrap();
+        unsafe {
+            assert_eq!(addr_list(&hostent), vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+    }
+
+    #[test]
+    fn test_parse_hostent_rejects_bad_aaaa_length() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x02v6\x07example\x00\x00\x1c\x00\x01"[..],
+            b"\xc0\x0c\x00\x1c\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let result = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Addrs) };
+        assert_eq!(result.err(), Some(ARES_EFORMERR));
+    }
+
+    #[test]
+    fn test_parse_hostent_skips_opt_among_answers() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x02lb\x07example\x00\x00\x01\x00\x01"[..],
+            b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00",
+        ].concat();
+        let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Addrs) }.unwrap();
+        unsafe {
+            assert_eq!(CStr::from_ptr(hostent.h_name).to_str(), Ok("lb.example"));
+            assert_eq!(addr_list(&hostent), vec![vec![1, 2, 3, 4]]);
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+    }
+
+    #[test]
+    fn test_parse_hostent_cname_chain_aliases() {
+        // The A record's owner points back into the second CNAME's rdata
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x06\x03cdn\xc0\x10",
+            b"\xc0\x2d\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x07\x04edge\xc0\x2d",
+            b"\xc0\x3f\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+        ].concat();
+        let hostent = HostEnt::parse(&buf, HostentParseMode::Addrs).unwrap();
+        assert_eq!(hostent.name(), "edge.cdn.example.com");
+        assert_eq!(hostent.aliases().collect::<Vec<_>>(), vec!["www.example.com", "cdn.example.com"]);
+        assert_eq!(hostent.addrs().collect::<Vec<_>>(), vec!["1.2.3.4".parse::<IpAddr>().unwrap()]);
+        assert_eq!(HostEnt::parse(&buf, HostentParseMode::Addrs6).err(), Some(ARES_ENODATA));
+    }
+
+    #[test]
+    fn test_parse_hostent_names_canonical_host() {
+        // Addresses listed ahead of the CNAME leading to them
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x03www\x07example\x00\x00\x01\x00\x01"[..],
+            b"\x03cdn\x07example\x00\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x02\xc0\x1d",
+            b"\xc0\x1d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+        ].concat();
+        let hostent = HostEnt::parse(&buf, HostentParseMode::Addrs4).unwrap();
+        assert_eq!(hostent.name(), "cdn.example");
+        assert_eq!(hostent.aliases().collect::<Vec<_>>(), vec!["www.example"]);
+        assert_eq!(hostent.addrs().count(), 2);
+    }
+
+    #[test]
+    fn test_parse_hostent_drops_unrelated_owners() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x03\x00\x00\x00\x00\x02lb\x07example\x00\x00\x01\x00\x01"[..],
+            b"\x04evil\x04test\x00\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x06\x06\x06\x06",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x21\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x06\x06\x06\x07",
+        ].concat();
+        let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Addrs) }.unwrap();
+        unsafe {
+            assert_eq!(CStr::from_ptr(hostent.h_name).to_str(), Ok("lb.example"));
+            assert_eq!(addr_list(&hostent), vec![vec![1, 2, 3, 4]]);
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+    }
+
+    #[test]
+    fn test_parse_ns_hostent_with_glue() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x02\x07example\x00\x00\x02\x00\x01"[..],
+            b"\xc0\x0c\x00\x02\x00\x01\x00\x00\x01\x2c\x00\x06\x03ns1\xc0\x0c",
+            b"\xc0\x0c\x00\x02\x00\x01\x00\x00\x01\x2c\x00\x06\x03ns2\xc0\x0c",
+            b"\xc0\x25\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\xc0\x00\x02\x01",
+            b"\xc0\x37\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\xc0\x00\x02\x02",
+        ].concat();
+        let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Aliases) }.unwrap();
+        assert_eq!(hostent.h_addrtype, libc::AF_INET);
+        assert_eq!(hostent.h_length, 4);
+        unsafe {
+            let aliases: Vec<&str> = (0..).map(|i| *hostent.h_aliases.add(i))
+                .take_while(|alias| !alias.is_null())
+                .map(|alias| CStr::from_ptr(alias).to_str().unwrap())
+                .collect();
+            assert_eq!(aliases, vec!["ns1.example", "ns2.example"]);
+            assert_eq!(addr_list(&hostent), vec![vec![192, 0, 2, 1], vec![192, 0, 2, 2]]);
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+    }
+
+    #[test]
+    fn test_parse_ns_hostent_failure_frees_earlier_aliases() {
+        let buf = dangling_second_name_reply(2, b"");
+        let before = live_allocations();
+        let result = unsafe { parse_hostent(buf.as_ptr(), buf.len() as c_int, HostentParseMode::Aliases) };
+        assert_eq!(result.err(), Some(ARES_EBADRESP));
+        assert_eq!(live_allocations(), before);
+    }
+
+    #[test]
+    fn test_parse_ptr_hostent() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x014\x013\x012\x011\x07in-addr\x04arpa\x00\x00\x0c\x00\x01"[..],
+            b"\xc0\x0c\x00\x0c\x00\x01\x00\x00\x01\x2c\x00\x0d\x03one\x07example\x00",
+            b"\xc0\x0c\x00\x0c\x00\x01\x00\x00\x01\x2c\x00\x06\x03two\xc0\x36",
+        ].concat();
+        let addr = [1, 2, 3, 4];
+        let hostent = unsafe { parse_ptr_hostent(buf.as_ptr(), buf.len() as c_int, &addr, libc::AF_INET) }.unwrap();
+        assert_eq!(hostent.h_addrtype, libc::AF_INET);
+        unsafe {
+            assert_eq!(CStr::from_ptr(hostent.h_name).to_str(), Ok("one.example"));
+            let aliases: Vec<&str> = (0..).map(|i| *hostent.h_aliases.add(i))
+                .take_while(|alias| !alias.is_null())
+                .map(|alias| CStr::from_ptr(alias).to_str().unwrap())
+                .collect();
+            assert_eq!(aliases, vec!["one.example", "two.example"]);
+            assert_eq!(addr_list(&hostent), vec![vec![1, 2, 3, 4]]);
+            free_hostent(Box::into_raw(Box::new(hostent)));
+        }
+        let result = unsafe { parse_ptr_hostent(buf.as_ptr(), buf.len() as c_int, &addr, libc::AF_INET6) };
+        assert_eq!(result.err(), Some(ARES_EBADFAMILY));
+    }
+
+    #[test]
+    fn test_hostent_wrapper_frees_on_drop() {
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x02lb\x07example\x00\x00\x01\x00\x01"[..],
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x01\x02\x03\x04",
+            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x05\x06\x07\x08",
+        ].concat();
+        let before = live_allocations();
+        let hostent = HostEnt::parse(&buf, HostentParseMode::Addrs).unwrap();
+        assert_eq!(hostent.name(), "lb.example");
+        assert_eq!(hostent.aliases().count(), 0);
+        let addrs: Vec<IpAddr> = hostent.addrs().collect();
+        assert_eq!(addrs, vec!["1.2.3.4".parse::<IpAddr>().unwrap(), "5.6.7.8".parse().unwrap()]);
+        drop(addrs);
+        drop(hostent);
+        assert_eq!(live_allocations(), before);
+    }
+}
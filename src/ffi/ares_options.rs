@@ -4,7 +4,8 @@
 use libc::{in_addr};
 use crate::ffi::Channel;
 use std::net::{ IpAddr, Ipv4Addr };
-use std::ffi::{c_char, c_int, c_uint, c_ushort, c_void};
+use std::ffi::{c_char, c_int, c_uint, c_ushort, c_void, CStr};
+use crate::core::sysconfig::MAX_NDOTS;
 use crate::ffi::error::*;
 use crate::ares_socket_t;
 
@@ -117,6 +118,19 @@ pub const ARES_OPT_QUERY_CACHE    : c_int = 1 << 21;
 pub const ARES_OPT_EVENT_THREAD   : c_int = 1 << 22;
 pub const ARES_OPT_SERVER_FAILOVER: c_int = 1 << 23;
 
+pub const ARES_FLAG_USEVC         : c_int = 1 << 0;
+pub const ARES_FLAG_PRIMARY       : c_int = 1 << 1;
+pub const ARES_FLAG_IGNTC         : c_int = 1 << 2;
+pub const ARES_FLAG_NORECURSE     : c_int = 1 << 3;
+pub const ARES_FLAG_STAYOPEN      : c_int = 1 << 4;
+pub const ARES_FLAG_NOSEARCH      : c_int = 1 << 5;
+pub const ARES_FLAG_NOALIASES     : c_int = 1 << 6;
+pub const ARES_FLAG_NOCHECKRESP   : c_int = 1 << 7;
+pub const ARES_FLAG_EDNS          : c_int = 1 << 8;
+pub const ARES_FLAG_NO_DFLT_SVR   : c_int = 1 << 9;
+#[allow(non_upper_case_globals)]
+pub const ARES_FLAG_DNS0x20       : c_int = 1 << 10;
+
 #[no_mangle]
 pub unsafe extern "C" fn ares_init_options(channel: Channel, options: *const ares_options, optmask: c_int) -> c_int {
     let channeldata = unsafe { &mut *channel };
@@ -135,5 +149,22 @@ pub unsafe extern "C" fn ares_init_options(channel: Channel, options: *const are
     if optmask & ARES_OPT_TCP_PORT != 0 {
         channeldata.ares.default_tcp_port = options.tcp_port;
     }
+    if optmask & ARES_OPT_FLAGS != 0 && options.flags & ARES_FLAG_EDNS != 0 {
+        channeldata.ares.config.options.edns0 = true;
+    }
+    if optmask & ARES_OPT_NDOTS != 0 {
+        channeldata.ares.config.options.ndots = options.ndots.clamp(0, MAX_NDOTS as c_int) as u32;
+    }
+    if optmask & ARES_OPT_DOMAINS != 0 && !options.domains.is_null() {
+        if options.ndomains < 0 {
+            return ARES_EBADFLAGS;
+        }
+        let domains = unsafe { std::slice::from_raw_parts(options.domains, options.ndomains as usize) };
+        channeldata.ares.config.search = domains.iter().map(|domain| unsafe { CStr::from_ptr(*domain) }.to_string_lossy().into_owned()).collect();
+    }
+    if optmask & ARES_OPT_EDNSPSZ != 0 {
+        // Under 512 would advertise less than plain DNS guarantees
+        channeldata.ares.edns_udp_size = options.ednspsz.clamp(512, u16::MAX as c_int) as u16;
+    }
     ARES_SUCCESS
 }
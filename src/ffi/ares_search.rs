@@ -0,0 +1,155 @@
+use std::cell::{ Cell, RefCell };
+use std::collections::VecDeque;
+use std::ffi::{ c_char, c_int, c_void, CStr };
+use std::os::fd::AsRawFd;
+use std::rc::Rc;
+
+use crate::core::error::Rcode;
+use crate::core::packets::DnsFrame;
+use crate::ffi::{ AresCallback, Callback, Channel, FFIData };
+use crate::ffi::error::*;
+
+/// One ares_search call, querying its candidate names in turn until one of them has records
+#[derive(Debug)]
+pub struct SearchLookup {
+    channel: Channel,
+    candidates: RefCell<VecDeque<String>>,
+    dnsclass: u16,
+    dnstype: u16,
+    callback: AresCallback,
+    arg: *mut c_void,
+    /// A candidate exists without records of the type, ENODATA then being reported over ENOTFOUND
+    got_nodata: Cell<bool>,
+    timeouts: Cell<c_int>,
+}
+
+impl SearchLookup {
+    pub(crate) fn reply(self: &Rc<Self>, buf: Vec<u8>, frame: DnsFrame, timeouts: u32) {
+        let status = match Rcode::from_flags(frame.flags) {
+            Some(rcode) => ares_status(rcode.into()),
+            None if frame.answers.is_empty() => ARES_ENODATA,
+            None => ARES_SUCCESS,
+        };
+        self.timeouts.set(self.timeouts.get() + timeouts as c_int);
+        match status {
+            ARES_SUCCESS => unsafe { (self.callback)(self.arg, status, self.timeouts.get(), buf.as_ptr() as *mut u8, buf.len() as c_int) },
+            status => self.try_next(status),
+        }
+    }
+    pub(crate) fn fail(self: &Rc<Self>, status: c_int, timeouts: u32) {
+        self.timeouts.set(self.timeouts.get() + timeouts as c_int);
+        self.try_next(status);
+    }
+    /// Moves on to the next candidate when `status` says the name didn't resolve, otherwise reports it
+    fn try_next(self: &Rc<Self>, status: c_int) {
+        if status == ARES_ENODATA {
+            self.got_nodata.set(true);
+        }
+        let next = match status {
+            ARES_ENODATA | ARES_ENOTFOUND | ARES_ESERVFAIL => self.candidates.borrow_mut().pop_front(),
+            _ => None,
+        };
+        let Some(name) = next else {
+            let status = if self.got_nodata.get() && status != ARES_ECANCELLED { ARES_ENODATA } else { status };
+            return unsafe { (self.callback)(self.arg, status, self.timeouts.get(), std::ptr::null_mut(), 0) };
+        };
+        let channeldata = unsafe { &mut *self.channel };
+        let ffidata = FFIData { callback: Callback::Search(self.clone()), arg: self.arg };
+        match channeldata.ares.query(&name, self.dnsclass, self.dnstype, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+            Ok(fd) => channeldata.socket_created(fd),
+            Err(err) => self.fail(io_status(&err), 0),
+        }
+    }
+}
+
+/// Like ares_query, but trying `name` under each suffix of the search list, in the order the `ndots`
+/// option gives, and reporting ARES_ENOTFOUND only once every candidate failed. A trailing dot skips the suffixes
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_search(channel: Channel, name: *const c_char, dnsclass: c_int, dnstype: c_int, callback: AresCallback, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    let lookup = Rc::new(SearchLookup {
+        channel,
+        candidates: RefCell::new(channeldata.ares.config.search_candidates(&name).into()),
+        dnsclass: dnsclass as u16,
+        dnstype: dnstype as u16,
+        callback,
+        arg,
+        got_nodata: Cell::new(false),
+        timeouts: Cell::new(0),
+    });
+    lookup.try_next(ARES_ENOTFOUND);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::packets::DnsQuery;
+    use crate::core::testing::*;
+    use crate::ffi::ares_destroy;
+    use crate::cstr;
+    use std::io::Cursor;
+
+    unsafe extern "C" fn record_search(arg: *mut c_void, status: c_int, _timeouts: c_int, abuf: *mut u8, alen: c_int) {
+        let answer = match abuf.is_null() {
+            true => vec![],
+            false => unsafe { std::slice::from_raw_parts(abuf, alen as usize) }.to_vec(),
+        };
+        unsafe { (*(arg as *mut Vec<(c_int, Vec<u8>)>)).push((status, answer)) };
+    }
+
+    fn search(name: *const c_char) -> (Vec<(c_int, Vec<u8>)>, Vec<String>) {
+        let asked = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let log = asked.clone();
+        let server = StubServer::spawn(move |query| {
+            let question = DnsQuery::parse(&mut Cursor::new(&query[12..]))?.name.join(".");
+            log.lock().unwrap().push(question.clone());
+            match question.as_str() {
+                "host.example.test" => Some(reply(query, 0x8180, &[A_ANSWER])),
+                "empty.corp.test" => Some(reply(query, 0x8180, &[])),
+                _ => Some(reply(query, 0x8183, &[])),
+            }
+        });
+        let mut results: Vec<(c_int, Vec<u8>)> = vec![];
+        unsafe {
+            let channel = channel_for(&server);
+            (*channel).ares.config.search = vec!["corp.test".to_string(), "example.test".to_string()];
+            let arg = &mut results as *mut _ as *mut c_void;
+            ares_search(channel, name, 1, 1, record_search, arg);
+            while results.is_empty() {
+                pump_once(channel);
+            }
+            ares_destroy(channel);
+        }
+        let asked = asked.lock().unwrap().clone();
+        (results, asked)
+    }
+
+    #[test]
+    fn test_search_tries_suffixes_in_order() {
+        let (results, asked) = search(cstr!("host"));
+        assert_eq!(asked, vec!["host.corp.test", "host.example.test"]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, ARES_SUCCESS);
+        assert!(results[0].1.ends_with(b"\x01\x02\x03\x04"));
+    }
+
+    #[test]
+    fn test_search_reports_not_found_after_every_candidate() {
+        let (results, asked) = search(cstr!("missing"));
+        assert_eq!(asked, vec!["missing.corp.test", "missing.example.test", "missing"]);
+        assert_eq!(results, vec![(ARES_ENOTFOUND, vec![])]);
+
+        let (results, asked) = search(cstr!("empty"));
+        assert_eq!(asked, vec!["empty.corp.test", "empty.example.test", "empty"]);
+        assert_eq!(results, vec![(ARES_ENODATA, vec![])]);
+    }
+
+    #[test]
+    fn test_search_skips_suffixes_for_fqdn() {
+        let (results, asked) = search(cstr!("host.example.test."));
+        assert_eq!(asked, vec!["host.example.test"]);
+        assert_eq!(results[0].0, ARES_SUCCESS);
+    }
+}
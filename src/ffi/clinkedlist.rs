@@ -11,6 +11,17 @@ pub fn chain_nodes<T>(mut elts: Vec<T>) -> T where T: CLinkedList {
     tail
 }
 
+/// Walks a list from `head` (possibly null) on, e.g. one handed out by the library.
+/// The list must stay alive and unchanged for as long as the returned references are used
+pub unsafe fn iter_clinked<'a, T>(head: *mut T) -> impl Iterator<Item = &'a T> where T: CLinkedList + 'a {
+    let mut cursor = head;
+    std::iter::from_fn(move || {
+        let node = unsafe { cursor.as_mut() }?;
+        cursor = *node.next();
+        Some(&*node)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,4 +54,18 @@ mod tests {
             assert_eq!(head.next, std::ptr::null_mut());
         }
     }
+
+    #[test]
+    fn test_iter_clinked() {
+        let vec = vec![DummyNode::new(1), DummyNode::new(2), DummyNode::new(3)];
+        let head = Box::into_raw(Box::new(chain_nodes(vec)));
+        let nums: Vec<u8> = unsafe { iter_clinked(head) }.map(|node| node.num).collect();
+        assert_eq!(nums, vec![1, 2, 3]);
+        assert_eq!(unsafe { iter_clinked::<DummyNode>(std::ptr::null_mut()) }.count(), 0);
+        let mut cursor = head;
+        while !cursor.is_null() {
+            let node = unsafe { Box::from_raw(cursor) };
+            cursor = node.next;
+        }
+    }
 }
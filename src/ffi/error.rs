@@ -1,5 +1,7 @@
 use std::ffi::{ c_int, c_char };
+use std::io;
 use crate::cstr;
+use crate::core::error::{ DnsError, Rcode };
 
 pub const ARES_SUCCESS: c_int = 0;
 pub const ARES_ENODATA: c_int = 1;
@@ -58,3 +60,27 @@ pub extern "C" fn ares_strerror(code: c_int) -> *const c_char {
         _ => cstr!("unknown"),
     }
 }
+
+pub fn ares_status(err: DnsError) -> c_int {
+    match err {
+        DnsError::BadResponse => ARES_EBADRESP,
+        DnsError::NoData => ARES_ENODATA,
+        DnsError::NotFound => ARES_ENOTFOUND,
+        DnsError::Timeout => ARES_ETIMEOUT,
+        DnsError::Transport(io::ErrorKind::ConnectionRefused) => ARES_ECONNREFUSED,
+        DnsError::Transport(_) => ARES_ESERVFAIL,
+        DnsError::Protocol(Rcode::FormErr) => ARES_EFORMERR,
+        DnsError::Protocol(Rcode::NxDomain) => ARES_ENOTFOUND,
+        DnsError::Protocol(Rcode::NotImp) => ARES_ENOTIMP,
+        DnsError::Protocol(Rcode::Refused) => ARES_EREFUSED,
+        DnsError::Protocol(Rcode::ServFail | Rcode::Other(_)) => ARES_ESERVFAIL,
+    }
+}
+
+/// Status for a failure to set up a query's socket
+pub fn io_status(err: &io::Error) -> c_int {
+    match err.raw_os_error() {
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => ARES_ENOMEM,
+        _ => ARES_ESERVFAIL,
+    }
+}
@@ -1,24 +1,35 @@
+mod ares_addrinfo;
 mod ares_data;
 mod ares_hostent;
 mod ares_options;
+mod ares_search;
 mod cnullterminated;
 mod cstr;
 mod clinkedlist;
-mod error;
+pub(crate) mod error;
 mod offset_of;
 
-use std::ffi::{ c_int, c_void, c_char };
+use std::ffi::{ c_int, c_void, c_char, c_ushort };
 use std::os::fd::{ AsRawFd };
 use std::ffi::{ CString, CStr };
 use std::io::Cursor;
-use std::net::IpAddr;
+use std::net::{ IpAddr, Ipv6Addr };
 use std::cmp::min;
+use std::rc::Rc;
 use crate::core::packets::*;
-use crate::core::ares::{ Ares, Status, Family };
+use crate::core::ares::{ Ares, Task, Status, Family };
 use crate::core::servers_csv;
+use crate::core::socket::{ SocketFunctions, SocketHooks };
+use crate::core::sysconfig::{ dedup_nameservers, format_ns_addr, ns_socket_addr, parse_ns_addr };
+use crate::core::replies;
+use crate::core::error::Rcode;
 use crate::ffi::ares_hostent::*;
+pub use crate::ffi::ares_hostent::{ HostEnt, HostentParseMode };
 use crate::ffi::ares_data::*;
+use crate::ffi::ares_addrinfo::AddrinfoLookup;
+use crate::ffi::ares_search::SearchLookup;
 use crate::ffi::clinkedlist::*;
+use crate::ffi::error::{ ARES_EBADNAME, ARES_EBADQUERY, ARES_EBADSTR, ARES_EBADFAMILY, ARES_EBADRESP, ARES_EBADFLAGS, ARES_ECANCELLED, ares_status, io_status };
 use crate::cstr;
 
 pub const ARES_SUCCESS: i32 = 0;
@@ -44,34 +55,101 @@ pub extern "C" fn ares_library_cleanup() {
 pub type Channel = *mut ChannelData;
 
 pub struct ChannelData {
-    ares: Ares<FFIData>,
+    pub(crate) ares: Ares<FFIData>,
     sock_create_callback: Option<AresSockCreateCallback>,
     sock_create_callback_arg: *mut libc::c_void,
+    retry_callback: Option<AresRetryCallback>,
+    retry_callback_arg: *mut libc::c_void,
+    servers_down_callback: Option<AresServersDownCallback>,
+    servers_down_callback_arg: *mut libc::c_void,
+    /// Ids of the unfinished queries ares_process holds outside `ares.tasks` while running callbacks,
+    /// so that ares_cancel and ares_cancel_query called from those callbacks still reach them
+    processing: Vec<c_ushort>,
+    /// Those of `processing` cancelled by a callback, reported as soon as that callback returns
+    cancelled: Vec<c_ushort>,
 }
 
-#[derive(Debug)]
+impl ChannelData {
+    /// Reports the socket of a task just created, which every task-creating entry point must do
+    fn socket_created(&self, fd: c_int) {
+        if let Some(cb) = self.sock_create_callback {
+            unsafe { cb(fd, libc::SOCK_DGRAM, self.sock_create_callback_arg) };
+        }
+    }
+    /// Hands a finished task's reply over, or the final error if it ran out of tries on server failures
+    fn deliver(&mut self, task: &Task<FFIData>, buf: Vec<u8>, frame: DnsFrame) {
+        match task.final_error(&frame) {
+            Some(err) => self.fail(task, ares_status(err)),
+            None => {
+                self.processing.retain(|qid| *qid != task.request.transaction_id);
+                (task.userdata.callback).run(buf, frame, &task.userdata, task.timeouts);
+            }
+        }
+    }
+    /// Moves the tasks out for ares_process to run their callbacks, which may queue new ones meanwhile
+    fn take_tasks(&mut self) -> Vec<Task<FFIData>> {
+        let tasks = std::mem::take(&mut self.ares.tasks);
+        self.processing = tasks.iter().filter(|task| !task.is_done()).map(|task| task.request.transaction_id).collect();
+        tasks
+    }
+    /// Puts back the tasks of `take_tasks`, ahead of those queued since
+    fn restore_tasks(&mut self, tasks: Vec<Task<FFIData>>) {
+        self.processing.clear();
+        let added = std::mem::replace(&mut self.ares.tasks, tasks);
+        self.ares.tasks.extend(added);
+    }
+    /// Reports ARES_ECANCELLED to the tasks of `tasks` that callbacks cancelled while ares_process held them
+    fn run_cancelled(&mut self, tasks: &mut [Task<FFIData>]) {
+        while !self.cancelled.is_empty() {
+            let qid = self.cancelled.remove(0);
+            let Some(task) = tasks.iter_mut().find(|task| task.status != Status::Cancelled && task.request.transaction_id == qid) else { continue };
+            task.status = Status::Cancelled;
+            (task.userdata.callback).run_error(ARES_ECANCELLED, task.userdata.arg, task.timeouts);
+        }
+    }
+    /// Reports a task out of tries, notifying first if every configured server failed it
+    fn fail(&mut self, task: &Task<FFIData>, status: c_int) {
+        self.processing.retain(|qid| *qid != task.request.transaction_id);
+        if let Some(cb) = self.servers_down_callback.filter(|_| self.ares.all_servers_failed(task)) {
+            unsafe { cb(self.servers_down_callback_arg, task.request.transaction_id, status) };
+        }
+        (task.userdata.callback).run_error(status, task.userdata.arg, task.timeouts);
+    }
+}
+
+#[derive(Debug, Clone)]
 enum Callback {
     AresHostCallback(AresHostCallback),
     AresCallback(AresCallback),
+    /// One of the queries of an ares_getaddrinfo lookup
+    Addrinfo(Rc<AddrinfoLookup>),
+    /// The query for the current candidate name of an ares_search call
+    Search(Rc<SearchLookup>),
 }
 
 impl Callback {
-    fn run(&self, buf: Vec<u8>, result: DnsFrame, ffidata: &FFIData) {
+    fn run(&self, buf: Vec<u8>, result: DnsFrame, ffidata: &FFIData, timeouts: u32) {
+        let timeouts = timeouts as c_int;
         match self {
-            Self::AresHostCallback(callback) => run_ares_host_callback(buf, result, *callback, ffidata.arg),
-            Self::AresCallback(callback) => run_ares_callback(buf, result, *callback, ffidata.arg),
+            Self::AresHostCallback(callback) => run_ares_host_callback(buf, result, *callback, ffidata.arg, timeouts),
+            Self::AresCallback(callback) => run_ares_callback(buf, result, *callback, ffidata.arg, timeouts),
+            Self::Addrinfo(lookup) => lookup.reply(buf, result, timeouts as u32),
+            Self::Search(lookup) => lookup.reply(buf, result, timeouts as u32),
         }
     }
-    fn run_error(&self, status: i32, arg: *mut c_void) {
+    fn run_error(&self, status: i32, arg: *mut c_void, timeouts: u32) {
+        let timeouts = timeouts as c_int;
         match self {
-            Self::AresHostCallback(callback) => unsafe { callback(arg, status, 0, std::ptr::null_mut()) },
-            Self::AresCallback(callback) => unsafe { callback(arg, status, 0, std::ptr::null_mut(), 0) },
+            Self::AresHostCallback(callback) => unsafe { callback(arg, status, timeouts, std::ptr::null_mut()) },
+            Self::AresCallback(callback) => unsafe { callback(arg, status, timeouts, std::ptr::null_mut(), 0) },
+            Self::Addrinfo(lookup) => lookup.fail(status, timeouts as u32),
+            Self::Search(lookup) => lookup.fail(status, timeouts as u32),
         }
     }
 }
 
 #[derive(Debug)]
-struct FFIData {
+pub(crate) struct FFIData {
     callback: Callback,
     arg: *mut c_void,
 }
@@ -83,11 +161,58 @@ pub struct ares_addr_node {
     pub data: [u8; 16], // enough to hold IPv6
 }
 
+impl ares_addr_node {
+    /// The address held for an AF_INET (first 4 bytes of `data`) or AF_INET6 node, None for another family
+    pub fn as_ip(&self) -> Option<IpAddr> {
+        match self.family {
+            libc::AF_INET => Some(IpAddr::from(<[u8; 4]>::try_from(&self.data[0..4]).unwrap())),
+            libc::AF_INET6 => Some(IpAddr::from(self.data)),
+            _ => None,
+        }
+    }
+}
+
+impl From<IpAddr> for ares_addr_node {
+    /// Unlinked node holding `ip`
+    fn from(ip: IpAddr) -> ares_addr_node {
+        let mut data = [0u8; 16];
+        let family = match ip {
+            IpAddr::V4(ip) => {
+                data[0..4].copy_from_slice(&ip.octets());
+                libc::AF_INET
+            }
+            IpAddr::V6(ip) => {
+                data = ip.octets();
+                libc::AF_INET6
+            }
+        };
+        ares_addr_node { next: std::ptr::null_mut(), family, data }
+    }
+}
+
+impl CLinkedList for ares_addr_node {
+    fn next(&mut self) -> &mut *mut Self { &mut self.next }
+}
+
+impl DataType for ares_addr_node {
+    fn datatype() -> AresDataType { AresDataType::AddrNode }
+}
+
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_init(out_channel: *mut Channel) -> c_int {
     let ares = Ares::from_sysconfig();
-    let channeldata = ChannelData { ares, sock_create_callback: None, sock_create_callback_arg: std::ptr::null_mut() };
+    let channeldata = ChannelData {
+        ares,
+        sock_create_callback: None,
+        sock_create_callback_arg: std::ptr::null_mut(),
+        retry_callback: None,
+        retry_callback_arg: std::ptr::null_mut(),
+        servers_down_callback: None,
+        servers_down_callback_arg: std::ptr::null_mut(),
+        processing: vec![],
+        cancelled: vec![],
+    };
     let channel = Box::into_raw(Box::new(channeldata));
     unsafe { *out_channel = channel };
     ARES_SUCCESS
@@ -106,32 +231,91 @@ pub unsafe extern "C" fn ares_gethostbyname(channel: Channel, hostname: *const c
     let family = match family {
         libc::AF_INET => Family::Ipv4,
         libc::AF_INET6 => Family::Ipv6,
+        libc::AF_UNSPEC => Family::Unspec,
         _ => panic!("unexpected family value: {}", family),
     };
     let hostname = unsafe { CStr::from_ptr(hostname).to_string_lossy() };
+    if let Some(addrs) = channeldata.ares.static_host(&hostname, family) {
+        let Some(hostent) = build_hostent(&hostname, &addrs) else {
+            return unsafe { callback(arg, ARES_EBADNAME, 0, std::ptr::null_mut()) };
+        };
+        let hostent = Box::into_raw(Box::new(hostent));
+        unsafe { callback(arg, ARES_SUCCESS, 0, &mut *hostent) };
+        return unsafe { ares_free_hostent(hostent) };
+    }
     let ffidata = FFIData { callback: Callback::AresHostCallback(callback), arg };
-    let newtask = channeldata.ares.gethostbyname(&hostname, family, ffidata);
-    if let Some(cb) = channeldata.sock_create_callback {
-        cb(newtask.sock.as_raw_fd(), libc::SOCK_DGRAM, channeldata.sock_create_callback_arg);
+    match channeldata.ares.gethostbyname(&hostname, family, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+        Ok(fd) => channeldata.socket_created(fd),
+        Err(err) => Callback::AresHostCallback(callback).run_error(io_status(&err), arg, 0),
     }
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_query(channel: Channel, name: *const c_char, dnsclass: c_int, dnstype: c_int, callback: AresCallback, arg: *mut c_void) {
     let channeldata = unsafe { &mut *channel };
     let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
     let ffidata = FFIData { callback: Callback::AresCallback(callback), arg };
-    channeldata.ares.query(&name, dnsclass as u16, dnstype as u16, ffidata);
+    match channeldata.ares.query(&name, dnsclass as u16, dnstype as u16, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+        Ok(fd) => channeldata.socket_created(fd),
+        Err(err) => Callback::AresCallback(callback).run_error(io_status(&err), arg, 0),
+    }
+}
+
+/// Like ares_query, but sent to `server` ("ip", "ip:port" or "[ipv6]:port") instead of the channel's servers
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_query_server(channel: Channel, server: *const c_char, name: *const c_char, dnsclass: c_int, dnstype: c_int, callback: AresCallback, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    let server = unsafe { CStr::from_ptr(server).to_string_lossy() };
+    let Ok((ip, port)) = parse_ns_addr(server.trim()) else {
+        return Callback::AresCallback(callback).run_error(ARES_EBADSTR, arg, 0);
+    };
+    let server = ns_socket_addr(ip, port.unwrap_or(channeldata.ares.default_udp_port));
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    let ffidata = FFIData { callback: Callback::AresCallback(callback), arg };
+    match channeldata.ares.query_to(server, &name, dnsclass as u16, dnstype as u16, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+        Ok(fd) => channeldata.socket_created(fd),
+        Err(err) => Callback::AresCallback(callback).run_error(io_status(&err), arg, 0),
+    }
 }
 
+/// Sends the caller-built query of `qlen` bytes at `qbuf` unmodified, the raw reply going to `callback`
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_send(channel: Channel, qbuf: *const u8, qlen: c_int, callback: AresCallback, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    let qbuf = unsafe { std::slice::from_raw_parts(qbuf, qlen.max(0) as usize) };
+    let ffidata = FFIData { callback: Callback::AresCallback(callback), arg };
+    match channeldata.ares.send(qbuf, ffidata).map(|newtask| newtask.sock.as_raw_fd()) {
+        Ok(fd) => channeldata.socket_created(fd),
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => Callback::AresCallback(callback).run_error(ARES_EBADQUERY, arg, 0),
+        Err(err) => Callback::AresCallback(callback).run_error(io_status(&err), arg, 0),
+    }
+}
+
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_data<T1, T2>(abuf: *const u8, alen: c_int, out: *mut *mut T2) -> c_int
 where T1: Parser + IntoAresData<T2>, T2: CLinkedList + DataType
 {
     let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
-    let frame = DnsFrame::parse(&mut Cursor::new(buf)).unwrap();
-    let replies: Vec<T1> = frame.answers.into_iter().map(|x| T1::parse(&mut Cursor::new(&x.data)).unwrap()).collect();
-    let aresreplies: Vec<_> = replies.into_iter().map(|x| x.into_ares_data(&buf)).collect();
-    let reply = clinkedlist::chain_nodes(aresreplies);
+    let replies: Vec<T1> = match replies::parse_answers(buf) {
+        Ok(replies) => replies,
+        Err(err) => return ares_status(err),
+    };
+    // Nodes built before a failing one own their strings, so dropping the Vec reclaims them
+    let Some(aresreplies) = replies.into_iter().map(|x| x.into_ares_data(buf)).collect::<Option<Vec<T2>>>() else {
+        return ARES_EBADRESP;
+    };
+    unsafe { ares_data_out(aresreplies, out) }
+}
+
+/// Hands `nodes` over as one list, released with ares_free_data
+unsafe fn ares_data_out<T2>(nodes: Vec<T2>, out: *mut *mut T2) -> c_int where T2: CLinkedList + DataType {
+    if nodes.is_empty() {
+        return ARES_ENODATA;
+    }
+    let reply = clinkedlist::chain_nodes(nodes);
     let aresdata: AresData<T2> = AresData { data_type: T2::datatype(), data: reply };
     let aresdata = Box::into_raw(Box::new(aresdata));
     unsafe { *out = &mut (*aresdata).data };
@@ -139,47 +323,137 @@ where T1: Parser + IntoAresData<T2>, T2: CLinkedList + DataType
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_mx_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresMxReply) -> c_int {
     unsafe { ares_parse_data::<MxReply, AresMxReply>(abuf, alen, out) }
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_txt_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresTxtReply) -> c_int {
-    unsafe { ares_parse_data::<TxtReply, AresTxtReply>(abuf, alen, out) }
+    let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
+    match replies::parse_answers::<TxtReply>(buf) {
+        Ok(txts) => unsafe { ares_data_out(txts.into_iter().flat_map(AresTxtReply::from_segments).collect(), out) },
+        Err(err) => ares_status(err),
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_srv_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresSrvReply) -> c_int {
+    unsafe { ares_parse_data::<SrvReply, AresSrvReply>(abuf, alen, out) }
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_naptr_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresNaptrReply) -> c_int {
+    unsafe { ares_parse_data::<NaptrReply, AresNaptrReply>(abuf, alen, out) }
+}
+
+/// SVCB answers ordered by priority, AliasMode ones first
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_svcb_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresSvcbReply) -> c_int {
+    unsafe { parse_svcb_data::<SvcbReply>(abuf, alen, out) }
+}
+
+/// Same as ares_parse_svcb_reply, for HTTPS answers
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_https_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresSvcbReply) -> c_int {
+    unsafe { parse_svcb_data::<HttpsReply>(abuf, alen, out) }
+}
+
+unsafe fn parse_svcb_data<T>(abuf: *const u8, alen: c_int, out: *mut *mut AresSvcbReply) -> c_int where T: Parser + Into<SvcbReply> {
+    let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
+    let mut svcbs: Vec<SvcbReply> = match replies::parse_answers::<T>(buf) {
+        Ok(replies) => replies.into_iter().map(Into::into).collect(),
+        Err(err) => return ares_status(err),
+    };
+    svcbs.sort_by_key(|svcb| svcb.priority);
+    let Some(nodes) = svcbs.into_iter().map(|svcb| svcb.into_ares_data(buf)).collect::<Option<Vec<_>>>() else {
+        return ARES_EBADRESP;
+    };
+    unsafe { ares_data_out(nodes, out) }
+}
+
+/// The first SOA answer, as a single node rather than a list
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_soa_reply(abuf: *const u8, alen: c_int, out: *mut *mut AresSoaReply) -> c_int {
+    let buf = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
+    let soa = match replies::parse_answers::<SoaReply>(buf) {
+        Ok(mut soas) => soas.swap_remove(0),
+        Err(err) => return ares_status(err),
+    };
+    let Some(reply) = soa.into_ares_data(buf) else { return ARES_EBADRESP };
+    let aresdata = Box::into_raw(Box::new(AresData { data_type: AresSoaReply::datatype(), data: reply }));
+    unsafe { *out = &mut (*aresdata).data };
+    ARES_SUCCESS
 }
 
 impl DnsLabel {
     pub fn build_cstring(&self, main_buf: &[u8]) -> Option<CString> {
-        Some(CString::new(self.build_string(main_buf)?).ok()?)
+        CString::new(self.build_string(main_buf)?).ok()
     }
 }
 
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_ns_reply(abuf: *const u8, alen: c_int, out: *mut *mut libc::hostent) -> c_int {
-    let hostent = unsafe { parse_hostent(abuf, alen, HostentParseMode::Aliases).unwrap() };
+    let hostent = match unsafe { parse_hostent(abuf, alen, HostentParseMode::Aliases) } {
+        Ok(hostent) => hostent,
+        Err(status) => return status,
+    };
+    let hostent = Box::into_raw(Box::new(hostent));
+    unsafe { *out = hostent };
+    ARES_SUCCESS
+}
+
+/// `addr` is the address looked up, `addrlen` bytes of `family`, which becomes the hostent's address
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_parse_ptr_reply(abuf: *const u8, alen: c_int, addr: *const c_void, addrlen: c_int, family: c_int, out: *mut *mut libc::hostent) -> c_int {
+    if addr.is_null() || addrlen < 0 {
+        return ARES_EBADFAMILY;
+    }
+    let addr = unsafe { std::slice::from_raw_parts(addr as *const u8, addrlen as usize) };
+    let hostent = match unsafe { parse_ptr_hostent(abuf, alen, addr, family) } {
+        Ok(hostent) => hostent,
+        Err(status) => return status,
+    };
     let hostent = Box::into_raw(Box::new(hostent));
     unsafe { *out = hostent };
     ARES_SUCCESS
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_a_reply(abuf: *const u8, alen: c_int, out: *mut *mut libc::hostent) -> c_int {
-    let hostent = unsafe { parse_hostent(abuf, alen, HostentParseMode::Addrs4).unwrap() };
+    let hostent = match unsafe { parse_hostent(abuf, alen, HostentParseMode::Addrs4) } {
+        Ok(hostent) => hostent,
+        Err(status) => return status,
+    };
     let hostent = Box::into_raw(Box::new(hostent));
     unsafe { *out = hostent };
     ARES_SUCCESS
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_parse_aaaa_reply(abuf: *const u8, alen: c_int, out: *mut *mut libc::hostent) -> c_int {
-    let hostent = unsafe { parse_hostent(abuf, alen, HostentParseMode::Addrs6).unwrap() };
+    let hostent = match unsafe { parse_hostent(abuf, alen, HostentParseMode::Addrs6) } {
+        Ok(hostent) => hostent,
+        Err(status) => return status,
+    };
     let hostent = Box::into_raw(Box::new(hostent));
     unsafe { *out = hostent };
     ARES_SUCCESS
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_free_hostent(hostent: *mut libc::hostent) {
     unsafe { free_hostent(hostent) };
 }
@@ -187,6 +461,10 @@ pub unsafe extern "C" fn ares_free_hostent(hostent: *mut libc::hostent) {
 pub type AresHostCallback = unsafe extern "C" fn(arg: *mut c_void, status: c_int, timeouts: c_int, hostent: *mut libc::hostent);
 pub type AresCallback = unsafe extern "C" fn(arg: *mut c_void, status: c_int, timeouts: c_int, abuf: *mut u8, alen: libc::c_int);
 pub type AresSockCreateCallback = unsafe extern "C" fn(socket_fd: c_int, sock_type: c_int, arg: *mut libc::c_void);
+/// Query `qid` failed with `status` on every configured server, right before its own callback gets `status`
+pub type AresServersDownCallback = unsafe extern "C" fn(arg: *mut c_void, qid: libc::c_ushort, status: c_int);
+/// `server` is formatted like an ares_get_servers_csv entry and only valid during the call
+pub type AresRetryCallback = unsafe extern "C" fn(arg: *mut c_void, qid: libc::c_ushort, attempt: c_int, server: *const c_char);
 
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
@@ -195,15 +473,24 @@ pub unsafe extern "C" fn ares_fds(channel: Channel, read_fds: &mut libc::fd_set,
     unsafe { libc::FD_ZERO(write_fds) };
     unsafe { libc::FD_ZERO(read_fds) };
 
+    let ares = &channeldata.ares;
     let mut nfds = 0;
-    for task in &channeldata.ares.tasks {
+    for task in &ares.tasks {
         let fd = task.sock.as_raw_fd();
         match task.status {
             Status::Writing => unsafe { libc::FD_SET(fd, write_fds) },
-            Status::Reading => unsafe { libc::FD_SET(fd, read_fds) },
-            Status::Completed => continue,
+            // TCP exchanges have a connection of their own, outside the receive group
+            Status::Reading if !task.grouped || task.tcp.is_some() => unsafe { libc::FD_SET(fd, read_fds) },
+            Status::Reading | Status::Completed | Status::Cancelled => continue,
         };
-        if nfds < fd { nfds = fd + 1 }
+        nfds = nfds.max(fd + 1);
+    }
+    if ares.tasks.iter().any(|task| task.status == Status::Reading && task.grouped) {
+        for sock in ares.receive_sockets() {
+            let fd = sock.as_raw_fd();
+            unsafe { libc::FD_SET(fd, read_fds) };
+            nfds = nfds.max(fd + 1);
+        }
     }
     nfds
 }
@@ -212,7 +499,9 @@ pub unsafe extern "C" fn ares_fds(channel: Channel, read_fds: &mut libc::fd_set,
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_timeout(channel: Channel, _maxtv: *mut libc::timeval, tv: *mut libc::timeval) -> *mut libc::timeval {
     let channeldata = unsafe { &mut *channel };
-    let max_wait_time = channeldata.ares.max_wait_time().as_millis();
+    // Nothing to wait for, the caller may block indefinitely
+    let Some(max_wait_time) = channeldata.ares.max_wait_time() else { return std::ptr::null_mut() };
+    let max_wait_time = max_wait_time.as_millis();
     unsafe {
         (*tv).tv_sec = (max_wait_time / 1000) as i64;
         (*tv).tv_usec = 1000 * (max_wait_time % 1000) as i64;
@@ -220,75 +509,197 @@ pub unsafe extern "C" fn ares_timeout(channel: Channel, _maxtv: *mut libc::timev
     tv
 }
 
-fn run_ares_host_callback(buf: Vec<u8>, result: DnsFrame, callback: AresHostCallback, arg: *mut c_void) {
-    let reply_code = result.flags & 0x0f;
-    if reply_code > 0 {
-        let status = match reply_code {
-            3 => ARES_ENOTFOUND,
-            _ => ARES_ESERVFAIL,
-        };
-        return unsafe { callback(arg, status, 0, std::ptr::null_mut()) };
+fn run_ares_host_callback(buf: Vec<u8>, result: DnsFrame, callback: AresHostCallback, arg: *mut c_void, timeouts: c_int) {
+    if let Some(rcode) = Rcode::from_flags(result.flags) {
+        let status = ares_status(rcode.into());
+        return unsafe { callback(arg, status, timeouts, std::ptr::null_mut()) };
     }
 
-    let hostent = unsafe { parse_hostent(buf.as_ptr(), buf.len() as i32, HostentParseMode::Addrs).unwrap() };
+    let hostent = match unsafe { parse_hostent(buf.as_ptr(), buf.len() as i32, HostentParseMode::Addrs) } {
+        Ok(hostent) => hostent,
+        Err(status) => return unsafe { callback(arg, status, timeouts, std::ptr::null_mut()) },
+    };
     let hostent = Box::into_raw(Box::new(hostent));
-    unsafe { callback(arg, ARES_SUCCESS, 0, &mut *hostent) };
+    unsafe { callback(arg, ARES_SUCCESS, timeouts, &mut *hostent) };
     unsafe { ares_free_hostent(hostent) };
 }
 
-fn run_ares_callback(buf: Vec<u8>, _result: DnsFrame, callback: AresCallback, arg: *mut c_void) {
-    unsafe { callback(arg, ARES_SUCCESS, 0, buf.as_ptr() as *mut u8, buf.len() as i32) };
+fn run_ares_callback(buf: Vec<u8>, _result: DnsFrame, callback: AresCallback, arg: *mut c_void, timeouts: c_int) {
+    unsafe { callback(arg, ARES_SUCCESS, timeouts, buf.as_ptr() as *mut u8, buf.len() as i32) };
 }
 
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_process(channel: Channel, read_fds: &mut libc::fd_set, write_fds: &mut libc::fd_set) {
     let channeldata = unsafe { &mut *channel };
-    for task in &mut channeldata.ares.tasks {
-        if task.is_expired() {
-            let ffidata = &task.userdata;
-            (ffidata.callback).run_error(ARES_ETIMEOUT, ffidata.arg);
-            task.status = Status::Completed;
+    let retried = channeldata.ares.retry_expired();
+    if let Some(cb) = channeldata.retry_callback {
+        for (qid, attempt) in retried {
+            let task = channeldata.ares.tasks.iter().find(|task| task.request.transaction_id == qid);
+            let Some(server) = task.and_then(|task| channeldata.ares.nameserver_for(task)) else { continue };
+            let server = CString::new(format_ns_addr(&server)).unwrap();
+            unsafe { cb(channeldata.retry_callback_arg, qid, attempt as c_int, server.as_ptr()) };
         }
     }
+    // Callbacks may enqueue new queries, which go after the current ones
+    let mut tasks = channeldata.take_tasks();
+    for idx in 0..tasks.len() {
+        let task = &mut tasks[idx];
+        // Done tasks already got their callback, each task fires exactly one
+        if !task.is_expired() || task.is_done() {
+            continue;
+        }
+        task.timeouts += 1;
+        task.status = Status::Completed;
+        channeldata.fail(&tasks[idx], ARES_ETIMEOUT);
+        channeldata.run_cancelled(&mut tasks);
+    }
+    channeldata.restore_tasks(tasks);
     channeldata.ares.remove_completed();
 
-    let mut tasks = std::mem::take(&mut channeldata.ares.tasks);
-    for task in &mut tasks {
-        if unsafe { libc::FD_ISSET(task.sock.as_raw_fd(), write_fds) } {
-            channeldata.ares.write_impl(task);
+    let mut tasks = channeldata.take_tasks();
+    for idx in 0..tasks.len() {
+        let task = &mut tasks[idx];
+        // The fd_sets may be stale (e.g. an fd reused after ares_cancel), so only do the I/O the task expects
+        let fd = task.sock.as_raw_fd();
+        // A UDP send can't block for long, so any readiness of a fresh task's fd (e.g. edge-triggered loops
+        // reporting only readability) is enough to send it. A TCP connection has to be writable, i.e. connected
+        let writable = unsafe { libc::FD_ISSET(fd, write_fds) };
+        let ready = writable || (task.tcp.is_none() && unsafe { libc::FD_ISSET(fd, read_fds) });
+        if task.status == Status::Writing && ready {
+            match channeldata.ares.write_impl(task) {
+                Err(err) => {
+                    channeldata.fail(task, ares_status(err));
+                    channeldata.run_cancelled(&mut tasks);
+                    continue;
+                }
+                // Moving on to a server of the other family took a new socket
+                Ok(()) if task.sock.as_raw_fd() != fd => channeldata.socket_created(task.sock.as_raw_fd()),
+                Ok(()) => {}
+            }
         }
-        if unsafe { libc::FD_ISSET(task.sock.as_raw_fd(), read_fds) } {
+        let own_reads = !task.grouped || task.tcp.is_some();
+        if task.status == Status::Reading && own_reads && unsafe { libc::FD_ISSET(fd, read_fds) } {
             if let Some((buf, frame)) = channeldata.ares.read_impl(task) {
-                (task.userdata.callback).run(buf, frame, &task.userdata);
+                channeldata.deliver(&tasks[idx], buf, frame);
+                channeldata.run_cancelled(&mut tasks);
             }
         }
     }
-    channeldata.ares.tasks = tasks;
+    let replies = channeldata.ares.drain_receive_group(&mut tasks, |sock| unsafe { libc::FD_ISSET(sock.as_raw_fd(), read_fds) });
+    for (idx, buf, frame) in replies {
+        // An earlier reply's callback may have cancelled it
+        if tasks[idx].status == Status::Cancelled {
+            continue;
+        }
+        channeldata.deliver(&tasks[idx], buf, frame);
+        channeldata.run_cancelled(&mut tasks);
+    }
+    channeldata.restore_tasks(tasks);
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_pending_queries(channel: Channel) -> c_int {
+    let channeldata = unsafe { &*channel };
+    channeldata.ares.pending_queries().len() as c_int
+}
+
+/// Same as `ares_process`, returning the number of queries still pending afterwards
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_process_pending(channel: Channel, read_fds: &mut libc::fd_set, write_fds: &mut libc::fd_set) -> c_int {
+    unsafe { ares_process(channel, read_fds, write_fds) };
+    unsafe { ares_pending_queries(channel) }
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_cancel(channel: Channel) {
+    let channeldata = unsafe { &mut *channel };
+    // Callbacks may enqueue new queries, which must survive the cancellation
+    let tasks = std::mem::take(&mut channeldata.ares.tasks);
+    // Those ares_process is running callbacks for hear about it once the current callback returns
+    let processing = std::mem::take(&mut channeldata.processing);
+    channeldata.cancelled.extend(processing);
+    for task in tasks.iter().filter(|task| !task.is_done()) {
+        (task.userdata.callback).run_error(ARES_ECANCELLED, task.userdata.arg, task.timeouts);
+    }
+}
+
+/// Cancels the single pending query `qid`, whose callback gets ARES_ECANCELLED right away, or
+/// once the running callback returns when called from inside ares_process.
+/// The task itself goes away on the next ares_process
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_cancel_query(channel: Channel, qid: c_ushort) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    if let Some(task) = channeldata.ares.cancel_query(qid) {
+        (task.userdata.callback).run_error(ARES_ECANCELLED, task.userdata.arg, task.timeouts);
+        return ARES_SUCCESS;
+    }
+    let Some(idx) = channeldata.processing.iter().position(|id| *id == qid) else { return ARES_ENOTFOUND };
+    let qid = channeldata.processing.remove(idx);
+    channeldata.cancelled.push(qid);
+    ARES_SUCCESS
 }
 
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
-pub unsafe extern "C" fn ares_set_servers(channel: Channel, mut head: *mut ares_addr_node) {
+pub unsafe extern "C" fn ares_set_servers(channel: Channel, head: *mut ares_addr_node) {
     let channeldata = unsafe { &mut *channel };
     channeldata.ares.config.nameservers.clear();
-    while !head.is_null() {
-        if unsafe { (*head).family } == libc::AF_INET {
-            let node = unsafe { &(*head) };
-            let oct4: [u8; 4] = node.data[0..4].try_into().unwrap();
-            channeldata.ares.config.nameservers.push((IpAddr::from(oct4), None));
-        }
-        head = unsafe { (*head).next };
+    // Nodes of other families are skipped
+    for ip in unsafe { iter_clinked(head) }.filter_map(ares_addr_node::as_ip) {
+        channeldata.ares.config.nameservers.push((ip, None));
+    }
+    dedup_nameservers(&mut channeldata.ares.config.nameservers);
+}
+
+/// Like ares_get_servers_ports, without the ports. The list is freed with ares_free_data
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_get_servers(channel: Channel, out: *mut *mut ares_addr_node) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let data: Vec<ares_addr_node> = channeldata.ares.config.nameservers.iter().map(|srv| ares_addr_node::from(srv.0)).collect();
+    if data.is_empty() {
+        unsafe { *out = std::ptr::null_mut() };
+        return ARES_SUCCESS;
+    }
+    let data = clinkedlist::chain_nodes(data);
+    let aresdata: AresData<ares_addr_node> = AresData { data_type: ares_addr_node::datatype(), data };
+    let aresdata = Box::into_raw(Box::new(aresdata));
+    unsafe { *out = &mut (*aresdata).data };
+    ARES_SUCCESS
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_servers_ports(channel: Channel, head: *mut AresAddrPortNode) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let mut nameservers = vec![];
+    for node in unsafe { iter_clinked(head) } {
+        let ip = match node.family {
+            libc::AF_INET => IpAddr::from(unsafe { node.addr.addr4.s_addr }.to_ne_bytes()),
+            libc::AF_INET6 => IpAddr::from(unsafe { node.addr.addr6.s6_addr }),
+            _ => return ARES_EBADFAMILY,
+        };
+        let port = (node.udp_port != 0).then_some(node.udp_port as u16);
+        nameservers.push((ip, port));
     }
+    dedup_nameservers(&mut nameservers);
+    channeldata.ares.config.nameservers = nameservers;
+    ARES_SUCCESS
 }
 
-fn ipv4_to_in_addr(ip: IpAddr) -> Option<AresAddrUnion> {
+fn ip_to_addr_union(ip: IpAddr) -> (c_int, AresAddrUnion) {
     match ip {
         IpAddr::V4(v4) => {
             let addr = u32::from_ne_bytes(v4.octets());
-            Some(AresAddrUnion { addr4: libc::in_addr { s_addr: addr } })
+            (libc::AF_INET, AresAddrUnion { addr4: libc::in_addr { s_addr: addr } })
         }
-        IpAddr::V6(_) => None,
+        // Link-local scopes stay embedded in the address, see sysconfig::embed_scope
+        IpAddr::V6(v6) => (libc::AF_INET6, AresAddrUnion { addr6: libc::in6_addr { s6_addr: v6.octets() } }),
     }
 }
 
@@ -298,14 +709,20 @@ pub unsafe extern "C" fn ares_get_servers_ports(channel: Channel, out: *mut *mut
     let channeldata = unsafe { &mut *channel };
     let mut data: Vec<AresAddrPortNode> = vec![];
     for srv in &channeldata.ares.config.nameservers {
+        let (family, addr) = ip_to_addr_union(srv.0);
+        let (udp_port, tcp_port) = channeldata.ares.ns_ports(srv);
         data.push(AresAddrPortNode {
             next: std::ptr::null_mut(),
-            family: libc::AF_INET,
-            addr: ipv4_to_in_addr(srv.0).unwrap(),
-            udp_port: srv.1.unwrap_or(channeldata.ares.default_udp_port) as c_int,
-            tcp_port: srv.1.unwrap_or(channeldata.ares.default_tcp_port) as c_int,
+            family,
+            addr,
+            udp_port: udp_port as c_int,
+            tcp_port: tcp_port as c_int,
         });
     }
+    if data.is_empty() {
+        unsafe { *out = std::ptr::null_mut() };
+        return ARES_SUCCESS;
+    }
     let data = clinkedlist::chain_nodes(data);
     let aresdata: AresData<AresAddrPortNode> = AresData { data_type: AresAddrPortNode::datatype(), data };
     let aresdata = Box::into_raw(Box::new(aresdata));
@@ -313,17 +730,27 @@ pub unsafe extern "C" fn ares_get_servers_ports(channel: Channel, out: *mut *mut
     ARES_SUCCESS
 }
 
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_get_servers_csv(channel: Channel) -> *mut c_char {
+    let channeldata = unsafe { &mut *channel };
+    let csv = servers_csv::to_csv(&channeldata.ares.config.nameservers);
+    CString::new(csv).unwrap().into_raw()
+}
+
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_set_servers_ports_csv(channel: Channel, servers: *const c_char) -> c_int {
     let channeldata = unsafe { &mut *channel };
-    let mut cursor = Cursor::new(CStr::from_ptr(servers).to_str().unwrap());
-    channeldata.ares.config.nameservers = servers_csv::parse_from_reader(&mut cursor).unwrap();
+    let Ok(servers) = CStr::from_ptr(servers).to_str() else { return ARES_EBADSTR };
+    // Parse everything first, so that a bad entry leaves the current servers intact
+    let Ok(nameservers) = servers_csv::parse_from_reader(&mut Cursor::new(servers)) else { return ARES_EBADSTR };
+    channeldata.ares.config.nameservers = nameservers;
     ARES_SUCCESS
 }
 
 #[no_mangle]
-#[allow(clippy::missing_safety_doc)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn ares_version(version: *mut c_int) -> *const c_char {
     let (major, minor, patch) = (1, 17, 1);
     let v = (major << 16) | (minor << 8) | patch;
@@ -335,17 +762,29 @@ pub const ARES_GETSOCK_MAXNUM: usize = 16; // per c-ares headers
 pub const ARES_SOCKET_BAD: ares_socket_t = -1;
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_getsock(channel: Channel, socks: *mut ares_socket_t, numsocks: c_int) -> c_int {
     let channeldata = unsafe { &mut *channel };
     let n = min(ARES_GETSOCK_MAXNUM, numsocks as usize);
 
+    // Like ares_fds, replies to tasks of the receive group are waited for on the group's sockets
+    let ares = &channeldata.ares;
+    let own = ares.tasks.iter().filter(|task| task.status != Status::Reading || !task.grouped || task.tcp.is_some());
+    // A connecting TCP socket is waited on for writability, see ARES_GETSOCK_WRITABLE.
+    // No need to wait ARES_GETSOCK_WRITABLE for UDP sockets
+    let mut wanted: Vec<(c_int, bool)> = own.map(|task| (task.sock.as_raw_fd(), task.tcp.is_some() && task.status == Status::Writing)).collect();
+    if ares.tasks.iter().any(|task| task.status == Status::Reading && task.grouped) {
+        wanted.extend(ares.receive_sockets().map(|sock| (sock.as_raw_fd(), false)));
+    }
     let mut mask: c_int = 0;
     for i in 0..n {
-        let maybe_task = channeldata.ares.tasks.get(i);
-        std::ptr::write(socks.add(i), maybe_task.map(|x| x.sock.as_raw_fd()).unwrap_or(ARES_SOCKET_BAD));
+        let maybe_sock = wanted.get(i);
+        std::ptr::write(socks.add(i), maybe_sock.map(|(fd, _)| *fd).unwrap_or(ARES_SOCKET_BAD));
 
-        if maybe_task.is_some() {
-            mask |= 1 << i; // No need to wait ARES_GETSOCK_WRITABLE for UDP sockets
+        match maybe_sock {
+            Some((_, true)) => mask |= 1 << (i + ARES_GETSOCK_MAXNUM),
+            Some((_, false)) => mask |= 1 << i,
+            None => {}
         }
     }
 
@@ -353,10 +792,56 @@ pub unsafe extern "C" fn ares_getsock(channel: Channel, socks: *mut ares_socket_
 }
 
 #[no_mangle]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_free_string(s: *mut libc::c_void) {
     drop(CString::from_raw(s as *mut c_char));
 }
 
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_udp_receive_sockets(channel: Channel, count: c_int) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    channeldata.ares.udp_receive_sockets = count.max(0) as usize;
+    ARES_SUCCESS
+}
+
+/// Attaches an EDNS Client Subnet option to subsequent queries; AF_UNSPEC clears it
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_client_subnet(channel: Channel, family: c_int, addr: *const c_void, source_prefix: c_int) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let (addr, max_prefix) = match family {
+        libc::AF_UNSPEC => {
+            channeldata.ares.client_subnet = None;
+            return ARES_SUCCESS;
+        }
+        libc::AF_INET => (IpAddr::from(unsafe { *(addr as *const [u8; 4]) }), 32),
+        libc::AF_INET6 => (IpAddr::from(unsafe { *(addr as *const [u8; 16]) }), 128),
+        _ => return ARES_EBADFAMILY,
+    };
+    if !(0..=max_prefix).contains(&source_prefix) {
+        return ARES_EBADFLAGS;
+    }
+    channeldata.ares.client_subnet = Some(ClientSubnet { addr, source_prefix: source_prefix as u8, scope_prefix: 0 });
+    ARES_SUCCESS
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_retry_callback(channel: Channel, callback: Option<AresRetryCallback>, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    channeldata.retry_callback = callback;
+    channeldata.retry_callback_arg = arg;
+}
+
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_servers_down_callback(channel: Channel, callback: Option<AresServersDownCallback>, arg: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    channeldata.servers_down_callback = callback;
+    channeldata.servers_down_callback_arg = arg;
+}
+
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ares_set_socket_callback(channel: Channel, callback: Option<AresSockCreateCallback>, arg: *mut c_void) {
@@ -364,3 +849,1022 @@ pub unsafe extern "C" fn ares_set_socket_callback(channel: Channel, callback: Op
     channeldata.sock_create_callback = callback;
     channeldata.sock_create_callback_arg = arg;
 }
+
+/// Binds later query sockets to local `port` instead of a random one (0 restores that); see Ares::udp_source_port
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_udp_source_port(channel: Channel, port: c_int) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let Ok(port) = u16::try_from(port) else { return ARES_EBADFLAGS };
+    // A group bound to the previous port is rebuilt on the next query
+    channeldata.ares.udp_source_port = port;
+    ARES_SUCCESS
+}
+
+/// Turns the retry without EDNS of queries answered FORMERR off (0) or back on; see Ares::edns_formerr_retry
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_edns_formerr_retry(channel: Channel, enabled: c_int) {
+    let channeldata = unsafe { &mut *channel };
+    channeldata.ares.edns_formerr_retry = enabled != 0;
+}
+
+/// Sets the class of the questions ares_gethostbyname asks; see Ares::default_class
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_default_class(channel: Channel, dnsclass: c_int) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let Ok(dnsclass) = u16::try_from(dnsclass) else { return ARES_EBADFLAGS };
+    channeldata.ares.default_class = dnsclass;
+    ARES_SUCCESS
+}
+
+/// Sets the NAT64 prefix (an in6_addr, NULL disabling it) AF_INET6 lookups map A records into; see Ares::nat64_prefix
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_nat64_prefix(channel: Channel, prefix: *const c_void) {
+    let channeldata = unsafe { &mut *channel };
+    let prefix = unsafe { (prefix as *const [u8; 16]).as_ref() };
+    channeldata.ares.nat64_prefix = prefix.map(|octets| Ipv6Addr::from(*octets));
+}
+
+/// Pins `addr` (an in_addr or in6_addr per `family`) to `name`, answering later ares_gethostbyname calls locally
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_add_static_host(channel: Channel, name: *const c_char, family: c_int, addr: *const c_void) -> c_int {
+    let channeldata = unsafe { &mut *channel };
+    let addr = match family {
+        libc::AF_INET => IpAddr::from(unsafe { *(addr as *const [u8; 4]) }),
+        libc::AF_INET6 => IpAddr::from(unsafe { *(addr as *const [u8; 16]) }),
+        _ => return ARES_EBADFAMILY,
+    };
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    channeldata.ares.add_static_host(&name, vec![addr]);
+    ARES_SUCCESS
+}
+
+/// Routes the socket I/O of subsequent queries through `funcs`, or back to std when it's null
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ares_set_socket_functions(channel: Channel, funcs: *const SocketFunctions, user_data: *mut c_void) {
+    let channeldata = unsafe { &mut *channel };
+    channeldata.ares.socket_hooks = unsafe { funcs.as_ref() }.map(|funcs| SocketHooks { funcs: *funcs, user_data });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::*;
+    use crate::ffi::error::{ ARES_ECONNREFUSED, ARES_ENOMEM };
+    use std::time::Instant;
+
+    fn addr(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    unsafe extern "C" fn record_status(arg: *mut c_void, status: c_int, _timeouts: c_int, _abuf: *mut u8, _alen: c_int) {
+        unsafe { (*(arg as *mut Vec<c_int>)).push(status) };
+    }
+
+    unsafe extern "C" fn record_answer(arg: *mut c_void, status: c_int, _timeouts: c_int, abuf: *mut u8, alen: c_int) {
+        assert_eq!(status, ARES_SUCCESS);
+        let answer = unsafe { std::slice::from_raw_parts(abuf, alen as usize) };
+        unsafe { (*(arg as *mut Vec<Vec<u8>>)).push(answer.to_vec()) };
+    }
+
+    #[test]
+    fn test_set_servers_csv_keeps_servers_on_error() {
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            assert_eq!(ares_set_servers_ports_csv(channel, cstr!("1.1.1.1,8.8.8.8:5353")), ARES_SUCCESS);
+            assert_eq!(ares_set_servers_ports_csv(channel, cstr!("9.9.9.9,bogus,1.0.0.1")), ARES_EBADSTR);
+            assert_eq!((*channel).ares.config.nameservers, vec![(addr("1.1.1.1"), None), (addr("8.8.8.8"), Some(5353))]);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_servers_without_port_read_back_both_defaults() {
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            (*channel).ares.default_udp_port = 5300;
+            (*channel).ares.default_tcp_port = 5301;
+            assert_eq!(ares_set_servers_ports_csv(channel, cstr!("8.8.8.8;1.1.1.1:5353")), ARES_SUCCESS);
+            let mut node: *mut AresAddrPortNode = std::ptr::null_mut();
+            assert_eq!(ares_get_servers_ports(channel, &mut node), ARES_SUCCESS);
+            let ports: Vec<(c_int, c_int)> = iter_clinked(node).map(|node| (node.udp_port, node.tcp_port)).collect();
+            assert_eq!(ports, vec![(5300, 5301), (5353, 5353)]);
+            ares_free_data(node as *mut c_void);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_scoped_ipv6_server_round_trip() {
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            assert_eq!(ares_set_servers_ports_csv(channel, cstr!("[fe80::1%lo]:53")), ARES_SUCCESS);
+
+            let mut node: *mut AresAddrPortNode = std::ptr::null_mut();
+            assert_eq!(ares_get_servers_ports(channel, &mut node), ARES_SUCCESS);
+            assert_eq!((*node).family, libc::AF_INET6);
+            assert_eq!((*node).udp_port, 53);
+            let ip = IpAddr::from((*node).addr.addr6.s6_addr);
+            assert_eq!(crate::core::sysconfig::split_scope(ip), (addr("fe80::1"), 1));
+
+            // Feeding the node back in keeps the scope as well
+            assert_eq!(ares_set_servers_ports(channel, node), ARES_SUCCESS);
+            ares_free_data(node as *mut c_void);
+            let csv = ares_get_servers_csv(channel);
+            assert_eq!(CStr::from_ptr(csv).to_str(), Ok("[fe80::1%lo]:53"));
+            ares_free_string(csv as *mut c_void);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_addr_node_round_trip() {
+        for ip in ["192.0.2.1", "2001:db8::53"] {
+            let node = ares_addr_node::from(addr(ip));
+            assert_eq!(node.as_ip(), Some(addr(ip)));
+        }
+        assert_eq!(ares_addr_node::from(addr("192.0.2.1")).data, *b"\xc0\x00\x02\x01\0\0\0\0\0\0\0\0\0\0\0\0");
+        let node = ares_addr_node { next: std::ptr::null_mut(), family: 0, data: [0; 16] };
+        assert_eq!(node.as_ip(), None);
+    }
+
+    #[test]
+    fn test_get_servers_returns_set_servers() {
+        let mut nodes = [ares_addr_node::from(addr("192.0.2.1")), ares_addr_node::from(addr("2001:db8::53"))];
+        let next: *mut ares_addr_node = &mut nodes[1];
+        nodes[0].next = next;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_set_servers(channel, &mut nodes[0]);
+            let mut node: *mut ares_addr_node = std::ptr::null_mut();
+            assert_eq!(ares_get_servers(channel, &mut node), ARES_SUCCESS);
+            let ips: Vec<Option<IpAddr>> = iter_clinked(node).map(ares_addr_node::as_ip).collect();
+            assert_eq!(ips, vec![Some(addr("192.0.2.1")), Some(addr("2001:db8::53"))]);
+            ares_free_data(node as *mut c_void);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_set_servers_deduplicates() {
+        let mut nodes: Vec<ares_addr_node> = ["1.1.1.1", "8.8.8.8", "1.1.1.1"].iter().map(|ip| ares_addr_node::from(addr(ip))).collect();
+        for i in 1..nodes.len() {
+            let next: *mut ares_addr_node = &mut nodes[i];
+            nodes[i - 1].next = next;
+        }
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_set_servers(channel, &mut nodes[0]);
+            assert_eq!((*channel).ares.config.nameservers, vec![(addr("1.1.1.1"), None), (addr("8.8.8.8"), None)]);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_query_without_nameservers_reports_connrefused() {
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            (*channel).ares.config.nameservers.clear();
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            fds_and_process(channel);
+            assert_eq!(statuses, vec![ARES_ECONNREFUSED]);
+            assert_eq!(ares_pending_queries(channel), 0);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_failed_send_reports_servfail() {
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_query_server(channel, cstr!("[::1]:53"), cstr!("example.test"), 1, 1, record_status, arg);
+            // An IPv4 socket can't reach the IPv6 server
+            (&mut (*channel).ares.tasks)[0].sock = std::net::UdpSocket::bind(("0.0.0.0", 0)).unwrap().into();
+            fds_and_process(channel);
+            assert_eq!(statuses, vec![ARES_ESERVFAIL]);
+            assert_eq!(ares_pending_queries(channel), 0);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_query_ipv6_server_round_trip() {
+        let server = StubServer::spawn_on(addr("::1"), |query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        let server_addr = CString::new(server.addr.to_string()).unwrap();
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_query_server(channel, server_addr.as_ptr(), cstr!("example.test"), 1, 1, record_answer, arg);
+            pump_once(channel);
+            ares_destroy(channel);
+        }
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[test]
+    fn test_getsock_reports_receive_group() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            assert_eq!(ares_set_udp_receive_sockets(channel, 2), ARES_SUCCESS);
+            for _ in 0..3 {
+                ares_query(channel, cstr!("example.test"), 1, 1, record_answer, arg);
+            }
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            fds_and_process(channel);
+
+            // Replies are waited for through ares_getsock alone, like an event loop would
+            for _ in 0..10 {
+                if answers.len() == 3 {
+                    break;
+                }
+                let mut socks = [ARES_SOCKET_BAD; ARES_GETSOCK_MAXNUM];
+                let mask = ares_getsock(channel, socks.as_mut_ptr(), socks.len() as c_int);
+                let mut pollfds: Vec<libc::pollfd> = (0..ARES_GETSOCK_MAXNUM).filter(|i| mask & (1 << i) != 0)
+                    .map(|i| libc::pollfd { fd: socks[i], events: libc::POLLIN, revents: 0 })
+                    .collect();
+                libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, 1000);
+                libc::FD_ZERO(&mut read_fds);
+                libc::FD_ZERO(&mut write_fds);
+                for pollfd in pollfds.iter().filter(|pollfd| pollfd.revents & libc::POLLIN != 0) {
+                    libc::FD_SET(pollfd.fd, &mut read_fds);
+                }
+                ares_process(channel, &mut read_fds, &mut write_fds);
+            }
+            ares_destroy(channel);
+        }
+        assert_eq!(answers.len(), 3);
+    }
+
+    #[test]
+    fn test_receive_group_change_keeps_queries_in_flight() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            assert_eq!(ares_set_udp_receive_sockets(channel, 2), ARES_SUCCESS);
+            ares_query(channel, cstr!("first.test"), 1, 1, record_answer, arg);
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            fds_and_process(channel);
+
+            // The second query rebinds the group, the first one still waiting on the old sockets
+            assert_eq!(ares_set_udp_receive_sockets(channel, 3), ARES_SUCCESS);
+            ares_query(channel, cstr!("second.test"), 1, 1, record_answer, arg);
+            assert_eq!((*channel).ares.retired_receive_sockets.len(), 2);
+            for _ in 0..10 {
+                if answers.len() == 2 {
+                    break;
+                }
+                let nfds = ares_fds(channel, &mut read_fds, &mut write_fds);
+                libc::select(nfds, &mut read_fds, &mut write_fds, std::ptr::null_mut(), &mut libc::timeval { tv_sec: 1, tv_usec: 0 });
+                ares_process(channel, &mut read_fds, &mut write_fds);
+            }
+            // Done tasks go, and the retired sockets with them
+            fds_and_process(channel);
+            assert!((*channel).ares.retired_receive_sockets.is_empty());
+            ares_destroy(channel);
+        }
+        assert_eq!(answers.len(), 2);
+    }
+
+    #[test]
+    fn test_timeout_and_reply_fire_one_callback() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            (*channel).ares.config.options.attempts = 1;
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            fds_and_process(channel);
+
+            let task = &mut (&mut (*channel).ares.tasks)[0];
+            assert!(wait_readable(&task.sock));
+            task.expires_at = Instant::now();
+            fds_and_process(channel);
+            assert_eq!(statuses, vec![ARES_ETIMEOUT]);
+
+            fds_and_process(channel);
+            assert_eq!(statuses, vec![ARES_ETIMEOUT]);
+            assert!((*channel).ares.tasks.is_empty());
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_process_stale_fd_set_after_cancel() {
+        let server = StubServer::spawn(|_| None);
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            assert!(ares_fds(channel, &mut read_fds, &mut write_fds) > 0);
+            let fd = (&(*channel).ares.tasks)[0].sock.as_raw_fd();
+            libc::FD_SET(fd, &mut read_fds);
+
+            ares_cancel(channel);
+            assert_eq!(statuses, vec![ARES_ECANCELLED]);
+            assert!((*channel).ares.tasks.is_empty());
+
+            // A fresh query is likely to reuse the cancelled fd
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            ares_process(channel, &mut read_fds, &mut write_fds);
+            assert_eq!(statuses, vec![ARES_ECANCELLED]);
+            assert!((*channel).ares.tasks.iter().all(|task| task.status != Status::Completed));
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_cancel_query_fires_once_then_goes_away() {
+        let server = StubServer::spawn(|_| None);
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            ares_query(channel, cstr!("a.example.test"), 1, 1, record_status, arg);
+            ares_query(channel, cstr!("b.example.test"), 1, 1, record_status, arg);
+            let qids: Vec<u16> = (*channel).ares.tasks.iter().map(|task| task.request.transaction_id).collect();
+            for qid in &qids {
+                assert_eq!(ares_cancel_query(channel, *qid), ARES_SUCCESS);
+            }
+            assert_eq!(ares_cancel_query(channel, qids[0]), ARES_ENOTFOUND);
+            assert_eq!(statuses, vec![ARES_ECANCELLED, ARES_ECANCELLED]);
+            assert_eq!(ares_pending_queries(channel), 0);
+
+            // Expired, yet no timeout is reported on top of the cancellation
+            for task in &mut (*channel).ares.tasks {
+                task.expires_at = Instant::now();
+            }
+            fds_and_process(channel);
+            assert!((*channel).ares.tasks.is_empty());
+            assert_eq!(statuses, vec![ARES_ECANCELLED, ARES_ECANCELLED]);
+            ares_destroy(channel);
+        }
+    }
+
+    struct Canceller {
+        channel: Channel,
+        /// Query to cancel through ares_cancel_query, everything through ares_cancel if none
+        qid: Option<c_ushort>,
+        statuses: Vec<c_int>,
+        cancel_status: c_int,
+    }
+
+    unsafe extern "C" fn cancel_on_reply(arg: *mut c_void, status: c_int, _timeouts: c_int, _abuf: *mut u8, _alen: c_int) {
+        let canceller = unsafe { &mut *(arg as *mut Canceller) };
+        canceller.statuses.push(status);
+        if status == ARES_SUCCESS {
+            match canceller.qid {
+                Some(qid) => canceller.cancel_status = unsafe { ares_cancel_query(canceller.channel, qid) },
+                None => unsafe { ares_cancel(canceller.channel) },
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancel_from_reply_callback_reaches_other_queries() {
+        let server = StubServer::spawn(|query| {
+            let question = DnsQuery::parse(&mut Cursor::new(&query[12..]))?.name.join(".");
+            (question == "a.example.test").then(|| reply(query, 0x8180, &[A_ANSWER]))
+        });
+        for by_qid in [false, true] {
+            unsafe {
+                let channel = channel_for(&server);
+                let mut canceller = Canceller { channel, qid: None, statuses: vec![], cancel_status: ARES_SUCCESS };
+                let arg = &mut canceller as *mut Canceller as *mut c_void;
+                ares_query(channel, cstr!("a.example.test"), 1, 1, cancel_on_reply, arg);
+                ares_query(channel, cstr!("b.example.test"), 1, 1, cancel_on_reply, arg);
+                if by_qid {
+                    canceller.qid = Some((&(*channel).ares.tasks)[1].request.transaction_id);
+                }
+                fds_and_process(channel);
+                assert!(wait_readable(&(&(*channel).ares.tasks)[0].sock));
+                fds_and_process(channel);
+                assert_eq!(canceller.statuses, vec![ARES_SUCCESS, ARES_ECANCELLED]);
+                assert_eq!(canceller.cancel_status, ARES_SUCCESS);
+                assert_eq!(ares_pending_queries(channel), 0);
+                ares_destroy(channel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_transmits_buffer_unmodified() {
+        let mut request = DnsFrame {
+            transaction_id: 0x4242,
+            flags: DnsHeader::query_flags(true),
+            queries: vec![DnsQuery { name: vec!["example".to_string(), "test".to_string()], qtype: 1, qclass: 1 }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        request.set_edns_udp_size(Some(4000));
+        let mut qbuf = vec![];
+        request.write(&mut qbuf);
+        let received = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let log = received.clone();
+        let server = StubServer::spawn(move |query| {
+            log.lock().unwrap().push(query.to_vec());
+            Some(reply(query, 0x8180, &[A_ANSWER]))
+        });
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        let mut statuses: Vec<c_int> = vec![];
+        unsafe {
+            let channel = channel_for(&server);
+            ares_send(channel, b"\x42\x42\x01".as_ptr(), 3, record_status, &mut statuses as *mut Vec<c_int> as *mut c_void);
+            assert_eq!(statuses, vec![ARES_EBADQUERY]);
+            ares_send(channel, qbuf.as_ptr(), qbuf.len() as c_int, record_answer, arg);
+            pump_once(channel);
+            ares_destroy(channel);
+        }
+        assert_eq!(*received.lock().unwrap(), vec![qbuf]);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(&answers[0][0..2], b"\x42\x42");
+    }
+
+    #[test]
+    fn test_query_truncated_reply_retries_over_tcp() {
+        let txt = [&b"\xc0\x0c\x00\x10\x00\x01\x00\x00\x01\x2c\x00\xc9\xc8"[..], &[b'x'; 200]].concat();
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8380, &[]))) // TC
+            .with_tcp(move |query| Some(reply(query, 0x8180, &[&txt, &txt, &txt])));
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            ares_query(channel, cstr!("example.test"), 1, 0x10, record_answer, arg);
+            pump_once(channel);
+            assert!((&(*channel).ares.tasks)[0].sock.is_tcp());
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            let mut socks = [ARES_SOCKET_BAD; ARES_GETSOCK_MAXNUM];
+            assert_eq!(ares_getsock(channel, socks.as_mut_ptr(), socks.len() as c_int), 1 << ARES_GETSOCK_MAXNUM);
+
+            // Connecting, sending and receiving each take a round
+            for _ in 0..10 {
+                if !answers.is_empty() {
+                    break;
+                }
+                let nfds = ares_fds(channel, &mut read_fds, &mut write_fds);
+                let mut tv = libc::timeval { tv_sec: 1, tv_usec: 0 };
+                libc::select(nfds, &mut read_fds, &mut write_fds, std::ptr::null_mut(), &mut tv);
+                ares_process(channel, &mut read_fds, &mut write_fds);
+            }
+            assert_eq!(answers.len(), 1);
+            let frame = DnsFrame::parse(&mut std::io::Cursor::new(&answers[0])).unwrap();
+            assert!(!frame.is_truncated());
+            assert_eq!(frame.answers.len(), 3);
+            assert!(answers[0].len() > 512);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_query_fails_gracefully_under_fd_pressure() {
+        // RLIMIT_NOFILE is process-wide, so the constrained part runs in a child test process
+        if std::env::var_os("CARES_RS_FD_PRESSURE").is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "ffi::tests::test_query_fails_gracefully_under_fd_pressure", "--test-threads=1"])
+                .env("CARES_RS_FD_PRESSURE", "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            let mut limit: libc::rlimit = std::mem::zeroed();
+            assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit), 0);
+            let next_fd = libc::dup(0);
+            libc::close(next_fd);
+            let lowered = libc::rlimit { rlim_cur: next_fd as libc::rlim_t, ..limit };
+            assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &lowered), 0);
+
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            libc::setrlimit(libc::RLIMIT_NOFILE, &limit);
+            assert_eq!(statuses, vec![ARES_ENOMEM]);
+            assert!((*channel).ares.tasks.is_empty());
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_pending_queries() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            for name in [cstr!("a.example.test"), cstr!("b.example.test"), cstr!("c.example.test")] {
+                ares_query(channel, name, 1, 1, record_status, arg);
+            }
+            assert_eq!(ares_pending_queries(channel), 3);
+            let pending = (*channel).ares.pending_queries();
+            assert_eq!(pending[1].name, "b.example.test");
+            assert_eq!(pending[1].server, Some((server.addr.ip(), Some(server.addr.port()))));
+
+            pump_once(channel);
+            assert_eq!(statuses, vec![ARES_SUCCESS; 3]);
+            assert_eq!(ares_pending_queries(channel), 0);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_timeout_null_without_queries() {
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            (*channel).ares.config.nameservers = vec![("127.0.0.1".parse().unwrap(), Some(9))];
+            let mut tv = libc::timeval { tv_sec: 42, tv_usec: 0 };
+            assert!(ares_timeout(channel, std::ptr::null_mut(), &mut tv).is_null());
+            assert_eq!(tv.tv_sec, 42);
+
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            assert_eq!(ares_timeout(channel, std::ptr::null_mut(), &mut tv), &mut tv as *mut libc::timeval);
+            assert!(tv.tv_sec <= (*channel).ares.config.options.timeout_secs as i64);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_process_pending_counts_down() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut statuses: Vec<c_int> = vec![];
+        let arg = &mut statuses as *mut Vec<c_int> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            for _ in 0..3 {
+                ares_query(channel, cstr!("example.test"), 1, 1, record_status, arg);
+            }
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            ares_fds(channel, &mut read_fds, &mut write_fds);
+            assert_eq!(ares_process_pending(channel, &mut read_fds, &mut write_fds), 3);
+            for task in &(*channel).ares.tasks {
+                assert!(wait_readable(&task.sock));
+            }
+
+            // Only the first reply is signaled
+            libc::FD_ZERO(&mut read_fds);
+            libc::FD_ZERO(&mut write_fds);
+            libc::FD_SET((&(*channel).ares.tasks)[0].sock.as_raw_fd(), &mut read_fds);
+            assert_eq!(ares_process_pending(channel, &mut read_fds, &mut write_fds), 2);
+
+            ares_fds(channel, &mut read_fds, &mut write_fds);
+            assert_eq!(ares_process_pending(channel, &mut read_fds, &mut write_fds), 0);
+            assert_eq!(statuses, vec![ARES_SUCCESS; 3]);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_client_subnet_sent_with_query() {
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            let addr = [0x20u8, 0x01, 0x0d, 0xb8, 0xab, 0xcd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+            assert_eq!(ares_set_client_subnet(channel, libc::AF_INET6, addr.as_ptr() as *const c_void, 129), ARES_EBADFLAGS);
+            assert_eq!(ares_set_client_subnet(channel, libc::AF_INET6, addr.as_ptr() as *const c_void, 36), ARES_SUCCESS);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, std::ptr::null_mut());
+            let writebuf = &(&(*channel).ares.tasks)[0].writebuf;
+            assert!(writebuf.ends_with(b"\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x0d\x00\x08\x00\x09\x00\x02\x24\x00\x20\x01\x0d\xb8\xa0"));
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_init_options_edns_sends_opt_record() {
+        use crate::ffi::ares_options::*;
+        let server = StubServer::spawn(|_| None);
+        let options = ares_options { flags: ARES_FLAG_EDNS, ednspsz: 1400, ..ares_options::default() };
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            assert_eq!(ares_init_options(channel, &options, ARES_OPT_FLAGS | ARES_OPT_EDNSPSZ), ARES_SUCCESS);
+            (*channel).ares.config.nameservers = server.sysconfig().nameservers;
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, std::ptr::null_mut());
+            let task = &(&(*channel).ares.tasks)[0];
+            assert_eq!(&task.writebuf[10..12], b"\x00\x01");
+            assert!(task.writebuf.ends_with(b"\x00\x00\x29\x05\x78\x00\x00\x00\x00\x00\x00"));
+            assert_eq!(task.receive_buffer_size(), 1400);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_init_options_rejects_negative_ndomains() {
+        use crate::ffi::ares_options::*;
+        let mut domains = [cstr!("corp.test")];
+        let options = ares_options { domains: domains.as_mut_ptr() as *mut *mut c_char, ndomains: -1, ..ares_options::default() };
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            assert_eq!(ares_init_options(channel, &options, ARES_OPT_DOMAINS), ARES_EBADFLAGS);
+            ares_destroy(channel);
+        }
+    }
+
+    unsafe extern "C" fn record_host_timeouts(arg: *mut c_void, status: c_int, timeouts: c_int, _hostent: *mut libc::hostent) {
+        unsafe { (*(arg as *mut Vec<(c_int, c_int)>)).push((status, timeouts)) };
+    }
+
+    #[test]
+    fn test_gethostbyname_reports_timeouts() {
+        let mut dropped = false;
+        let server = StubServer::spawn(move |query| {
+            // The first try is lost
+            if !std::mem::replace(&mut dropped, true) {
+                return None;
+            }
+            Some(reply(query, 0x8180, &[A_ANSWER]))
+        });
+        let mut results: Vec<(c_int, c_int)> = vec![];
+        let arg = &mut results as *mut Vec<(c_int, c_int)> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            ares_gethostbyname(channel, cstr!("example.test"), libc::AF_INET, record_host_timeouts, arg);
+            fds_and_process(channel);
+
+            (&mut (*channel).ares.tasks)[0].expires_at = Instant::now();
+            fds_and_process(channel);
+            assert!(results.is_empty());
+            // The retry goes out once the socket is reported writable again
+            pump_once(channel);
+            assert_eq!(results, vec![(ARES_SUCCESS, 1)]);
+            ares_destroy(channel);
+        }
+    }
+
+    unsafe extern "C" fn record_retry(arg: *mut c_void, qid: libc::c_ushort, attempt: c_int, server: *const c_char) {
+        let server = unsafe { CStr::from_ptr(server) }.to_string_lossy().into_owned();
+        unsafe { (*(arg as *mut Vec<(u16, c_int, String)>)).push((qid, attempt, server)) };
+    }
+
+    #[test]
+    fn test_retry_callback_fires_per_retransmission() {
+        let server = StubServer::spawn(|_| None);
+        let mut retries: Vec<(u16, c_int, String)> = vec![];
+        let mut statuses: Vec<c_int> = vec![];
+        unsafe {
+            let channel = channel_for(&server);
+            (*channel).ares.config.options.attempts = 3;
+            ares_set_retry_callback(channel, Some(record_retry), &mut retries as *mut _ as *mut c_void);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, &mut statuses as *mut _ as *mut c_void);
+            let qid = (&(*channel).ares.tasks)[0].request.transaction_id;
+            for _ in 0..3 {
+                fds_and_process(channel);
+                if let Some(task) = (*channel).ares.tasks.first_mut() {
+                    task.expires_at = Instant::now();
+                }
+            }
+            fds_and_process(channel);
+            let server = format!("127.0.0.1:{}", server.addr.port());
+            assert_eq!(retries, vec![(qid, 2, server.clone()), (qid, 3, server)]);
+            assert_eq!(statuses, vec![ARES_ETIMEOUT]);
+            ares_destroy(channel);
+        }
+    }
+
+    unsafe extern "C" fn record_servers_down(arg: *mut c_void, qid: libc::c_ushort, status: c_int) {
+        unsafe { (*(arg as *mut Vec<(u16, c_int)>)).push((qid, status)) };
+    }
+
+    #[test]
+    fn test_all_servers_timing_out_report_timeout() {
+        let (first, second) = (StubServer::spawn(|_| None), StubServer::spawn(|_| None));
+        let mut retries: Vec<(u16, c_int, String)> = vec![];
+        let mut down: Vec<(u16, c_int)> = vec![];
+        let mut statuses: Vec<c_int> = vec![];
+        unsafe {
+            let channel = channel_for(&first);
+            (*channel).ares.config.nameservers.extend(second.sysconfig().nameservers);
+            (*channel).ares.config.options.attempts = 2;
+            ares_set_retry_callback(channel, Some(record_retry), &mut retries as *mut _ as *mut c_void);
+            ares_set_servers_down_callback(channel, Some(record_servers_down), &mut down as *mut _ as *mut c_void);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, &mut statuses as *mut _ as *mut c_void);
+            let qid = (&(*channel).ares.tasks)[0].request.transaction_id;
+            for _ in 0..2 {
+                fds_and_process(channel);
+                if let Some(task) = (*channel).ares.tasks.first_mut() {
+                    task.expires_at = Instant::now();
+                }
+            }
+            fds_and_process(channel);
+            assert_eq!(retries, vec![(qid, 2, format!("127.0.0.1:{}", second.addr.port()))]);
+            assert_eq!(down, vec![(qid, ARES_ETIMEOUT)]);
+            assert_eq!(statuses, vec![ARES_ETIMEOUT]);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_server_failures_report_the_worst_rcode() {
+        let refused = StubServer::spawn(|query| Some(reply(query, 0x8185, &[])));
+        let servfail = StubServer::spawn(|query| Some(reply(query, 0x8182, &[])));
+        let mut down: Vec<(u16, c_int)> = vec![];
+        let mut statuses: Vec<c_int> = vec![];
+        unsafe {
+            let channel = channel_for(&servfail);
+            (*channel).ares.config.nameservers.extend(refused.sysconfig().nameservers);
+            (*channel).ares.config.options.attempts = 2;
+            ares_set_servers_down_callback(channel, Some(record_servers_down), &mut down as *mut _ as *mut c_void);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, &mut statuses as *mut _ as *mut c_void);
+            for _ in 0..2 {
+                pump_once(channel);
+            }
+            assert_eq!(statuses, vec![ARES_ESERVFAIL]);
+            assert_eq!(down.len(), 1);
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_parse_soa_reply() {
+        // dig example.com SOA, trimmed to the answer
+        let buf: Vec<u8> = [
+            &b"\x9f\x1c\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x06\x00\x01"[..],
+            b"\xc0\x0c\x00\x06\x00\x01\x00\x00\x0e\x10\x00\x2c",
+            b"\x02ns\x05icann\x03org\x00\x03noc\x03dns\xc0\x2c",
+            b"\x78\x9b\x4f\x5c\x00\x00\x1c\x20\x00\x00\x0e\x10\x00\x12\x75\x00\x00\x00\x0e\x10",
+        ].concat();
+        let mut out: *mut AresSoaReply = std::ptr::null_mut();
+        let before = live_allocations();
+        unsafe {
+            assert_eq!(ares_parse_soa_reply(buf.as_ptr(), buf.len() as c_int, &mut out), ARES_SUCCESS);
+            assert_eq!(CStr::from_ptr((*out).nsname).to_str(), Ok("ns.icann.org"));
+            assert_eq!(CStr::from_ptr((*out).hostmaster).to_str(), Ok("noc.dns.icann.org"));
+            assert_eq!(((*out).serial, (*out).refresh, (*out).retry), (2023444316, 7200, 3600));
+            assert_eq!(((*out).expire, (*out).minttl), (1209600, 3600));
+            ares_free_data(out as *mut c_void);
+        }
+        assert_eq!(live_allocations(), before);
+
+        let mut buf = buf[..29].to_vec(); // question only
+        buf[7] = 0;
+        assert_eq!(unsafe { ares_parse_soa_reply(buf.as_ptr(), buf.len() as c_int, &mut out) }, ARES_ENODATA);
+    }
+
+    #[test]
+    fn test_parse_srv_reply() {
+        // Targets are compressed against the question name
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x07example\x03com\x00\x00\x21\x00\x01"[..],
+            b"\xc0\x0c\x00\x21\x00\x01\x00\x00\x01\x2c\x00\x0c\x00\x0a\x00\x05\x13\xc4\x03sip\xc0\x0c",
+            b"\xc0\x0c\x00\x21\x00\x01\x00\x00\x01\x2c\x00\x0d\x00\x14\x00\x00\x13\xc5\x04sip2\xc0\x0c",
+        ].concat();
+        let mut out: *mut AresSrvReply = std::ptr::null_mut();
+        let before = live_allocations();
+        unsafe {
+            assert_eq!(ares_parse_srv_reply(buf.as_ptr(), buf.len() as c_int, &mut out), ARES_SUCCESS);
+            let srvs: Vec<_> = iter_clinked(out)
+                .map(|srv| (CStr::from_ptr(srv.host).to_str().unwrap(), srv.priority, srv.weight, srv.port))
+                .collect();
+            assert_eq!(srvs, vec![("sip.example.com", 10, 5, 5060), ("sip2.example.com", 20, 0, 5061)]);
+            ares_free_data(out as *mut c_void);
+        }
+        assert_eq!(live_allocations(), before);
+    }
+
+    #[test]
+    fn test_parse_naptr_reply() {
+        // ENUM entry for +1-555-0100, replacement compressed against the question name
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x23\x00\x01"[..],
+            b"\xc0\x0c\x00\x23\x00\x01\x00\x00\x01\x2c\x00\x36\x00\x64\x00\x0a\x01u\x07E2U+sip",
+            b"\x1b!^.*$!sip:info@example.com!\x04_sip\x04_udp\xc0\x0c",
+        ].concat();
+        let mut out: *mut AresNaptrReply = std::ptr::null_mut();
+        let before = live_allocations();
+        unsafe {
+            assert_eq!(ares_parse_naptr_reply(buf.as_ptr(), buf.len() as c_int, &mut out), ARES_SUCCESS);
+            let naptrs: Vec<&AresNaptrReply> = iter_clinked(out).collect();
+            assert_eq!(naptrs.len(), 1);
+            let naptr = naptrs[0];
+            assert_eq!((naptr.order, naptr.preference), (100, 10));
+            assert_eq!(CStr::from_ptr(naptr.flags as *const c_char).to_str(), Ok("u"));
+            assert_eq!(CStr::from_ptr(naptr.service as *const c_char).to_str(), Ok("E2U+sip"));
+            assert_eq!(CStr::from_ptr(naptr.regexp as *const c_char).to_str(), Ok("!^.*$!sip:info@example.com!"));
+            assert_eq!(CStr::from_ptr(naptr.replacement).to_str(), Ok("_sip._udp.example.com"));
+            ares_free_data(out as *mut c_void);
+        }
+        assert_eq!(live_allocations(), before);
+    }
+
+    #[test]
+    fn test_parse_txt_reply_segments() {
+        // A DKIM-style key split into a 255-byte chunk plus a 10-byte one, with a NUL inside
+        let rdata = [&[255u8][..], &[b'k'; 255], b"\x0a01234\x006789"].concat();
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\x07example\x03com\x00\x00\x10\x00\x01"[..],
+            b"\xc0\x0c\x00\x10\x00\x01\x00\x00\x01\x2c",
+            &(rdata.len() as u16).to_be_bytes(),
+            &rdata,
+        ].concat();
+        let mut out: *mut AresTxtReply = std::ptr::null_mut();
+        let before = live_allocations();
+        unsafe {
+            assert_eq!(ares_parse_txt_reply(buf.as_ptr(), buf.len() as c_int, &mut out), ARES_SUCCESS);
+            let segments: Vec<&[u8]> = iter_clinked(out)
+                .map(|txt| std::slice::from_raw_parts(txt.txt as *const u8, txt.length))
+                .collect();
+            assert_eq!(segments, vec![&[b'k'; 255][..], b"01234\x006789"]);
+            ares_free_data(out as *mut c_void);
+        }
+        assert_eq!(live_allocations(), before);
+    }
+
+    #[test]
+    fn test_parse_https_reply() {
+        // Listed out of priority order, the second one having no parameters
+        let buf: Vec<u8> = [
+            &b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\x07example\x03com\x00\x00\x41\x00\x01"[..],
+            b"\xc0\x0c\x00\x41\x00\x01\x00\x00\x01\x2c\x00\x0d\x00\x02\x00\x00\x01\x00\x06\x02h2\x02h3",
+            b"\xc0\x0c\x00\x41\x00\x01\x00\x00\x01\x2c\x00\x03\x00\x01\x00",
+        ].concat();
+        let mut out: *mut AresSvcbReply = std::ptr::null_mut();
+        let before = live_allocations();
+        unsafe {
+            assert_eq!(ares_parse_https_reply(buf.as_ptr(), buf.len() as c_int, &mut out), ARES_SUCCESS);
+            let svcbs: Vec<&AresSvcbReply> = iter_clinked(out).collect();
+            assert_eq!(svcbs.iter().map(|svcb| svcb.priority).collect::<Vec<_>>(), vec![1, 2]);
+            assert_eq!(CStr::from_ptr(svcbs[0].target).to_str(), Ok(""));
+            assert!(svcbs[0].params.is_null());
+            let params: Vec<(u16, &[u8])> = iter_clinked(svcbs[1].params)
+                .map(|param| (param.key, std::slice::from_raw_parts(param.value, param.length)))
+                .collect();
+            assert_eq!(params, vec![(1, &b"\x02h2\x02h3"[..])]);
+            ares_free_data(out as *mut c_void);
+        }
+        assert_eq!(live_allocations(), before);
+        assert_eq!(unsafe { ares_parse_svcb_reply(buf.as_ptr(), buf.len() as c_int, &mut out) }, ARES_ENODATA);
+    }
+
+    #[test]
+    fn test_parse_mx_failure_frees_earlier_nodes() {
+        let buf = dangling_second_name_reply(15, b"\x00\x0a");
+        let mut out: *mut AresMxReply = std::ptr::null_mut();
+        let before = live_allocations();
+        let status = unsafe { ares_parse_mx_reply(buf.as_ptr(), buf.len() as c_int, &mut out) };
+        assert_eq!(status, ARES_EBADRESP);
+        assert!(out.is_null());
+        assert_eq!(live_allocations(), before);
+    }
+
+    // An in-memory socket pair stands in for the connected fd a sandboxed app hands over
+    unsafe extern "C" fn pair_socket(_domain: c_int, _type: c_int, _protocol: c_int, user_data: *mut c_void) -> c_int {
+        unsafe { *(user_data as *const c_int) }
+    }
+
+    static PAIR_CLOSED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    unsafe extern "C" fn pair_close(fd: c_int, _user_data: *mut c_void) -> c_int {
+        PAIR_CLOSED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        unsafe { libc::close(fd) }
+    }
+
+    unsafe extern "C" fn pair_connect(_fd: c_int, _addr: *const libc::sockaddr, _len: libc::socklen_t, _user_data: *mut c_void) -> c_int {
+        0
+    }
+
+    unsafe extern "C" fn pair_recvfrom(fd: c_int, buf: *mut c_void, len: libc::size_t, flags: c_int, from: *mut libc::sockaddr, fromlen: *mut libc::socklen_t, _user_data: *mut c_void) -> libc::ssize_t {
+        unsafe { libc::recvfrom(fd, buf, len, flags, from, fromlen) }
+    }
+
+    unsafe extern "C" fn pair_sendv(fd: c_int, iov: *const libc::iovec, iovcnt: c_int, _user_data: *mut c_void) -> libc::ssize_t {
+        unsafe { libc::writev(fd, iov, iovcnt) }
+    }
+
+    #[test]
+    fn test_socket_functions_route_query_io() {
+        let mut pair = [0 as c_int; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_DGRAM, 0, pair.as_mut_ptr()) }, 0);
+        let funcs = SocketFunctions {
+            asocket: Some(pair_socket),
+            aclose: Some(pair_close),
+            aconnect: Some(pair_connect),
+            arecvfrom: Some(pair_recvfrom),
+            asendv: Some(pair_sendv),
+        };
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_set_socket_functions(channel, &funcs, &mut pair[0] as *mut c_int as *mut c_void);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_answer, arg);
+            assert_eq!((&(*channel).ares.tasks)[0].sock.as_raw_fd(), pair[0]);
+            fds_and_process(channel);
+
+            let mut query = [0u8; 512];
+            let len = libc::read(pair[1], query.as_mut_ptr() as *mut c_void, query.len());
+            assert!(len > 12);
+            let response = reply(&query[0..len as usize], 0x8180, &[A_ANSWER]);
+            libc::write(pair[1], response.as_ptr() as *const c_void, response.len());
+            fds_and_process(channel);
+            assert_eq!(answers, vec![response]);
+
+            // The channel's end went back through aclose along with its task
+            fds_and_process(channel);
+            assert_eq!(PAIR_CLOSED.load(std::sync::atomic::Ordering::Relaxed), 1);
+            ares_destroy(channel);
+            libc::close(pair[1]);
+        }
+    }
+
+    unsafe extern "C" fn record_host_addrs(arg: *mut c_void, status: c_int, _timeouts: c_int, hostent: *mut libc::hostent) {
+        assert_eq!(status, ARES_SUCCESS);
+        let hostent = unsafe { &*hostent };
+        let addrs = unsafe { std::slice::from_raw_parts(*hostent.h_addr_list as *const u8, hostent.h_length as usize) };
+        unsafe { (*(arg as *mut Vec<Vec<u8>>)).push(addrs.to_vec()) };
+    }
+
+    #[test]
+    fn test_static_host_answers_without_network() {
+        let mut addrs: Vec<Vec<u8>> = vec![];
+        let arg = &mut addrs as *mut Vec<Vec<u8>> as *mut c_void;
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            (*channel).ares.config.nameservers.clear();
+            let addr = [10u8, 0, 0, 1];
+            assert_eq!(ares_add_static_host(channel, cstr!("example.test"), libc::AF_INET, addr.as_ptr() as *const c_void), ARES_SUCCESS);
+            ares_gethostbyname(channel, cstr!("example.test"), libc::AF_INET, record_host_addrs, arg);
+            assert_eq!(addrs, vec![vec![10, 0, 0, 1]]);
+            assert!((*channel).ares.tasks.is_empty());
+            ares_destroy(channel);
+        }
+    }
+
+    #[test]
+    fn test_writing_task_sent_on_read_readiness() {
+        let server = StubServer::spawn(|query| Some(reply(query, 0x8180, &[A_ANSWER])));
+        let mut answers: Vec<Vec<u8>> = vec![];
+        let arg = &mut answers as *mut Vec<Vec<u8>> as *mut c_void;
+        unsafe {
+            let channel = channel_for(&server);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_answer, arg);
+            let mut read_fds: libc::fd_set = std::mem::zeroed();
+            let mut write_fds: libc::fd_set = std::mem::zeroed();
+            libc::FD_SET((&(*channel).ares.tasks)[0].sock.as_raw_fd(), &mut read_fds);
+            ares_process(channel, &mut read_fds, &mut write_fds);
+            assert!((&(*channel).ares.tasks)[0].status == Status::Reading);
+
+            assert!(wait_readable(&(&(*channel).ares.tasks)[0].sock));
+            fds_and_process(channel);
+            assert_eq!(answers.len(), 1);
+            ares_destroy(channel);
+        }
+    }
+
+    unsafe extern "C" fn record_socket(fd: c_int, sock_type: c_int, arg: *mut c_void) {
+        unsafe { (*(arg as *mut Vec<(c_int, c_int)>)).push((fd, sock_type)) };
+    }
+
+    #[test]
+    fn test_query_fires_socket_callback() {
+        let mut sockets: Vec<(c_int, c_int)> = vec![];
+        let mut channel: Channel = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(ares_init(&mut channel), ARES_SUCCESS);
+            ares_set_socket_callback(channel, Some(record_socket), &mut sockets as *mut Vec<(c_int, c_int)> as *mut c_void);
+            ares_query(channel, cstr!("example.test"), 1, 1, record_status, std::ptr::null_mut());
+            let fd = (&(*channel).ares.tasks)[0].sock.as_raw_fd();
+            assert_eq!(sockets, vec![(fd, libc::SOCK_DGRAM)]);
+            ares_destroy(channel);
+        }
+    }
+}